@@ -0,0 +1,28 @@
+use bevy::ecs::world::World;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use isometric_sort::cells::saved::CompareTransforms;
+
+// NOTE:
+// - times spawning items that only hold a CompareTransforms, as every sortable item gets one
+fn spawn(c: &mut Criterion) {
+    let mut group = c.benchmark_group("spawn_compare_transforms");
+    for n_items in [100, 10_000] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(n_items),
+            &n_items,
+            |b, n_items| {
+                b.iter(|| {
+                    let mut world = World::default();
+                    for _ in 0..*n_items {
+                        world.spawn(black_box(CompareTransforms::default()));
+                    }
+                    world
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, spawn);
+criterion_main!(benches);
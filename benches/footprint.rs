@@ -0,0 +1,82 @@
+use bevy::math::UVec3;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use isometric_sort::cells::{
+    cell::{Cell, Direction},
+    current::CurrentCells,
+    footprint::Footprint,
+    map::MapSize,
+};
+
+const MAP_SIZE: MapSize = MapSize(bevy::math::UVec2::new(32, 64));
+
+// NOTE:
+// - builds the lookup over the footprint of the item behind, then checks every cell behind the
+//   item in front against it, like occludes does for two neighbours that don't overlap
+// - the footprints go from 1 to 36 cells, to find where hashing starts to beat scanning, which is
+//   what HASHED_FOOTPRINT_THRESHOLD is set from
+fn footprint_lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("footprint_lookup");
+    for (cols, rows) in [
+        (1, 1),
+        (2, 2),
+        (3, 3),
+        (2, 5),
+        (3, 4),
+        (2, 7),
+        (4, 4),
+        (5, 5),
+        (6, 6),
+    ] {
+        let dims = UVec3::new(cols, rows, 3);
+        let in_front = CurrentCells::new(Cell::new(16, 40), dims, Direction::BottomRight, MAP_SIZE);
+        let beside = CurrentCells::new(Cell::new(26, 40), dims, Direction::BottomRight, MAP_SIZE);
+
+        for (name, footprint) in [
+            ("listed", Footprint::listed as fn(&[Cell]) -> Footprint),
+            ("hashed", Footprint::hashed),
+        ] {
+            group.bench_with_input(
+                BenchmarkId::new(name, cols * rows),
+                &(&in_front, &beside),
+                |b, (in_front, beside)| {
+                    b.iter(|| {
+                        let lookup = footprint(black_box(&beside.underneath));
+                        in_front
+                            .behind
+                            .iter()
+                            .filter(|cell| lookup.contains(cell))
+                            .count()
+                    });
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+// NOTE:
+// - items with no height have nothing behind them, so this only times building the footprint
+fn underneath(c: &mut Criterion) {
+    let mut group = c.benchmark_group("underneath");
+    for side in [1, 6, 20] {
+        let dims = UVec3::new(side, side, 0);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{side}x{side}")),
+            &dims,
+            |b, dims| {
+                b.iter(|| {
+                    CurrentCells::new(
+                        black_box(Cell::new(16, 60)),
+                        black_box(*dims),
+                        Direction::BottomRight,
+                        MAP_SIZE,
+                    )
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, footprint_lookup, underneath);
+criterion_main!(benches);
@@ -2,8 +2,13 @@ use bevy::{app::AppExit, prelude::*};
 use isometric_sort::cells::{
     cell::{Cell, Direction},
     current::CurrentCells,
-    saved::{Check, CompareTransforms, Corrects, EntitiesNearby, Results, SavedCells, SortMethod},
-    sort::{sort_items_partial_cmp, sort_items_topological},
+    grid::{update_cell_index, CellIndex},
+    map::{sync_map_size, MapGrid, MapSize},
+    saved::{
+        highlight_divergent_ranks, Check, CompareTransforms, Corrects, EntitiesNearby, Results,
+        SavedCells, SortMethod, Timings,
+    },
+    sort::{sort_items_brute_force, sort_items_partial_cmp, sort_items_topological, NoOcclusion},
 };
 
 #[derive(Clone, Debug, Default, PartialEq, Eq, Hash, States)]
@@ -21,18 +26,25 @@ fn main() {
         .register_type::<Direction>()
         .register_type::<SavedCells>()
         .init_resource::<Results>()
+        .init_resource::<Timings>()
+        .init_resource::<CellIndex>()
+        .add_startup_system(spawn_map)
         .add_startup_system(load_scene)
         .add_startup_system(load_checks)
-        .add_system(map_saved_cells_to_current)
+        .add_system(sync_map_size)
+        .add_system(map_saved_cells_to_current.after(sync_map_size))
         .add_systems(
             (
-                find_nearby_entities,
+                update_cell_index,
+                find_nearby_entities.after(update_cell_index),
                 sort_items_topological,
                 sort_items_partial_cmp,
+                sort_items_brute_force,
             )
                 .in_schedule(OnEnter(TestState::Compare)),
         )
         .add_system(check_z.run_if(in_state(TestState::Compare)))
+        .add_system(highlight_divergent_ranks.run_if(in_state(TestState::Compare)))
         .add_system(
             print_results
                 .after(check_z)
@@ -46,6 +58,11 @@ fn main() {
 }
 
 const SCENE_ID: u8 = 1;
+const MAP_SIZE: UVec2 = UVec2::new(128, 128);
+
+fn spawn_map(mut commands: Commands) {
+    commands.spawn(MapGrid { size: MAP_SIZE });
+}
 
 fn load_scene(mut commands: Commands, asset_server: Res<AssetServer>) {
     commands.spawn(DynamicSceneBundle {
@@ -70,9 +87,13 @@ fn load_checks(mut commands: Commands, asset_server: Res<AssetServer>) {
 fn map_saved_cells_to_current(
     mut commands: Commands,
     mut state: ResMut<NextState<TestState>>,
+    map_size: Option<Res<MapSize>>,
     items: Query<(Entity, &SavedCells), With<Transform>>,
     checks: Query<(Entity, &SavedCells), Without<Transform>>,
 ) {
+    let Some(map_size) = map_size else {
+        return;
+    };
     if items.iter().count() == 0 || checks.iter().count() == 0 {
         return;
     }
@@ -83,12 +104,8 @@ fn map_saved_cells_to_current(
         if saved.dimensions.z == 0 {
             commands.entity(entity).despawn();
         } else {
-            let current = CurrentCells::new(
-                saved.main_cell,
-                saved.dimensions,
-                saved.facing,
-                UVec2::new(128, 128),
-            );
+            let current =
+                CurrentCells::new(saved.main_cell, saved.dimensions, saved.facing, *map_size);
             commands
                 .entity(entity)
                 .remove::<SavedCells>()
@@ -96,12 +113,7 @@ fn map_saved_cells_to_current(
         }
     }
     for (entity, saved) in checks.iter() {
-        let current = CurrentCells::new(
-            saved.main_cell,
-            saved.dimensions,
-            saved.facing,
-            UVec2::new(128, 128),
-        );
+        let current = CurrentCells::new(saved.main_cell, saved.dimensions, saved.facing, *map_size);
         commands
             .entity(entity)
             .remove::<SavedCells>()
@@ -111,9 +123,13 @@ fn map_saved_cells_to_current(
     state.set(TestState::Compare);
 }
 
+type NearbyItemsQuery<'w, 's, 'a> =
+    Query<'w, 's, (Entity, &'a CurrentCells), (Without<Check>, Without<NoOcclusion>)>;
+
 fn find_nearby_entities(
     mut commands: Commands,
-    items: Query<(Entity, &CurrentCells), Without<Check>>,
+    index: Res<CellIndex>,
+    items: NearbyItemsQuery,
     checks: Query<(Entity, &CurrentCells), With<Check>>,
 ) {
     for (check_entity, check_cells) in checks.iter() {
@@ -122,25 +138,18 @@ fn find_nearby_entities(
             .find(|(_, cells)| cells.main_cell == check_cells.main_cell)
             .unwrap();
 
-        let entities_behind = items
-            .iter()
-            .filter(|(_, cells)| {
-                cells
-                    .underneath
-                    .iter()
-                    .any(|under| check_cells.behind.contains(under))
-            })
-            .map(|(entity, _)| entity)
+        let entities_behind = index
+            .standing_on(&check_cells.behind)
+            .into_iter()
+            .filter(|entity| items.contains(*entity))
             .collect::<Vec<Entity>>();
 
+        // NOTE:
+        // - the index only goes from a cell to the items standing on it, so the items in front
+        //   are the ones with the check standing on their behind cells
         let entities_in_front = items
             .iter()
-            .filter(|(_, cells)| {
-                cells
-                    .behind
-                    .iter()
-                    .any(|behind| check_cells.underneath.contains(behind))
-            })
+            .filter(|(_, cells)| index.standing_on(&cells.behind).contains(&check_entity))
             .map(|(entity, _)| entity)
             .collect::<Vec<Entity>>();
 
@@ -165,20 +174,20 @@ fn check_z(
                 let corresponding_z = items
                     .get(check.corresponding)
                     .ok()
-                    .and_then(|compare| compare.map.get(method))
+                    .map(|compare| compare.get(*method))
                     .unwrap();
                 let behind_zs = check
                     .behind
                     .iter()
                     .flat_map(|entity| items.get(*entity).ok())
-                    .flat_map(|compare| compare.map.get(method))
-                    .collect::<Vec<&f32>>();
+                    .map(|compare| compare.get(*method))
+                    .collect::<Vec<f32>>();
                 let in_front_zs = check
                     .in_front
                     .iter()
                     .flat_map(|entity| items.get(*entity).ok())
-                    .flat_map(|compare| compare.map.get(method))
-                    .collect::<Vec<&f32>>();
+                    .map(|compare| compare.get(*method))
+                    .collect::<Vec<f32>>();
                 (method, corresponding_z, behind_zs, in_front_zs)
             })
             .for_each(|(method, item_z, behind_z, in_front_z)| {
@@ -192,6 +201,18 @@ fn check_z(
                 dbg!(are_behind_z_correct);
                 dbg!(are_in_front_z_correct);
 
+                for misordered in check.misordered(*method, |entity| items.get(entity).ok()) {
+                    let wrong_side = if misordered.should_be_behind {
+                        "in front of"
+                    } else {
+                        "behind"
+                    };
+                    info!(
+                        "{method:?} put {:?} {wrong_side} {:?}, with a z delta of {}",
+                        misordered.entity, check.corresponding, misordered.z_delta
+                    );
+                }
+
                 let corrects = results.map.get_mut(method).unwrap();
                 corrects.push(Corrects {
                     all_behind: are_behind_z_correct,
@@ -201,15 +222,10 @@ fn check_z(
     }
 }
 
-fn print_results(results: Res<Results>) {
-    for method in &SortMethod::all() {
-        println!("======================");
-        let corrects = results.map.get(method).unwrap();
-        dbg!(method);
-        corrects.iter().for_each(|corrects| {
-            dbg!(corrects.are_both_true());
-        })
-    }
+fn print_results(results: Res<Results>, timings: Res<Timings>) {
+    print!("{}", results.report());
+    println!("======================");
+    print!("{}", timings.report());
 }
 
 fn exit(mut app_exit_events: EventWriter<AppExit>) {
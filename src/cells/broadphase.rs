@@ -0,0 +1,190 @@
+use bevy::{
+    ecs::{
+        entity::Entity,
+        query::{Added, Changed, Without},
+        removal_detection::RemovedComponents,
+        system::{Query, ResMut, Resource},
+    },
+    math::UVec2,
+    utils::HashMap,
+};
+
+use crate::cells::{current::CurrentCells, sort::NoOcclusion};
+
+// NOTE:
+// - a persistent index of which items are in which square bucket of cells, for scenes that
+//   are mostly static, so that finding the items near another doesn't need to check all of them
+// - items are indexed by both their underneath and behind cells, so two items that overlap
+//   always share at least one bucket
+#[derive(Debug, Resource)]
+pub struct BroadphaseGrid {
+    pub bucket_size: u32,
+    buckets: HashMap<UVec2, Vec<Entity>>,
+    entity_buckets: HashMap<Entity, Vec<UVec2>>,
+}
+
+impl Default for BroadphaseGrid {
+    fn default() -> Self {
+        Self::new(8)
+    }
+}
+
+impl BroadphaseGrid {
+    /// # Panics
+    ///
+    /// Panics if `bucket_size` is 0.
+    pub fn new(bucket_size: u32) -> Self {
+        assert!(bucket_size > 0, "Buckets must be at least one cell wide");
+        Self {
+            bucket_size,
+            buckets: HashMap::default(),
+            entity_buckets: HashMap::default(),
+        }
+    }
+
+    pub fn insert(&mut self, entity: Entity, cells: &CurrentCells) {
+        self.remove(entity);
+        let buckets = self.buckets_of(cells);
+        for bucket in &buckets {
+            self.buckets.entry(*bucket).or_default().push(entity);
+        }
+        self.entity_buckets.insert(entity, buckets);
+    }
+
+    pub fn remove(&mut self, entity: Entity) {
+        let Some(buckets) = self.entity_buckets.remove(&entity) else {
+            return;
+        };
+        for bucket in buckets {
+            if let Some(entities) = self.buckets.get_mut(&bucket) {
+                entities.retain(|other| *other != entity);
+                if entities.is_empty() {
+                    self.buckets.remove(&bucket);
+                }
+            }
+        }
+    }
+
+    // NOTE:
+    // - returns every item sharing a bucket with these cells, which is a superset of the items
+    //   that are actually in front or behind them
+    pub fn query_near(&self, cells: &CurrentCells) -> Vec<Entity> {
+        let mut near = self
+            .buckets_of(cells)
+            .iter()
+            .filter_map(|bucket| self.buckets.get(bucket))
+            .flatten()
+            .copied()
+            .collect::<Vec<Entity>>();
+        near.sort();
+        near.dedup();
+        near
+    }
+
+    fn buckets_of(&self, cells: &CurrentCells) -> Vec<UVec2> {
+        let mut buckets = cells
+            .underneath
+            .iter()
+            .chain(cells.behind.iter())
+            .map(|cell| UVec2::new(cell.x, cell.y) / self.bucket_size)
+            .collect::<Vec<UVec2>>();
+        buckets.sort_by_key(|bucket| (bucket.y, bucket.x));
+        buckets.dedup();
+        buckets
+    }
+}
+
+type ChangedItemsQuery<'w, 's, 'a> =
+    Query<'w, 's, (Entity, &'a CurrentCells), (Changed<CurrentCells>, Without<NoOcclusion>)>;
+
+// NOTE:
+// - an item that loses NoOcclusion is indexed again, as its CurrentCells may not have changed
+//   since it was left out
+#[allow(clippy::needless_pass_by_value)]
+pub fn update_broadphase(
+    mut grid: ResMut<BroadphaseGrid>,
+    changed: ChangedItemsQuery,
+    items: Query<&CurrentCells, Without<NoOcclusion>>,
+    no_occlusion: Query<Entity, Added<NoOcclusion>>,
+    mut removed: RemovedComponents<CurrentCells>,
+    mut restored: RemovedComponents<NoOcclusion>,
+) {
+    for entity in (&mut removed).into_iter().chain(no_occlusion.iter()) {
+        grid.remove(entity);
+    }
+    let restored = (&mut restored)
+        .into_iter()
+        .filter_map(|entity| items.get(entity).ok().map(|cells| (entity, cells)));
+    for (entity, cells) in changed.iter().chain(restored) {
+        grid.insert(entity, cells);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        ecs::{schedule::Schedule, world::World},
+        math::UVec3,
+    };
+
+    use super::*;
+    use crate::cells::{
+        cell::{Cell, Direction},
+        map::MapSize,
+    };
+
+    fn cells(main_cell: Cell) -> CurrentCells {
+        CurrentCells::new(
+            main_cell,
+            UVec3::ONE,
+            Direction::BottomRight,
+            MapSize::new(64, 64),
+        )
+    }
+
+    #[test]
+    fn query_near_finds_adjacent_and_skips_distant() {
+        let mut world = World::default();
+        world.init_resource::<BroadphaseGrid>();
+        let adjacent = world.spawn(cells(Cell::new(10, 9))).id();
+        let distant = world.spawn(cells(Cell::new(50, 50))).id();
+        let mut schedule = Schedule::default();
+        schedule.add_system(update_broadphase);
+        schedule.run(&mut world);
+
+        let near = world
+            .resource::<BroadphaseGrid>()
+            .query_near(&cells(Cell::new(10, 10)));
+        assert!(near.contains(&adjacent));
+        assert!(!near.contains(&distant));
+
+        world.despawn(adjacent);
+        schedule.run(&mut world);
+        let near = world
+            .resource::<BroadphaseGrid>()
+            .query_near(&cells(Cell::new(10, 10)));
+        assert!(near.is_empty());
+    }
+
+    #[test]
+    fn item_losing_no_occlusion_is_indexed_again() {
+        let mut world = World::default();
+        world.init_resource::<BroadphaseGrid>();
+        let item = world.spawn(cells(Cell::new(10, 9))).id();
+        let mut schedule = Schedule::default();
+        schedule.add_system(update_broadphase);
+        let near = |world: &World| {
+            world
+                .resource::<BroadphaseGrid>()
+                .query_near(&cells(Cell::new(10, 10)))
+        };
+
+        world.entity_mut(item).insert(NoOcclusion);
+        schedule.run(&mut world);
+        assert!(near(&world).is_empty());
+
+        world.entity_mut(item).remove::<NoOcclusion>();
+        schedule.run(&mut world);
+        assert_eq!(near(&world), vec![item]);
+    }
+}
@@ -1,10 +1,15 @@
 use bevy::{
-    math::{IVec2, UVec2},
+    math::{IVec2, UVec2, Vec2},
     reflect::Reflect,
+    sprite::Anchor,
+    utils::HashSet,
 };
 use std::cmp::Ordering;
 
-#[derive(Clone, Copy, PartialEq, Eq, Reflect)]
+use crate::cells::{current::CurrentCells, map::MapSize};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Reflect)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Cell {
     pub x: u32,
     pub y: u32,
@@ -15,10 +20,261 @@ impl Cell {
         Self { x, y }
     }
 
-    pub fn next_cell(self, direction: Direction, map_size: UVec2) -> Option<Cell> {
+    pub fn next_cell(self, direction: Direction, map_size: MapSize) -> Option<Cell> {
         self.nth_cell_in_direction(direction, 1, map_size)
     }
 
+    // NOTE:
+    // - chunks must start on an even row, so that local and global rows share the same stagger
+    // - returns None if the global cell is past u32::MAX
+    /// # Panics
+    ///
+    /// Panics if `chunk_origin.y` is odd.
+    #[must_use]
+    pub fn to_global(self, chunk_origin: UVec2) -> Option<Cell> {
+        assert!(
+            chunk_origin.y.is_multiple_of(2),
+            "Chunks must start on an even row"
+        );
+        let x = self.x.checked_add(chunk_origin.x)?;
+        let y = self.y.checked_add(chunk_origin.y)?;
+        Some(Cell::new(x, y))
+    }
+
+    /// # Panics
+    ///
+    /// Panics if `chunk_origin.y` is odd.
+    #[must_use]
+    pub fn to_local(self, chunk_origin: UVec2) -> Option<Cell> {
+        assert!(
+            chunk_origin.y.is_multiple_of(2),
+            "Chunks must start on an even row"
+        );
+        let x = self.x.checked_sub(chunk_origin.x)?;
+        let y = self.y.checked_sub(chunk_origin.y)?;
+        Some(Cell::new(x, y))
+    }
+
+    // NOTE:
+    // - the cells on the straight line from self to other, both included, stepping each time to
+    //   the neighbour that leaves the shortest way to other on the diamond lattice
+    // - stops at the edge of the map if the line leaves it before reaching other
+    pub fn line_to(self, other: Cell, map_size: MapSize) -> impl Iterator<Item = Cell> {
+        let target = other.diamond_coords();
+        std::iter::successors(Some(self), move |cell| {
+            if *cell == other {
+                return None;
+            }
+            let position = IVec2::from(*cell);
+            let direction = Direction::all().into_iter().min_by_key(|direction| {
+                let next_cell = position + cell.offset(*direction);
+                let remaining = target - Self::offset_diamond_coords(next_cell);
+                remaining.dot(remaining)
+            })?;
+            cell.next_cell(direction, map_size)
+        })
+    }
+
+    // NOTE:
+    // - the fewest next_cell moves from self to other, across all 8 directions
+    // - on the halved diamond lattice the diagonals move along one axis and the other directions
+    //   along both, so this is the largest of the two distances
+    #[allow(clippy::cast_sign_loss)]
+    pub fn distance(self, other: Cell) -> u32 {
+        let delta = (other.diamond_coords() - self.diamond_coords()) / 2;
+        delta.x.abs().max(delta.y.abs()) as u32
+    }
+
+    // NOTE:
+    // - every cell reachable from self in radius next_cell moves or fewer, self included, sorted
+    //   in render order
+    // - the moves stay on the map, so near its edges this can leave out cells that are within
+    //   distance of self
+    pub fn cells_within(self, radius: u32, map_size: MapSize) -> Vec<Cell> {
+        let mut within = HashSet::from_iter([self]);
+        let mut frontier = vec![self];
+        for _ in 0..radius {
+            frontier = frontier
+                .into_iter()
+                .flat_map(|cell| cell.all_next_cells(map_size))
+                .filter(|cell| within.insert(*cell))
+                .collect();
+        }
+        let mut within = within.into_iter().collect::<Vec<Cell>>();
+        within.sort();
+        within
+    }
+
+    // NOTE:
+    // - the cells from min to max, both included, in rows and columns of the map, clipped to the
+    //   map, for systems that only look at the cells in view
+    // - cells are yielded in their render order, row by row
+    pub fn window(min: Cell, max: Cell, map_size: MapSize) -> impl Iterator<Item = Cell> {
+        let max_x = max.x.min(map_size.0.x.saturating_sub(1));
+        let max_y = max.y.min(map_size.0.y.saturating_sub(1));
+        let in_map = map_size.0.x > 0 && map_size.0.y > 0;
+        (min.y..=max_y)
+            .filter(move |_| in_map)
+            .flat_map(move |y| (min.x..=max_x).map(move |x| Cell::new(x, y)))
+    }
+
+    pub fn is_adjacent(self, other: Cell, map_size: MapSize) -> bool {
+        self.adjacent_direction(other)
+            .and_then(|direction| self.next_cell(direction, map_size))
+            .is_some()
+    }
+
+    // NOTE:
+    // - same cells as all_next_cells, but counted without collecting them
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn valid_neighbor_count(self, map_size: MapSize) -> u8 {
+        Direction::all()
+            .into_iter()
+            .filter(|direction| self.next_cell(*direction, map_size).is_some())
+            .count() as u8
+    }
+
+    // NOTE:
+    // - cells on the centre lines of the map count as Right and Bottom
+    pub fn quadrant(self, map_size: MapSize) -> Quadrant {
+        let is_left = self.x < map_size.0.x / 2;
+        let is_top = self.y < map_size.0.y / 2;
+        match (is_top, is_left) {
+            (true, true) => Quadrant::TopLeft,
+            (true, false) => Quadrant::TopRight,
+            (false, true) => Quadrant::BottomLeft,
+            (false, false) => Quadrant::BottomRight,
+        }
+    }
+
+    pub fn adjacent_direction(self, other: Cell) -> Option<Direction> {
+        Direction::all()
+            .into_iter()
+            .find(|direction| IVec2::from(self) + self.offset(*direction) == IVec2::from(other))
+    }
+
+    // NOTE:
+    // - the centre of Cell(0, 0) is at origin, and y grows upwards in world space
+    // - each row is half a tile high, and odd rows are shifted half a tile to the right
+    #[allow(clippy::cast_precision_loss)]
+    pub fn to_world(self, tile_size: Vec2, origin: Vec2) -> Vec2 {
+        let odd_row_shift = (self.y % 2) as f32 / 2.;
+        origin
+            + Vec2::new(
+                (self.x as f32 + odd_row_shift) * tile_size.x,
+                -(self.y as f32) * tile_size.y / 2.,
+            )
+    }
+
+    // NOTE:
+    // - the position of the chosen point of the tile's diamond, for renderers that anchor tiles
+    //   on a corner rather than on the centre
+    // - origin is the centre of Cell(0, 0), like in to_world
+    pub fn to_world_with_origin(self, tile_size: Vec2, origin: Vec2, anchor: TileOrigin) -> Vec2 {
+        let centre = self.to_world(tile_size, origin);
+        match anchor {
+            TileOrigin::Center => centre,
+            TileOrigin::TopCorner => centre + Vec2::new(0., tile_size.y / 2.),
+            TileOrigin::BottomCorner => centre - Vec2::new(0., tile_size.y / 2.),
+        }
+    }
+
+    // NOTE:
+    // - the bottom centre of a sprite of sprite_size placed on this cell with the given anchor,
+    //   which is where the sprite seems to stand, for sprites not anchored at their base
+    pub fn sprite_base_world(
+        self,
+        tile_size: Vec2,
+        origin: Vec2,
+        anchor: &Anchor,
+        sprite_size: Vec2,
+    ) -> Vec2 {
+        let anchor = anchor.as_vec();
+        self.to_world(tile_size, origin)
+            - Vec2::new(anchor.x * sprite_size.x, (anchor.y + 0.5) * sprite_size.y)
+    }
+
+    // NOTE:
+    // - a point on the straight line between the centres of a and b, with t = 0. at a and
+    //   t = 1. at b, like for a camera panning from one cell to the other
+    pub fn lerp_world(a: Cell, b: Cell, t: f32, tile_size: Vec2, origin: Vec2) -> Vec2 {
+        a.to_world(tile_size, origin)
+            .lerp(b.to_world(tile_size, origin), t)
+    }
+
+    // NOTE:
+    // - each tile is a diamond around its centre, so the position is first expressed on the
+    //   diamond lattice, where rounding to the nearest point finds the tile it falls in
+    // - origin is the centre of Cell(0, 0), like in to_world
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn from_world(
+        position: Vec2,
+        tile_size: Vec2,
+        origin: Vec2,
+        map_size: MapSize,
+    ) -> Option<Cell> {
+        let position = position - origin;
+        let across = position.x / tile_size.x;
+        let down = -position.y / tile_size.y;
+        let top_right = (across - down + 0.5).floor() as i32;
+        let bottom_right = (across + down + 0.5).floor() as i32;
+
+        let y = bottom_right - top_right;
+        let x = (top_right + bottom_right - y.rem_euclid(2)).div_euclid(2);
+        Self::maybe_new_from_offset(IVec2::new(x, y), map_size.as_ivec2())
+    }
+
+    // NOTE:
+    // - the centre of the cell under position, for snapping sprites to the grid
+    // - every position within a cell gives the exact same centre, as it's computed from the cell
+    //   alone, so snapped sprites don't shimmer from one frame to the next
+    // - returns None for positions outside of the map, like from_world
+    pub fn snap_world(
+        position: Vec2,
+        tile_size: Vec2,
+        origin: Vec2,
+        map_size: MapSize,
+    ) -> Option<Vec2> {
+        Self::from_world(position, tile_size, origin, map_size)
+            .map(|cell| cell.to_world(tile_size, origin))
+    }
+
+    // NOTE:
+    // - an item facing BottomRight lays dimensions.x along the TopRight/BottomLeft axis
+    // - an item facing BottomLeft lays dimensions.x along the TopLeft/BottomRight axis
+    // so the facing follows whichever diagonal axis the drag moved along the most
+    pub fn between_facings(start: Cell, end: Cell) -> Direction {
+        let delta = end.diamond_coords() - start.diamond_coords();
+        if delta.x.abs() >= delta.y.abs() {
+            Direction::BottomRight
+        } else {
+            Direction::BottomLeft
+        }
+    }
+
+    // Doubled coordinates on the diamond lattice:
+    // - x grows by 2 for each step TopRight
+    // - y grows by 2 for each step BottomRight
+    pub(crate) fn diamond_coords(self) -> IVec2 {
+        Self::offset_diamond_coords(IVec2::from(self))
+    }
+
+    // NOTE:
+    // - like diamond_coords, but for offsets that may fall off the map
+    fn offset_diamond_coords(cell: IVec2) -> IVec2 {
+        let odd_row_shift = cell.y.rem_euclid(2);
+        IVec2::new(
+            2 * cell.x + odd_row_shift - cell.y,
+            2 * cell.x + odd_row_shift + cell.y,
+        )
+    }
+
+    pub(crate) fn from_diamond_coords(coords: IVec2, map_size: MapSize) -> Option<Cell> {
+        let y = (coords.y - coords.x) / 2;
+        let x = (i32::midpoint(coords.x, coords.y) - y.rem_euclid(2)).div_euclid(2);
+        Self::maybe_new_from_offset(IVec2::new(x, y), map_size.as_ivec2())
+    }
+
     fn maybe_new_from_offset(cell: IVec2, map_max: IVec2) -> Option<Self> {
         let respects_lower_map_bound = cell.x >= 0 && cell.y >= 0;
         let respects_higher_map_bound = cell.x < map_max.x && cell.y < map_max.y;
@@ -29,15 +285,14 @@ impl Cell {
         })
     }
 
-    #[allow(dead_code)]
-    fn all_next_cells(self, map_size: UVec2) -> impl Iterator<Item = Cell> {
+    fn all_next_cells(self, map_size: MapSize) -> impl Iterator<Item = Cell> {
         self.directional_next_cells(map_size, Direction::all().iter())
             .into_iter()
             .flatten()
     }
 
     #[allow(dead_code)]
-    fn diagonal_next_cells(self, map_size: UVec2) -> impl Iterator<Item = Cell> {
+    fn diagonal_next_cells(self, map_size: MapSize) -> impl Iterator<Item = Cell> {
         self.directional_next_cells(map_size, Direction::diagonals().iter())
             .into_iter()
             .flatten()
@@ -45,7 +300,7 @@ impl Cell {
 
     fn directional_next_cells<'d>(
         self,
-        map_size: UVec2,
+        map_size: MapSize,
         directions: impl Iterator<Item = &'d Direction>,
     ) -> Vec<Option<Cell>> {
         directions.fold(Vec::new(), |mut cells, direction| {
@@ -55,23 +310,39 @@ impl Cell {
         })
     }
 
-    fn nth_cell_in_direction(self, direction: Direction, n: u32, map_size: UVec2) -> Option<Cell> {
+    // NOTE:
+    // - the cells that the behind search of a 1x1 item on this cell discovers at the given step,
+    //   for probing occlusion without building a CurrentCells
+    pub fn behind_arc(self, radius: u32, map_size: MapSize) -> Vec<Cell> {
+        if radius == 0 {
+            return Vec::new();
+        }
+        let mut arc = CurrentCells::behind_steps(&[self], radius, map_size)
+            .pop()
+            .unwrap_or_default();
+        arc.sort();
+        arc
+    }
+
+    // NOTE:
+    // - the cell n steps away in direction, or None if any of the steps leaves the map
+    pub fn nth_cell_in_direction(
+        self,
+        direction: Direction,
+        n: u32,
+        map_size: MapSize,
+    ) -> Option<Cell> {
         let map_max = map_size.as_ivec2();
-        (0..n).fold(Some(self), |mut next_cell, _| {
-            next_cell = next_cell
-                .map(|cell| {
-                    let mut next_cell = IVec2::from(cell);
-                    next_cell += cell.offset(direction);
-                    next_cell
-                })
-                .and_then(|cell| Self::maybe_new_from_offset(cell, map_max));
-            next_cell
+        (0..n).try_fold(self, |cell, _| {
+            let mut next_cell = IVec2::from(cell);
+            next_cell += cell.offset(direction);
+            Self::maybe_new_from_offset(next_cell, map_max)
         })
     }
 
     #[allow(clippy::match_same_arms)]
     fn offset(self, direction: Direction) -> IVec2 {
-        let is_y_even = self.y % 2 == 0;
+        let is_y_even = self.y.is_multiple_of(2);
         match (direction, is_y_even) {
             (Direction::Top, _) => IVec2::new(0, -2),
             (Direction::TopRight, true) => IVec2::new(0, -1),
@@ -121,6 +392,7 @@ impl std::fmt::Debug for Cell {
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Reflect)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Direction {
     Top,
     TopRight,
@@ -133,8 +405,7 @@ pub enum Direction {
 }
 
 impl Direction {
-    #[allow(dead_code)]
-    fn all() -> [Self; 8] {
+    pub fn all() -> [Self; 8] {
         [
             Self::Top,
             Self::TopRight,
@@ -147,7 +418,93 @@ impl Direction {
         ]
     }
 
-    #[allow(dead_code)]
+    // NOTE:
+    // - the directions that the cells behind an item are found in, checked in this order
+    pub fn behind_set() -> [Self; 3] {
+        [Self::TopLeft, Self::TopRight, Self::Top]
+    }
+
+    // NOTE:
+    // - the directions that dimensions.x and dimensions.y expand towards from main_cell, for an
+    //   item with this facing
+    // - main_cell is the bottom-most cell for every facing, so the footprint always expands
+    //   upwards, and facing the opposite way lays the dimensions along the same axes
+    /// # Panics
+    ///
+    /// Panics if `facing` is not one of the diagonal directions.
+    pub fn footprint_expansion(facing: Self) -> (Self, Self) {
+        match facing {
+            Self::BottomRight | Self::TopLeft => (Self::TopRight, Self::TopLeft),
+            Self::BottomLeft | Self::TopRight => (Self::TopLeft, Self::TopRight),
+            _ => panic!(
+                "Items can only face BottomRight or BottomLeft, or TopLeft or TopRight,\n{facing:?} is not valid"
+            ),
+        }
+    }
+
+    // NOTE:
+    // - the move of next_cell in diamond_coords, which is the same from even and odd rows
+    pub(crate) fn diamond_step(self) -> IVec2 {
+        match self {
+            Self::Top => IVec2::new(2, -2),
+            Self::TopRight => IVec2::new(2, 0),
+            Self::Right => IVec2::new(2, 2),
+            Self::BottomRight => IVec2::new(0, 2),
+            Self::Bottom => IVec2::new(-2, 2),
+            Self::BottomLeft => IVec2::new(-2, 0),
+            Self::Left => IVec2::new(-2, -2),
+            Self::TopLeft => IVec2::new(0, -2),
+        }
+    }
+
+    #[must_use]
+    pub const fn opposite(self) -> Self {
+        match self {
+            Self::Top => Self::Bottom,
+            Self::TopRight => Self::BottomLeft,
+            Self::Right => Self::Left,
+            Self::BottomRight => Self::TopLeft,
+            Self::Bottom => Self::Top,
+            Self::BottomLeft => Self::TopRight,
+            Self::Left => Self::Right,
+            Self::TopLeft => Self::BottomRight,
+        }
+    }
+
+    // NOTE:
+    // - one step clockwise in the order of all(), so four steps give the opposite direction
+    #[must_use]
+    pub const fn rotate_cw(self) -> Self {
+        match self {
+            Self::Top => Self::TopRight,
+            Self::TopRight => Self::Right,
+            Self::Right => Self::BottomRight,
+            Self::BottomRight => Self::Bottom,
+            Self::Bottom => Self::BottomLeft,
+            Self::BottomLeft => Self::Left,
+            Self::Left => Self::TopLeft,
+            Self::TopLeft => Self::Top,
+        }
+    }
+
+    #[must_use]
+    pub const fn rotate_ccw(self) -> Self {
+        match self {
+            Self::Top => Self::TopLeft,
+            Self::TopRight => Self::Top,
+            Self::Right => Self::TopRight,
+            Self::BottomRight => Self::Right,
+            Self::Bottom => Self::BottomRight,
+            Self::BottomLeft => Self::Bottom,
+            Self::Left => Self::BottomLeft,
+            Self::TopLeft => Self::Left,
+        }
+    }
+
+    pub fn is_diagonal(self) -> bool {
+        Self::diagonals().contains(&self)
+    }
+
     fn diagonals() -> [Self; 4] {
         [
             Self::TopRight,
@@ -158,10 +515,276 @@ impl Direction {
     }
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TileOrigin {
+    #[default]
+    Center,
+    TopCorner,
+    BottomCorner,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Quadrant {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+// NOTE:
+// - Horizontal flips the cells across the horizontal centre line of the map, top to bottom
+// - Vertical flips the cells across the vertical centre line of the map, left to right
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MirrorAxis {
+    Horizontal,
+    Vertical,
+}
+
+// NOTE:
+// - cells are mirrored in half tile columns, where odd rows sit between the columns of even rows
+// - rows and half tile columns are mirrored around an even line, so that every cell keeps the
+//   stagger of its row: on maps with an even height or with odd rows reaching the right edge, the
+//   last row or column has no mirror, and its cells are dropped
+pub fn mirror_footprint(cells: &[Cell], axis: MirrorAxis, map_size: MapSize) -> Vec<Cell> {
+    let map_max = map_size.as_ivec2();
+    let even_below = |n: i32| n - n.rem_euclid(2);
+    let centre_column = even_below(2 * map_max.x - 1);
+    let centre_row = even_below(map_max.y - 1);
+    cells
+        .iter()
+        .filter_map(|cell| {
+            let cell = IVec2::from(*cell);
+            let column = 2 * cell.x + cell.y % 2;
+            let (column, y) = match axis {
+                MirrorAxis::Horizontal => (column, centre_row - cell.y),
+                MirrorAxis::Vertical => (centre_column - column, cell.y),
+            };
+            let x = (column - y.rem_euclid(2)).div_euclid(2);
+            Cell::maybe_new_from_offset(IVec2::new(x, y), map_max)
+        })
+        .collect()
+}
+
+// NOTE:
+// - a can see b when none of the cells on the line between them is blocked, a and b themselves
+//   can be blocked, like a guard standing on a and a crate on b
+pub fn has_line_of_sight(a: Cell, b: Cell, map_size: MapSize, blocked: &HashSet<Cell>) -> bool {
+    !a.line_to(b, map_size)
+        .any(|cell| cell != a && cell != b && blocked.contains(&cell))
+}
+
+// NOTE:
+// - draws the staggered grid like the comments above the tests, with highlighted cells as #
+#[cfg(test)]
+pub(crate) fn debug_grid(map_size: MapSize, highlight: &[Cell]) -> String {
+    use std::fmt::Write;
+
+    let label = |cell: Cell| {
+        if highlight.contains(&cell) {
+            "#".to_string()
+        } else {
+            format!("{},{}", cell.x, cell.y)
+        }
+    };
+    let width = (0..map_size.0.y)
+        .flat_map(|y| (0..map_size.0.x).map(move |x| Cell::new(x, y)))
+        .map(|cell| label(cell).len())
+        .fold(3, usize::max);
+    let blank_row = format!(
+        "  |{}",
+        format!("{}|", " ".repeat(width)).repeat(map_size.0.x as usize - 1)
+    );
+
+    let mut grid = format!("{blank_row}\n");
+    for y in 0..map_size.0.y {
+        grid.push_str(if y.is_multiple_of(2) { "|" } else { "  |" });
+        for x in 0..map_size.0.x {
+            write!(grid, "{:^width$}|", label(Cell::new(x, y))).unwrap();
+        }
+        grid.push('\n');
+    }
+    grid.push_str(&blank_row);
+    grid
+}
+
 #[cfg(test)]
 mod tests {
+    use bevy::{ecs::entity::Entity, utils::HashMap};
+
     use super::*;
 
+    #[test]
+    fn cells_key_a_hash_map() {
+        let mut occupants = HashMap::default();
+        occupants.insert(Cell::new(1, 2), Entity::from_raw(0));
+        occupants.insert(Cell::new(2, 1), Entity::from_raw(1));
+        assert_eq!(occupants.len(), 2);
+        assert_eq!(occupants.get(&Cell::new(1, 2)), Some(&Entity::from_raw(0)));
+        assert_eq!(occupants.get(&Cell::new(2, 1)), Some(&Entity::from_raw(1)));
+        assert_eq!(occupants.get(&Cell::new(1, 1)), None);
+    }
+
+    #[test]
+    fn three_steps_top_right() {
+        let map_size = MapSize::new(4, 8);
+        let even_row = Cell::new(1, 6).nth_cell_in_direction(Direction::TopRight, 3, map_size);
+        assert_eq!(even_row, Some(Cell::new(2, 3)));
+        let odd_row = Cell::new(1, 5).nth_cell_in_direction(Direction::TopRight, 3, map_size);
+        assert_eq!(odd_row, Some(Cell::new(3, 2)));
+        let off_the_top = Cell::new(0, 1).nth_cell_in_direction(Direction::TopRight, 3, map_size);
+        assert_eq!(off_the_top, None);
+    }
+
+    #[test]
+    fn sprite_base_world_follows_anchor() {
+        let tile_size = Vec2::new(64., 32.);
+        let sprite_size = Vec2::new(64., 128.);
+        let cell = Cell::new(1, 3);
+        let origin = Vec2::new(100., 50.);
+        let position = cell.to_world(tile_size, origin);
+
+        assert_eq!(
+            cell.sprite_base_world(tile_size, origin, &Anchor::BottomCenter, sprite_size),
+            position
+        );
+        assert_eq!(
+            cell.sprite_base_world(tile_size, origin, &Anchor::Center, sprite_size),
+            position - Vec2::new(0., 64.)
+        );
+        assert_eq!(
+            cell.sprite_base_world(tile_size, origin, &Anchor::TopLeft, sprite_size),
+            position + Vec2::new(32., -128.)
+        );
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn mirror_l_shaped_footprint() {
+        let map_size = MapSize::new(5, 9);
+        let tile_size = Vec2::new(64., 32.);
+        let l_shape = [
+            Cell::new(1, 6),
+            Cell::new(1, 5),
+            Cell::new(2, 4),
+            Cell::new(0, 5),
+        ];
+
+        let vertical = mirror_footprint(&l_shape, MirrorAxis::Vertical, map_size);
+        assert_eq!(
+            vertical,
+            vec![
+                Cell::new(3, 6),
+                Cell::new(2, 5),
+                Cell::new(2, 4),
+                Cell::new(3, 5)
+            ]
+        );
+        for (cell, mirrored) in l_shape.iter().zip(&vertical) {
+            let (cell, mirrored) = (
+                cell.to_world(tile_size, Vec2::ZERO),
+                mirrored.to_world(tile_size, Vec2::ZERO),
+            );
+            assert_eq!(cell.y, mirrored.y);
+            assert_eq!(cell.x + mirrored.x, 256.);
+        }
+
+        let horizontal = mirror_footprint(&l_shape, MirrorAxis::Horizontal, map_size);
+        assert_eq!(
+            horizontal,
+            vec![
+                Cell::new(1, 2),
+                Cell::new(1, 3),
+                Cell::new(2, 4),
+                Cell::new(0, 3)
+            ]
+        );
+
+        for axis in [MirrorAxis::Horizontal, MirrorAxis::Vertical] {
+            let mirrored = mirror_footprint(&l_shape, axis, map_size);
+            assert_eq!(mirror_footprint(&mirrored, axis, map_size), l_shape);
+        }
+    }
+
+    #[test]
+    fn mirror_drops_cells_without_a_mirror() {
+        let map_size = MapSize::new(3, 6);
+        let cells = [Cell::new(2, 5), Cell::new(1, 5), Cell::new(0, 4)];
+        assert_eq!(
+            mirror_footprint(&cells, MirrorAxis::Vertical, map_size),
+            vec![Cell::new(0, 5), Cell::new(2, 4)]
+        );
+        assert_eq!(
+            mirror_footprint(&cells, MirrorAxis::Horizontal, map_size),
+            vec![Cell::new(0, 0)]
+        );
+    }
+
+    #[test]
+    fn opposite_and_rotations_round_trip() {
+        for direction in Direction::all() {
+            assert_eq!(direction.opposite().opposite(), direction);
+            assert_eq!(direction.rotate_cw().rotate_ccw(), direction);
+            let mut rotated = direction;
+            for _ in 0..4 {
+                rotated = rotated.rotate_cw();
+            }
+            assert_eq!(rotated, direction.opposite());
+            for _ in 0..4 {
+                rotated = rotated.rotate_cw();
+            }
+            assert_eq!(rotated, direction);
+        }
+        for diagonal in Direction::diagonals() {
+            assert!(diagonal.rotate_cw().rotate_cw().is_diagonal());
+        }
+        assert_eq!(Direction::TopRight.rotate_cw(), Direction::Right);
+        assert_eq!(Direction::Top.rotate_ccw(), Direction::TopLeft);
+    }
+
+    #[test]
+    fn footprint_expansion_matches_underneath() {
+        assert_eq!(
+            Direction::footprint_expansion(Direction::BottomRight),
+            (Direction::TopRight, Direction::TopLeft)
+        );
+        assert_eq!(
+            Direction::footprint_expansion(Direction::BottomLeft),
+            (Direction::TopLeft, Direction::TopRight)
+        );
+        assert_eq!(
+            Direction::footprint_expansion(Direction::TopLeft),
+            Direction::footprint_expansion(Direction::BottomRight)
+        );
+        assert_eq!(
+            Direction::footprint_expansion(Direction::TopRight),
+            Direction::footprint_expansion(Direction::BottomLeft)
+        );
+        assert_eq!(
+            Direction::behind_set(),
+            [Direction::TopLeft, Direction::TopRight, Direction::Top]
+        );
+    }
+
+    #[test]
+    fn diamond_step_matches_next_cell() {
+        let map_size = MapSize::new(6, 9);
+        for cell in [Cell::new(2, 4), Cell::new(2, 5)] {
+            for direction in Direction::all() {
+                let next_cell = cell.next_cell(direction, map_size).unwrap();
+                assert_eq!(
+                    next_cell.diamond_coords() - cell.diamond_coords(),
+                    direction.diamond_step()
+                );
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Items can only face BottomRight or BottomLeft")]
+    fn footprint_expansion_panics_on_invalid_facing() {
+        let _ = Direction::footprint_expansion(Direction::Top);
+    }
+
     #[test]
     fn find_next_cells_01() {
         let cell = Cell::new(0, 1);
@@ -173,8 +796,10 @@ mod tests {
             Cell::new(0, 2),
             Cell::new(0, 0),
         ];
-        let actual = cell.all_next_cells(UVec2::new(4, 6)).collect::<Vec<Cell>>();
-        assert_eq!(actual, expected)
+        let actual = cell
+            .all_next_cells(MapSize::new(4, 6))
+            .collect::<Vec<Cell>>();
+        assert_eq!(actual, expected);
     }
 
     #[test]
@@ -190,8 +815,10 @@ mod tests {
             Cell::new(0, 3),
             Cell::new(1, 2),
         ];
-        let actual = cell.all_next_cells(UVec2::new(4, 6)).collect::<Vec<Cell>>();
-        assert_eq!(actual, expected)
+        let actual = cell
+            .all_next_cells(MapSize::new(4, 6))
+            .collect::<Vec<Cell>>();
+        assert_eq!(actual, expected);
     }
 
     #[test]
@@ -207,8 +834,10 @@ mod tests {
             Cell::new(1, 2),
             Cell::new(1, 1),
         ];
-        let actual = cell.all_next_cells(UVec2::new(4, 6)).collect::<Vec<Cell>>();
-        assert_eq!(actual, expected)
+        let actual = cell
+            .all_next_cells(MapSize::new(4, 6))
+            .collect::<Vec<Cell>>();
+        assert_eq!(actual, expected);
     }
 
     #[test]
@@ -222,7 +851,556 @@ mod tests {
             Cell::new(2, 4),
             Cell::new(2, 3),
         ];
-        let actual = cell.all_next_cells(UVec2::new(4, 6)).collect::<Vec<Cell>>();
-        assert_eq!(actual, expected)
+        let actual = cell
+            .all_next_cells(MapSize::new(4, 6))
+            .collect::<Vec<Cell>>();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn to_world_staggers_odd_rows() {
+        let tile_size = Vec2::new(64., 32.);
+        let to_world = |x, y| Cell::new(x, y).to_world(tile_size, Vec2::ZERO);
+        assert_eq!(to_world(0, 0), Vec2::new(0., 0.));
+        assert_eq!(to_world(1, 0), Vec2::new(64., 0.));
+        assert_eq!(to_world(0, 1), Vec2::new(32., -16.));
+        assert_eq!(to_world(1, 1), Vec2::new(96., -16.));
+        assert_eq!(to_world(0, 2), Vec2::new(0., -32.));
+    }
+
+    #[test]
+    fn to_world_from_an_origin() {
+        let tile_size = Vec2::new(64., 32.);
+        let origin = Vec2::new(-100., 200.);
+        let to_world = |x, y| Cell::new(x, y).to_world(tile_size, origin);
+        assert_eq!(to_world(0, 0), origin);
+        assert_eq!(to_world(1, 1), origin + Vec2::new(96., -16.));
+        assert_eq!(to_world(0, 2), origin + Vec2::new(0., -32.));
+        let map_size = MapSize::new(4, 6);
+        let position = to_world(1, 1) + Vec2::new(10., -5.);
+        assert_eq!(
+            Cell::from_world(position, tile_size, origin, map_size),
+            Some(Cell::new(1, 1))
+        );
+    }
+
+    #[test]
+    fn lerp_world_between_diagonal_cells() {
+        let tile_size = Vec2::new(64., 32.);
+        let origin = Vec2::new(-40., 20.);
+        let (a, b) = (Cell::new(1, 2), Cell::new(2, 4));
+        assert_eq!(
+            Cell::lerp_world(a, b, 0., tile_size, origin),
+            a.to_world(tile_size, origin)
+        );
+        assert_eq!(
+            Cell::lerp_world(a, b, 0.5, tile_size, origin),
+            Cell::new(1, 3).to_world(tile_size, origin)
+        );
+        assert_eq!(
+            Cell::lerp_world(a, b, 1., tile_size, origin),
+            b.to_world(tile_size, origin)
+        );
+    }
+
+    #[test]
+    fn to_world_with_origins() {
+        let tile_size = Vec2::new(64., 32.);
+        let cell = Cell::new(1, 3);
+        let origin = Vec2::new(10., -20.);
+        let centre = cell.to_world_with_origin(tile_size, origin, TileOrigin::Center);
+        assert_eq!(centre, cell.to_world(tile_size, origin));
+        assert_eq!(
+            cell.to_world_with_origin(tile_size, origin, TileOrigin::TopCorner),
+            centre + Vec2::new(0., 16.)
+        );
+        assert_eq!(
+            cell.to_world_with_origin(tile_size, origin, TileOrigin::BottomCorner),
+            centre - Vec2::new(0., 16.)
+        );
+    }
+
+    #[test]
+    fn from_world_round_trip() {
+        let tile_size = Vec2::new(64., 32.);
+        let map_size = MapSize::new(4, 6);
+        for cell in [
+            Cell::new(0, 0),
+            Cell::new(1, 1),
+            Cell::new(0, 2),
+            Cell::new(3, 5),
+        ] {
+            let position = cell.to_world(tile_size, Vec2::ZERO);
+            assert_eq!(
+                Cell::from_world(position, tile_size, Vec2::ZERO, map_size),
+                Some(cell)
+            );
+            let near_position = position + Vec2::new(10., -5.);
+            assert_eq!(
+                Cell::from_world(near_position, tile_size, Vec2::ZERO, map_size),
+                Some(cell)
+            );
+        }
+    }
+
+    #[test]
+    fn snap_world_is_exact_near_centre() {
+        let tile_size = Vec2::new(64., 32.);
+        let map_size = MapSize::new(4, 6);
+        let centre = Cell::new(2, 3).to_world(tile_size, Vec2::ZERO);
+        let snapped = [
+            Vec2::ZERO,
+            Vec2::new(0.49, 0.),
+            Vec2::new(-0.51, 0.33),
+            Vec2::new(12.25, -6.1),
+            Vec2::new(-20., 3.),
+        ]
+        .map(|offset| Cell::snap_world(centre + offset, tile_size, Vec2::ZERO, map_size));
+
+        assert!(snapped.iter().all(|position| *position == Some(centre)));
+        assert_eq!(
+            Cell::snap_world(Vec2::new(-500., 500.), tile_size, Vec2::ZERO, map_size),
+            None
+        );
+    }
+
+    #[test]
+    fn from_world_outside_map() {
+        let tile_size = Vec2::new(64., 32.);
+        let map_size = MapSize::new(4, 6);
+        let position = Vec2::new(-500., 500.);
+        assert_eq!(
+            Cell::from_world(position, tile_size, Vec2::ZERO, map_size),
+            None
+        );
+    }
+
+    #[test]
+    fn from_world_inverts_to_world_from_an_origin() {
+        let tile_size = Vec2::new(64., 32.);
+        let origin = Vec2::new(320., -48.);
+        let map_size = MapSize::new(5, 7);
+        for cell in Cell::window(Cell::new(0, 0), Cell::new(4, 6), map_size) {
+            let position = cell.to_world(tile_size, origin);
+            assert_eq!(
+                Cell::from_world(position, tile_size, origin, map_size),
+                Some(cell)
+            );
+        }
+        assert_eq!(
+            Cell::from_world(Vec2::ZERO, tile_size, origin, map_size),
+            None
+        );
+        let far_below = origin + Vec2::new(0., -1000.);
+        assert_eq!(
+            Cell::from_world(far_below, tile_size, origin, map_size),
+            None
+        );
+    }
+
+    #[test]
+    fn adjacent_direction_even_row() {
+        let cell = Cell::new(1, 2);
+        assert_eq!(
+            cell.adjacent_direction(Cell::new(1, 1)),
+            Some(Direction::TopRight)
+        );
+        assert_eq!(
+            cell.adjacent_direction(Cell::new(0, 1)),
+            Some(Direction::TopLeft)
+        );
+        assert_eq!(cell.adjacent_direction(Cell::new(2, 1)), None);
+    }
+
+    #[test]
+    fn adjacent_direction_odd_row() {
+        let cell = Cell::new(1, 3);
+        assert_eq!(
+            cell.adjacent_direction(Cell::new(2, 2)),
+            Some(Direction::TopRight)
+        );
+        assert_eq!(
+            cell.adjacent_direction(Cell::new(1, 2)),
+            Some(Direction::TopLeft)
+        );
+        assert_eq!(cell.adjacent_direction(Cell::new(0, 2)), None);
+    }
+
+    #[test]
+    fn is_adjacent_across_rows() {
+        let map_size = MapSize::new(4, 6);
+        assert!(Cell::new(1, 2).is_adjacent(Cell::new(0, 3), map_size));
+        assert!(Cell::new(0, 3).is_adjacent(Cell::new(1, 2), map_size));
+        assert!(!Cell::new(1, 2).is_adjacent(Cell::new(2, 3), map_size));
+        assert!(!Cell::new(1, 2).is_adjacent(Cell::new(1, 2), map_size));
+        assert!(!Cell::new(3, 5).is_adjacent(Cell::new(4, 6), map_size));
+    }
+
+    #[test]
+    fn valid_neighbor_counts() {
+        let map_size = MapSize::new(3, 7);
+        assert_eq!(Cell::new(0, 0).valid_neighbor_count(map_size), 3);
+        assert_eq!(Cell::new(2, 6).valid_neighbor_count(map_size), 4);
+        assert_eq!(Cell::new(0, 3).valid_neighbor_count(map_size), 7);
+        assert_eq!(Cell::new(1, 3).valid_neighbor_count(map_size), 8);
+        assert_eq!(
+            usize::from(Cell::new(0, 3).valid_neighbor_count(map_size)),
+            Cell::new(0, 3).all_next_cells(map_size).count()
+        );
+    }
+
+    #[test]
+    fn debug_grid_matches_comments() {
+        let expected = [
+            "  |   |   |",
+            "|0,0|1,0|2,0|",
+            "  |0,1|1,1|2,1|",
+            "|0,2| # |2,2|",
+            "  |   |   |",
+        ]
+        .join("\n");
+        assert_eq!(debug_grid(MapSize::new(3, 3), &[Cell::new(1, 2)]), expected);
+    }
+
+    #[test]
+    fn quadrants() {
+        let map_size = MapSize::new(4, 8);
+        assert_eq!(Cell::new(0, 0).quadrant(map_size), Quadrant::TopLeft);
+        assert_eq!(Cell::new(3, 0).quadrant(map_size), Quadrant::TopRight);
+        assert_eq!(Cell::new(0, 7).quadrant(map_size), Quadrant::BottomLeft);
+        assert_eq!(Cell::new(3, 7).quadrant(map_size), Quadrant::BottomRight);
+        assert_eq!(Cell::new(1, 3).quadrant(map_size), Quadrant::TopLeft);
+        assert_eq!(Cell::new(2, 3).quadrant(map_size), Quadrant::TopRight);
+        assert_eq!(Cell::new(1, 4).quadrant(map_size), Quadrant::BottomLeft);
+        assert_eq!(Cell::new(2, 4).quadrant(map_size), Quadrant::BottomRight);
+    }
+
+    #[test]
+    fn window_inside_map() {
+        let map_size = MapSize::new(10, 10);
+        let window =
+            Cell::window(Cell::new(3, 4), Cell::new(5, 5), map_size).collect::<Vec<Cell>>();
+        assert_eq!(
+            window,
+            vec![
+                Cell::new(3, 4),
+                Cell::new(4, 4),
+                Cell::new(5, 4),
+                Cell::new(3, 5),
+                Cell::new(4, 5),
+                Cell::new(5, 5),
+            ]
+        );
+        assert!(window.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn window_is_clipped_to_map() {
+        let map_size = MapSize::new(10, 10);
+        let window =
+            Cell::window(Cell::new(8, 9), Cell::new(12, 15), map_size).collect::<Vec<Cell>>();
+        assert_eq!(window, vec![Cell::new(8, 9), Cell::new(9, 9)]);
+        assert_eq!(
+            Cell::window(Cell::new(5, 5), Cell::new(4, 4), map_size).count(),
+            0
+        );
+    }
+
+    #[test]
+    fn line_to_follows_the_diagonals() {
+        let map_size = MapSize::new(3, 7);
+        assert_eq!(
+            Cell::new(1, 4)
+                .line_to(Cell::new(1, 0), map_size)
+                .collect::<Vec<Cell>>(),
+            vec![Cell::new(1, 4), Cell::new(1, 2), Cell::new(1, 0)]
+        );
+        assert_eq!(
+            Cell::new(0, 6)
+                .line_to(Cell::new(2, 2), map_size)
+                .collect::<Vec<Cell>>(),
+            vec![
+                Cell::new(0, 6),
+                Cell::new(0, 5),
+                Cell::new(1, 4),
+                Cell::new(1, 3),
+                Cell::new(2, 2),
+            ]
+        );
+        assert_eq!(
+            Cell::new(1, 3)
+                .line_to(Cell::new(1, 3), map_size)
+                .collect::<Vec<Cell>>(),
+            vec![Cell::new(1, 3)]
+        );
+    }
+
+    fn assert_contiguous(line: &[Cell], map_size: MapSize) {
+        for pair in line.windows(2) {
+            assert!(pair[0].is_adjacent(pair[1], map_size), "{line:?}");
+        }
+    }
+
+    #[test]
+    fn line_to_runs_along_rows_and_columns() {
+        let map_size = MapSize::new(5, 9);
+        let row = Cell::new(0, 3)
+            .line_to(Cell::new(3, 3), map_size)
+            .collect::<Vec<Cell>>();
+        assert_eq!(row, (0..=3).map(|x| Cell::new(x, 3)).collect::<Vec<Cell>>());
+        assert_contiguous(&row, map_size);
+
+        let column = Cell::new(2, 1)
+            .line_to(Cell::new(2, 7), map_size)
+            .collect::<Vec<Cell>>();
+        assert_eq!(
+            column,
+            vec![
+                Cell::new(2, 1),
+                Cell::new(2, 3),
+                Cell::new(2, 5),
+                Cell::new(2, 7)
+            ]
+        );
+        assert_contiguous(&column, map_size);
+    }
+
+    #[test]
+    fn line_to_is_contiguous_and_as_short_as_the_distance() {
+        let map_size = MapSize::new(5, 9);
+        let diagonal = Cell::new(1, 8)
+            .line_to(Cell::new(3, 4), map_size)
+            .collect::<Vec<Cell>>();
+        assert_eq!(
+            diagonal,
+            vec![
+                Cell::new(1, 8),
+                Cell::new(1, 7),
+                Cell::new(2, 6),
+                Cell::new(2, 5),
+                Cell::new(3, 4),
+            ]
+        );
+        for start in Cell::window(Cell::new(0, 0), Cell::new(4, 8), map_size) {
+            for end in Cell::window(Cell::new(0, 0), Cell::new(4, 8), map_size) {
+                let line = start.line_to(end, map_size).collect::<Vec<Cell>>();
+                assert_eq!(line.first(), Some(&start));
+                assert_contiguous(&line, map_size);
+                if line.last() == Some(&end) {
+                    assert_eq!(line.len(), start.distance(end) as usize + 1);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn line_to_stops_at_the_map_edge() {
+        let map_size = MapSize::new(3, 7);
+        let line = Cell::new(0, 2)
+            .line_to(Cell::new(5, 2), map_size)
+            .collect::<Vec<Cell>>();
+        assert_eq!(
+            line,
+            vec![Cell::new(0, 2), Cell::new(1, 2), Cell::new(2, 2)]
+        );
+    }
+
+    #[test]
+    fn distance_along_rows_columns_and_diagonals() {
+        for y in [4, 5] {
+            let cell = Cell::new(2, y);
+            assert_eq!(cell.distance(cell), 0);
+            assert_eq!(cell.distance(Cell::new(5, y)), 3);
+            assert_eq!(cell.distance(Cell::new(0, y)), 2);
+            assert_eq!(cell.distance(Cell::new(2, y - 4)), 2);
+            assert_eq!(cell.distance(Cell::new(2, y + 1)), 1);
+            assert_eq!(cell.distance(Cell::new(2, y - 1)), 1);
+            assert_eq!(cell.distance(Cell::new(2, y + 3)), 2);
+        }
+        assert_eq!(Cell::new(1, 4).distance(Cell::new(2, 1)), 3);
+        assert_eq!(Cell::new(1, 5).distance(Cell::new(2, 2)), 2);
+        assert_eq!(Cell::new(2, 2).distance(Cell::new(1, 5)), 2);
+    }
+
+    #[test]
+    fn distance_matches_next_cell_reachability() {
+        let map_size = MapSize::new(6, 9);
+        for start in Cell::window(Cell::new(0, 0), Cell::new(5, 8), map_size) {
+            let mut steps = bevy::utils::HashMap::from_iter([(start, 0)]);
+            let mut frontier = vec![start];
+            let mut n_steps = 0;
+            while !frontier.is_empty() {
+                n_steps += 1;
+                frontier = frontier
+                    .into_iter()
+                    .flat_map(|cell| cell.all_next_cells(map_size).collect::<Vec<Cell>>())
+                    .filter(|cell| !steps.contains_key(cell))
+                    .collect();
+                frontier.sort();
+                frontier.dedup();
+                for cell in &frontier {
+                    steps.insert(*cell, n_steps);
+                }
+            }
+            for (cell, n_steps) in steps {
+                assert_eq!(start.distance(cell), n_steps, "{start:?} to {cell:?}");
+            }
+        }
+    }
+
+    fn rows(rows: &[(u32, &[u32])]) -> Vec<Cell> {
+        rows.iter()
+            .flat_map(|(y, xs)| xs.iter().map(move |x| Cell::new(*x, *y)))
+            .collect()
+    }
+
+    #[test]
+    fn cells_within_one_step() {
+        // NOTE:
+        // - the centre and its 8 neighbours
+        let map_size = MapSize::new(5, 9);
+        assert_eq!(
+            Cell::new(2, 4).cells_within(1, map_size),
+            rows(&[
+                (2, &[2]),
+                (3, &[1, 2]),
+                (4, &[1, 2, 3]),
+                (5, &[1, 2]),
+                (6, &[2])
+            ])
+        );
+        assert_eq!(
+            Cell::new(2, 5).cells_within(1, map_size),
+            rows(&[
+                (3, &[2]),
+                (4, &[2, 3]),
+                (5, &[1, 2, 3]),
+                (6, &[2, 3]),
+                (7, &[2])
+            ])
+        );
+        assert_eq!(
+            Cell::new(0, 0).cells_within(1, map_size),
+            rows(&[(0, &[0, 1]), (1, &[0]), (2, &[0])])
+        );
+        assert_eq!(
+            Cell::new(2, 4).cells_within(0, map_size),
+            vec![Cell::new(2, 4)]
+        );
+    }
+
+    /*
+      |   |   |   |   |
+    |0,0|1,0|2,0|3,0|4,0|
+      |0,1|1,1|2,1|3,1|4,1|
+    |0,2|1,2|2,2|3,2|4,2|
+      |0,3|1,3|2,3|3,3|4,3|
+    |0,4|1,4|2,4|3,4|4,4|
+      |0,5|1,5|2,5|3,5|4,5|
+    |0,6|1,6|2,6|3,6|4,6|
+      |0,7|1,7|2,7|3,7|4,7|
+    |0,8|1,8|2,8|3,8|4,8|
+      |   |   |   |   |
+    */
+
+    #[test]
+    fn cells_within_two_steps() {
+        let map_size = MapSize::new(5, 9);
+        assert_eq!(
+            Cell::new(2, 4).cells_within(2, map_size),
+            rows(&[
+                (0, &[2]),
+                (1, &[1, 2]),
+                (2, &[1, 2, 3]),
+                (3, &[0, 1, 2, 3]),
+                (4, &[0, 1, 2, 3, 4]),
+                (5, &[0, 1, 2, 3]),
+                (6, &[1, 2, 3]),
+                (7, &[1, 2]),
+                (8, &[2]),
+            ])
+        );
+        // NOTE:
+        // - the bottom cell, 2,9, is off the map
+        assert_eq!(
+            Cell::new(2, 5).cells_within(2, map_size),
+            rows(&[
+                (1, &[2]),
+                (2, &[2, 3]),
+                (3, &[1, 2, 3]),
+                (4, &[1, 2, 3, 4]),
+                (5, &[0, 1, 2, 3, 4]),
+                (6, &[1, 2, 3, 4]),
+                (7, &[1, 2, 3]),
+                (8, &[2, 3]),
+            ])
+        );
+    }
+
+    #[test]
+    fn line_of_sight_clear_and_blocked() {
+        let map_size = MapSize::new(3, 7);
+        let (guard, target) = (Cell::new(1, 4), Cell::new(1, 0));
+        let mut blocked = HashSet::from_iter([Cell::new(0, 2), guard, target]);
+        assert!(has_line_of_sight(guard, target, map_size, &blocked));
+
+        blocked.insert(Cell::new(1, 2));
+        assert!(!has_line_of_sight(guard, target, map_size, &blocked));
+        assert!(!has_line_of_sight(target, guard, map_size, &blocked));
+    }
+
+    #[test]
+    fn chunk_coords_across_boundary() {
+        let chunk_a = UVec2::new(0, 0);
+        let chunk_b = UVec2::new(4, 0);
+        let in_a = Cell::new(3, 5).to_global(chunk_a).unwrap();
+        let in_b = Cell::new(0, 6).to_global(chunk_b).unwrap();
+        assert!(in_a.is_adjacent(in_b, MapSize::new(8, 8)));
+        assert_eq!(in_b.to_local(chunk_b), Some(Cell::new(0, 6)));
+        assert_eq!(in_a.to_local(chunk_b), None);
+    }
+
+    #[test]
+    fn chunk_coords_past_u32_max() {
+        let chunk = UVec2::new(u32::MAX - 1, 0);
+        assert_eq!(
+            Cell::new(1, 2).to_global(chunk),
+            Some(Cell::new(u32::MAX, 2))
+        );
+        assert_eq!(Cell::new(2, 2).to_global(chunk), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Chunks must start on an even row")]
+    fn chunk_coords_odd_origin() {
+        let _ = Cell::new(0, 0).to_global(UVec2::new(0, 3));
+    }
+
+    #[test]
+    fn between_facings_drag_top_right() {
+        let start = Cell::new(1, 4);
+        let end = Cell::new(2, 2);
+        assert_eq!(Cell::between_facings(start, end), Direction::BottomRight);
+    }
+
+    #[test]
+    fn between_facings_drag_bottom_left() {
+        let start = Cell::new(1, 3);
+        let end = Cell::new(1, 4);
+        assert_eq!(Cell::between_facings(start, end), Direction::BottomRight);
+    }
+
+    #[test]
+    fn between_facings_drag_top_left() {
+        let start = Cell::new(1, 3);
+        let end = Cell::new(0, 1);
+        assert_eq!(Cell::between_facings(start, end), Direction::BottomLeft);
+    }
+
+    #[test]
+    fn between_facings_drag_bottom_right() {
+        let start = Cell::new(0, 2);
+        let end = Cell::new(0, 3);
+        assert_eq!(Cell::between_facings(start, end), Direction::BottomLeft);
     }
 }
@@ -1,10 +1,68 @@
 use bevy::{
-    ecs::component::Component,
-    math::{UVec2, UVec3},
+    ecs::{component::Component, entity::Entity},
+    math::{IVec2, Rect, UVec3, Vec2},
+    utils::HashSet,
 };
 use std::cmp::Ordering;
 
-use crate::cells::cell::{Cell, Direction};
+use crate::cells::{
+    cell::{Cell, Direction},
+    error::{CellsError, OcclusionCycleError},
+    footprint::Footprint,
+    map::MapSize,
+};
+
+// NOTE:
+// - Cutout items, like fences and foliage, don't hide the items that merely touch their footprint
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Transparency {
+    #[default]
+    Opaque,
+    Cutout,
+}
+
+// NOTE:
+// - the directions checked from each cell when looking for the cells behind an item
+// - the search always climbs one step towards Top for each unit of height, so Top only decides
+//   whether the cells directly above are behind the item as well
+// - a steeper camera angle can narrow these, the default matches a standard isometric view
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BehindDirections(pub Vec<Direction>);
+
+impl Default for BehindDirections {
+    fn default() -> Self {
+        Self(Direction::behind_set().to_vec())
+    }
+}
+
+// NOTE:
+// - Equal: the items have the same main_cell, dimensions and facing
+// - Neither: no cell behind either item is on the other's footprint, so partial_cmp gives None
+// - InFront: self is in front, hidden holds the cells of other's footprint behind self
+// - Behind: self is behind, hidden holds the cells of self's footprint behind other
+// - Both: each item hides part of the other, which makes partial_cmp panic
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CmpExplanation {
+    Equal,
+    Neither,
+    InFront {
+        hidden: Vec<Cell>,
+    },
+    Behind {
+        hidden: Vec<Cell>,
+    },
+    Both {
+        hidden_by_self: Vec<Cell>,
+        hidden_by_other: Vec<Cell>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum InfluenceKind {
+    Footprint,
+    Behind,
+    Shadow,
+}
 
 #[derive(Clone, Debug, Component)]
 pub struct CurrentCells {
@@ -13,18 +71,515 @@ pub struct CurrentCells {
     pub facing: Direction,
     pub underneath: Vec<Cell>,
     pub behind: Vec<Cell>,
+    pub front: Vec<Cell>,
+    pub transparency: Transparency,
+    // NOTE:
+    // - the directions the item was built with, None for the default ones
+    pub behind_directions: Option<BehindDirections>,
+    // NOTE:
+    // - the footprint and the cells behind it together, built with the item and kept up to date
+    //   by the methods changing underneath or behind
+    pub influence: HashSet<Cell>,
+    // NOTE:
+    // - the cells of a footprint built with from_cells, as diamond coords relative to main_cell,
+    //   None for the rectangles built from dimensions
+    // - the methods moving or turning the item move these cells, instead of building a rectangle
+    pub shape: Option<Vec<IVec2>>,
+    // NOTE:
+    // - set by with_inclusive_behind, so that the item keeps its footprint in behind when it is
+    //   rebuilt or its behind is extended
+    pub inclusive_behind: bool,
 }
 
 impl CurrentCells {
-    pub fn new(main_cell: Cell, dims: UVec3, facing: Direction, map_size: UVec2) -> Self {
+    pub fn new(main_cell: Cell, dims: UVec3, facing: Direction, map_size: MapSize) -> Self {
+        Self::new_with_behind_directions(
+            main_cell,
+            dims,
+            facing,
+            map_size,
+            &BehindDirections::default(),
+        )
+    }
+
+    // NOTE:
+    // - like new, but looking for the cells behind the item with the given directions
+    // - the item keeps them, so that extend_behind and the methods rebuilding the item, like
+    //   rotate_around, look for its cells behind with the same directions
+    pub fn new_with_behind_directions(
+        main_cell: Cell,
+        dims: UVec3,
+        facing: Direction,
+        map_size: MapSize,
+        behind_directions: &BehindDirections,
+    ) -> Self {
         let underneath = Self::underneath(main_cell, dims, facing, map_size);
-        let behind = Self::behind(&underneath, dims.z, map_size);
+        let behind = {
+            #[cfg(feature = "trace")]
+            let _behind_span = bevy::log::info_span!("current_cells_behind").entered();
+            Self::behind_towards(&underneath, dims.z, map_size, behind_directions)
+        };
+        let front = Self::front(&underneath, dims.z, map_size);
+        let influence = Self::influence(&underneath, &behind);
         Self {
             main_cell,
             dimensions: dims,
             facing,
             underneath,
             behind,
+            front,
+            transparency: Transparency::Opaque,
+            behind_directions: (*behind_directions != BehindDirections::default())
+                .then(|| behind_directions.clone()),
+            influence,
+            shape: None,
+            inclusive_behind: false,
+        }
+    }
+
+    /// # Errors
+    ///
+    /// Returns an error if `main_cell` or any other cell of the footprint is outside of the map,
+    /// or if `facing` is not one of the diagonal directions.
+    pub fn try_new(
+        main_cell: Cell,
+        dims: UVec3,
+        facing: Direction,
+        map_size: MapSize,
+    ) -> Result<Self, CellsError> {
+        if main_cell.x >= map_size.0.x || main_cell.y >= map_size.0.y {
+            return Err(CellsError::OutOfBounds {
+                cell: main_cell,
+                map_size,
+            });
+        }
+        if !facing.is_diagonal() {
+            return Err(CellsError::InvalidFacing(facing));
+        }
+        if Self::underneath(main_cell, dims, facing, map_size).len() < (dims.x * dims.y) as usize {
+            return Err(CellsError::FootprintOutOfBounds {
+                main_cell,
+                dimensions: dims,
+                map_size,
+            });
+        }
+        Ok(Self::new(main_cell, dims, facing, map_size))
+    }
+
+    pub fn new_batch(
+        placements: &[(Cell, UVec3, Direction)],
+        map_size: MapSize,
+    ) -> Vec<Result<Self, CellsError>> {
+        placements
+            .iter()
+            .map(|(main_cell, dims, facing)| Self::try_new(*main_cell, *dims, *facing, map_size))
+            .collect()
+    }
+
+    // NOTE:
+    // - builds an item from any set of cells, facing BottomRight
+    // - main_cell is the bottom-most cell, and dimensions.x and dimensions.y are the extent of the
+    //   cells towards TopRight and TopLeft
+    // - cells filling that extent make a rectangle like the ones built with new, any other set of
+    //   cells is kept as the shape of the item
+    /// # Panics
+    ///
+    /// Panics if `cells` is empty.
+    pub fn from_cells(mut cells: Vec<Cell>, height: u32, map_size: MapSize) -> Self {
+        cells.sort();
+        cells.dedup();
+        let main_cell = *cells.last().expect("Items must cover at least one cell");
+        let main_coords = main_cell.diamond_coords();
+        let shape = cells
+            .iter()
+            .map(|cell| cell.diamond_coords() - main_coords)
+            .collect();
+        let mut cells = Self::from_shape(
+            main_cell,
+            shape,
+            height,
+            Direction::BottomRight,
+            map_size,
+            &BehindDirections::default(),
+        );
+        if cells.underneath.len() == (cells.dimensions.x * cells.dimensions.y) as usize {
+            cells.shape = None;
+        }
+        cells
+    }
+
+    // NOTE:
+    // - builds the item with the cells of shape around main_cell, leaving out the ones off the map
+    // - dimensions.x and dimensions.y are the extent of the shape towards TopRight and TopLeft
+    fn from_shape(
+        main_cell: Cell,
+        shape: Vec<IVec2>,
+        height: u32,
+        facing: Direction,
+        map_size: MapSize,
+        behind_directions: &BehindDirections,
+    ) -> Self {
+        let main_coords = main_cell.diamond_coords();
+        let mut underneath = shape
+            .iter()
+            .filter_map(|offset| Cell::from_diamond_coords(main_coords + *offset, map_size))
+            .collect::<Vec<Cell>>();
+        underneath.sort();
+
+        let min = shape.iter().copied().fold(IVec2::ZERO, IVec2::min);
+        let max = shape.iter().copied().fold(IVec2::ZERO, IVec2::max);
+        let extent = ((max - min) / 2 + IVec2::ONE).as_uvec2();
+
+        let behind = Self::behind_towards(&underneath, height, map_size, behind_directions);
+        let front = Self::front(&underneath, height, map_size);
+        let influence = Self::influence(&underneath, &behind);
+        Self {
+            main_cell,
+            dimensions: extent.extend(height),
+            facing,
+            underneath,
+            behind,
+            front,
+            transparency: Transparency::Opaque,
+            behind_directions: (*behind_directions != BehindDirections::default())
+                .then(|| behind_directions.clone()),
+            influence,
+            shape: Some(shape),
+            inclusive_behind: false,
+        }
+    }
+
+    /// # Panics
+    ///
+    /// Panics if `rect` doesn't cover the centre of any cell on the map.
+    pub fn from_world_rect(
+        rect: Rect,
+        tile_size: Vec2,
+        origin: Vec2,
+        height: u32,
+        map_size: MapSize,
+    ) -> Self {
+        let cells = Self::cells_in_world_rect(rect, tile_size, origin, map_size);
+        Self::from_cells(cells, height, map_size)
+    }
+
+    // NOTE:
+    // - a cell is covered when its centre is inside the rect
+    // - origin is the centre of Cell(0, 0), like in Cell::to_world
+    // - the cells under the top left and bottom right corners bound the rows and columns to
+    //   check, since a cell under a point has its centre less than a row and half a tile away
+    // - a corner off the map bounds the search with the edge of the map instead
+    fn cells_in_world_rect(
+        rect: Rect,
+        tile_size: Vec2,
+        origin: Vec2,
+        map_size: MapSize,
+    ) -> Vec<Cell> {
+        let top_left = Vec2::new(rect.min.x, rect.max.y);
+        let bottom_right = Vec2::new(rect.max.x, rect.min.y);
+        let first =
+            Cell::from_world(top_left, tile_size, origin, map_size).unwrap_or(Cell::new(0, 0));
+        let last =
+            Cell::from_world(bottom_right, tile_size, origin, map_size).unwrap_or(Cell::new(
+                map_size.0.x.saturating_sub(1),
+                map_size.0.y.saturating_sub(1),
+            ));
+
+        (first.y..=last.y)
+            .flat_map(|y| (first.x..=last.x).map(move |x| Cell::new(x, y)))
+            .filter(|cell| rect.contains(cell.to_world(tile_size, origin)))
+            .collect()
+    }
+
+    // NOTE:
+    // - turns the item on the diamond lattice, around the pivot, a quarter turn at a time from
+    //   BottomRight to BottomLeft, TopLeft, TopRight and back to BottomRight, so that turning to
+    //   the opposite facing is a half turn, and turning from BottomRight to TopRight is a quarter
+    //   turn the other way
+    // - main_cell is then the bottom-most cell of the turned footprint
+    // - returns false, leaving the item untouched, if the new main_cell would be off the map
+    /// # Panics
+    ///
+    /// Panics if `new_facing` is not one of the diagonal directions.
+    pub fn rotate_around(&mut self, pivot: Cell, new_facing: Direction, map_size: MapSize) -> bool {
+        let n_turns = quarter_turns(self.facing, new_facing);
+        if n_turns == 0 {
+            return true;
+        }
+
+        let pivot_coords = pivot.diamond_coords();
+        let main_offset = self.main_cell.diamond_coords() - pivot_coords;
+        let main_coords = self
+            .shape_coords()
+            .into_iter()
+            .map(|offset| pivot_coords + turn(main_offset + offset, n_turns))
+            .max_by_key(|coords| bottom_most(*coords))
+            .unwrap_or(pivot_coords + turn(main_offset, n_turns));
+
+        match Cell::from_diamond_coords(main_coords, map_size) {
+            Some(main_cell) => {
+                *self = self.rebuilt(main_cell, new_facing, map_size);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // NOTE:
+    // - moves the item one cell towards where it's facing, keeping its dimensions
+    // - returns false, leaving the item untouched, if the new main_cell would be off the map
+    pub fn step_forward(&mut self, map_size: MapSize) -> bool {
+        match self.main_cell.next_cell(self.facing, map_size) {
+            Some(main_cell) => {
+                *self = self.rebuilt(main_cell, self.facing, map_size);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_fully_on_map(&self) -> bool {
+        let n_cells = self
+            .shape
+            .as_ref()
+            .map_or((self.dimensions.x * self.dimensions.y) as usize, Vec::len);
+        self.underneath.len() == n_cells
+    }
+
+    // NOTE:
+    // - moves main_cell to the nearest cell where the whole footprint fits on the map
+    // - returns false, leaving the item untouched, if it already fits or if it can't fit anywhere
+    pub fn clamp_to_map(&mut self, map_size: MapSize) -> bool {
+        if self.is_fully_on_map() {
+            return false;
+        }
+
+        let reach = self.dimensions.x + self.dimensions.y;
+        let rows = self.main_cell.y.saturating_sub(2 * reach)
+            ..=(self.main_cell.y + 2 * reach).min(map_size.0.y.saturating_sub(1));
+        let cols = self.main_cell.x.saturating_sub(reach)
+            ..=(self.main_cell.x + reach).min(map_size.0.x.saturating_sub(1));
+        let main_coords = self.main_cell.diamond_coords();
+
+        let clamped = rows
+            .flat_map(|y| cols.clone().map(move |x| Cell::new(x, y)))
+            .map(|cell| self.rebuilt(cell, self.facing, map_size))
+            .filter(Self::is_fully_on_map)
+            .min_by_key(|item| {
+                let shift = (item.main_cell.diamond_coords() - main_coords).abs();
+                (shift.x + shift.y, item.main_cell)
+            });
+
+        match clamped {
+            Some(clamped) => {
+                *self = clamped;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // NOTE:
+    // - by default behind leaves out the cells underneath the item, this adds them back, so that
+    //   a tall item also hides whatever stands on its own footprint, like a pillar on a stack
+    #[must_use]
+    pub fn with_inclusive_behind(mut self) -> Self {
+        let mut behind = self.underneath.clone();
+        behind.append(&mut self.behind);
+        behind.sort();
+        behind.dedup();
+        self.influence = Self::influence(&self.underneath, &behind);
+        self.behind = behind;
+        self.inclusive_behind = true;
+        self
+    }
+
+    // NOTE:
+    // - footprints are clipped by the map they are built with, so items built with different
+    //   map sizes, like items in different chunks, are rebuilt on a shared map before comparing
+    // - items are rebuilt from their main_cell, dimensions and facing, like with new
+    pub fn compare_in_map(&self, other: &Self, map_size: MapSize) -> Option<Ordering> {
+        let rebuild = |cells: &Self| cells.rebuilt(cells.main_cell, cells.facing, map_size);
+        rebuild(self).partial_cmp(&rebuild(other))
+    }
+
+    // NOTE:
+    // - builds the item again at main_cell and facing, keeping its dimensions, shape, transparency,
+    //   behind directions and inclusive behind
+    // - a shape is turned with the facing, and main_cell is then its bottom-most cell
+    pub(crate) fn rebuilt(&self, main_cell: Cell, facing: Direction, map_size: MapSize) -> Self {
+        let default = BehindDirections::default();
+        let behind_directions = self.behind_directions.as_ref().unwrap_or(&default);
+        let rebuilt = match &self.shape {
+            None => Self::new_with_behind_directions(
+                main_cell,
+                self.dimensions,
+                facing,
+                map_size,
+                behind_directions,
+            ),
+            Some(shape) => {
+                let n_turns = quarter_turns(self.facing, facing);
+                let turned = shape
+                    .iter()
+                    .map(|offset| turn(*offset, n_turns))
+                    .collect::<Vec<IVec2>>();
+                let bottom = turned
+                    .iter()
+                    .copied()
+                    .max_by_key(|coords| bottom_most(*coords))
+                    .unwrap_or(IVec2::ZERO);
+                let shape = turned.into_iter().map(|offset| offset - bottom).collect();
+                Self::from_shape(
+                    main_cell,
+                    shape,
+                    self.dimensions.z,
+                    facing,
+                    map_size,
+                    behind_directions,
+                )
+            }
+        };
+        let rebuilt = rebuilt.with_transparency(self.transparency);
+        if self.inclusive_behind {
+            rebuilt.with_inclusive_behind()
+        } else {
+            rebuilt
+        }
+    }
+
+    // NOTE:
+    // - the diamond coords of every cell of the footprint relative to main_cell, including the
+    //   ones off the map
+    fn shape_coords(&self) -> Vec<IVec2> {
+        if let Some(shape) = &self.shape {
+            return shape.clone();
+        }
+        let (col_dir, row_dir) = Direction::footprint_expansion(self.facing);
+        let dims = self.dimensions.as_ivec3();
+        (0..dims.y)
+            .flat_map(|row| {
+                (0..dims.x)
+                    .map(move |col| col_dir.diamond_step() * col + row_dir.diamond_step() * row)
+            })
+            .collect()
+    }
+
+    // NOTE:
+    // - grows the item and continues the search for the cells behind it from where it stopped,
+    //   which gives the same cells as rebuilding it at the new height
+    // - the footprint of an inclusive item is already in behind, and the search never adds it
+    //   again, so the item stays inclusive
+    // - map_size must be the one the item was built with
+    pub fn extend_behind(&mut self, additional_height: u32, map_size: MapSize) {
+        let underneath = &self.underneath;
+        let mut frontier = underneath
+            .iter()
+            .filter_map(|cell| {
+                (0..self.dimensions.z).try_fold(*cell, |cell, _| {
+                    cell.next_cell(Direction::Top, map_size)
+                        .filter(|top_cell| !underneath.contains(top_cell))
+                })
+            })
+            .collect::<Vec<Cell>>();
+        frontier.sort();
+        frontier.dedup();
+
+        let default = BehindDirections::default();
+        let behind_directions = self.behind_directions.as_ref().unwrap_or(&default);
+        let new_steps = Self::behind_steps_from(
+            underneath,
+            frontier,
+            &self.behind,
+            additional_height,
+            map_size,
+            &behind_directions.0,
+            Direction::Top,
+        );
+        let new_cells = new_steps.into_iter().flatten().collect::<Vec<Cell>>();
+        self.influence.extend(new_cells.iter().copied());
+        self.behind.extend(new_cells);
+        self.behind.sort();
+        self.dimensions.z += additional_height;
+        self.front = Self::front(underneath, self.dimensions.z, map_size);
+    }
+
+    #[must_use]
+    pub fn with_transparency(mut self, transparency: Transparency) -> Self {
+        self.transparency = transparency;
+        self
+    }
+
+    // NOTE:
+    // - other is behind self when it stands on any cell behind self
+    // - a Cutout item skips the cells that are only touching the edges of its footprint
+    pub fn occludes(&self, other: &Self) -> bool {
+        self.hidden_cells(other).next().is_some()
+    }
+
+    // NOTE:
+    // - the cells of other's footprint that stand behind self, which make self occlude other
+    fn hidden_cells<'c>(&'c self, other: &'c Self) -> impl Iterator<Item = Cell> + 'c {
+        let footprint = Footprint::new(&other.underneath);
+        self.behind
+            .iter()
+            .filter(|behind| match self.transparency {
+                Transparency::Opaque => true,
+                Transparency::Cutout => !self.underneath.iter().any(|under| {
+                    matches!(
+                        under.adjacent_direction(**behind),
+                        Some(Direction::TopLeft | Direction::TopRight)
+                    )
+                }),
+            })
+            .filter(move |behind| footprint.contains(behind))
+            .copied()
+    }
+
+    // NOTE:
+    // - the reason behind what partial_cmp returns for the two items, with the cells involved
+    pub fn explain_cmp(&self, other: &Self) -> CmpExplanation {
+        if self == other {
+            return CmpExplanation::Equal;
+        }
+        let hidden_by_self = self.hidden_cells(other).collect::<Vec<Cell>>();
+        let hidden_by_other = other.hidden_cells(self).collect::<Vec<Cell>>();
+        match (hidden_by_self.is_empty(), hidden_by_other.is_empty()) {
+            (true, true) => CmpExplanation::Neither,
+            (false, true) => CmpExplanation::InFront {
+                hidden: hidden_by_self,
+            },
+            (true, false) => CmpExplanation::Behind {
+                hidden: hidden_by_other,
+            },
+            (false, false) => CmpExplanation::Both {
+                hidden_by_self,
+                hidden_by_other,
+            },
+        }
+    }
+
+    // NOTE:
+    // - co-located items, like a stack, are Equal, which keeps partial_cmp consistent with eq
+    /// # Errors
+    ///
+    /// Returns an error if the two items are both in front and behind each other.
+    pub fn try_cmp(&self, other: &Self) -> Result<Option<Ordering>, OcclusionCycleError> {
+        if self == other {
+            return Ok(Some(Ordering::Equal));
+        }
+
+        let is_other_behind_self = self.occludes(other);
+        let is_self_behind_other = other.occludes(self);
+
+        match (is_other_behind_self, is_self_behind_other) {
+            (true, true) => Err(OcclusionCycleError {
+                first: self.main_cell,
+                second: other.main_cell,
+            }),
+            (true, false) => Ok(Some(Ordering::Greater)),
+            (false, true) => Ok(Some(Ordering::Less)),
+            (false, false) => Ok(None),
         }
     }
 
@@ -32,6 +587,170 @@ impl CurrentCells {
         self.dimensions.x * self.dimensions.y * self.dimensions.z
     }
 
+    // NOTE:
+    // - a copy of the footprint, for callers keeping their own spatial structures
+    pub fn underneath_set(&self) -> HashSet<Cell> {
+        self.underneath.iter().copied().collect()
+    }
+
+    // NOTE:
+    // - two items can only be in front of one another if their masks share a cell
+    pub fn influence_mask(&self) -> &HashSet<Cell> {
+        &self.influence
+    }
+
+    pub fn could_interact(&self, other: &Self) -> bool {
+        !self.influence_mask().is_disjoint(other.influence_mask())
+    }
+
+    // NOTE:
+    // - every cell the item has an effect on, tagged with the reason, for editor overlays
+    // - sorted by cell, so the tags of the same cell are next to each other
+    pub fn influence_report(&self) -> Vec<(Cell, InfluenceKind)> {
+        let mut report = self
+            .underneath
+            .iter()
+            .map(|cell| (*cell, InfluenceKind::Footprint))
+            .chain(
+                self.behind
+                    .iter()
+                    .map(|cell| (*cell, InfluenceKind::Behind)),
+            )
+            .collect::<Vec<(Cell, InfluenceKind)>>();
+        report.sort();
+        report
+    }
+
+    // NOTE:
+    // - like influence_report, with the cells of shadow_cells as well
+    pub fn influence_report_with_shadow(&self, map_size: MapSize) -> Vec<(Cell, InfluenceKind)> {
+        let mut report = self.influence_report();
+        report.extend(
+            self.shadow_cells(map_size)
+                .into_iter()
+                .map(|cell| (cell, InfluenceKind::Shadow)),
+        );
+        report.sort();
+        report
+    }
+
+    // NOTE:
+    // - every cell of the footprint at every level of the item's height, from the ground up
+    pub fn volume_cells(&self) -> impl Iterator<Item = (Cell, u32)> + '_ {
+        (0..self.dimensions.z)
+            .flat_map(move |level| self.underneath.iter().map(move |cell| (*cell, level)))
+    }
+
+    // NOTE:
+    // - the cells the item's ground shadow falls on, for drawing blob shadows
+    // - the same cells as front, found again on the given map
+    pub fn shadow_cells(&self, map_size: MapSize) -> Vec<Cell> {
+        Self::front(&self.underneath, self.dimensions.z, map_size)
+    }
+
+    // NOTE:
+    // - the cells of this item's footprint that are hidden by an occluder in front of it
+    pub fn overlapping_cells<'c>(&'c self, occluder: &'c Self) -> impl Iterator<Item = Cell> + 'c {
+        self.underneath
+            .iter()
+            .filter(|cell| occluder.behind.contains(cell))
+            .copied()
+    }
+
+    pub fn overlaps(&self, occluder: &Self) -> bool {
+        self.overlapping_cells(occluder).next().is_some()
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    pub fn overlap_ratio(&self, occluders: &[CurrentCells]) -> f32 {
+        if self.underneath.is_empty() {
+            return 0.;
+        }
+
+        let n_covered = self
+            .underneath
+            .iter()
+            .filter(|cell| {
+                occluders
+                    .iter()
+                    .any(|occluder| occluder.behind.contains(cell))
+            })
+            .count();
+        n_covered as f32 / self.underneath.len() as f32
+    }
+
+    pub fn underneath_world(
+        &self,
+        tile_size: Vec2,
+        origin: Vec2,
+    ) -> impl Iterator<Item = Vec2> + '_ {
+        self.underneath
+            .iter()
+            .map(move |cell| cell.to_world(tile_size, origin))
+    }
+
+    pub fn behind_world(&self, tile_size: Vec2, origin: Vec2) -> impl Iterator<Item = Vec2> + '_ {
+        self.behind
+            .iter()
+            .map(move |cell| cell.to_world(tile_size, origin))
+    }
+
+    // NOTE:
+    // - the corners of each cell are on a lattice of half a tile wide and half a tile high
+    // - every cell adds its four edges going anticlockwise, and edges shared by two cells cancel
+    //   out, so that only the outer ones are left to chain into a loop
+    // - vertices in the middle of a straight side are dropped
+    // - origin is the centre of Cell(0, 0), like in Cell::to_world
+    pub fn footprint_outline_world(&self, tile_size: Vec2, origin: Vec2) -> Vec<Vec2> {
+        let mut edges: Vec<(IVec2, IVec2)> = Vec::new();
+        for cell in &self.underneath {
+            let cell = IVec2::from(*cell);
+            let centre = IVec2::new(2 * cell.x + cell.y % 2, -cell.y);
+            let corners = [IVec2::X, IVec2::Y, IVec2::NEG_X, IVec2::NEG_Y].map(|c| centre + c);
+            for (index, from) in corners.iter().enumerate() {
+                let to = corners[(index + 1) % corners.len()];
+                match edges.iter().position(|edge| *edge == (to, *from)) {
+                    Some(shared) => {
+                        edges.swap_remove(shared);
+                    }
+                    None => edges.push((*from, to)),
+                }
+            }
+        }
+
+        let Some(start) = edges
+            .iter()
+            .map(|(from, _)| *from)
+            .min_by_key(|v| (v.x, v.y))
+        else {
+            return Vec::new();
+        };
+        let mut outline = vec![start];
+        let mut current = start;
+        while let Some(index) = edges.iter().position(|(from, _)| *from == current) {
+            let (_, to) = edges.swap_remove(index);
+            if to == start {
+                break;
+            }
+            outline.push(to);
+            current = to;
+        }
+
+        let n_vertices = outline.len();
+        (0..n_vertices)
+            .filter(|index| {
+                let previous = outline[(index + n_vertices - 1) % n_vertices];
+                let next = outline[(index + 1) % n_vertices];
+                outline[*index] - previous != next - outline[*index]
+            })
+            .map(|index| origin + outline[index].as_vec2() * tile_size / 2.)
+            .collect()
+    }
+
+    fn influence(underneath: &[Cell], behind: &[Cell]) -> HashSet<Cell> {
+        underneath.iter().chain(behind).copied().collect()
+    }
+
     // NOTE:
     // - main_cell is always the bottom-most cell
     // if facing BottomRight:
@@ -40,86 +759,205 @@ impl CurrentCells {
     // if facing BottomLeft:
     // - dimensions.x expands towards TopLeft
     // - dimensions.y expands towards TopRight
+    // Items facing TopLeft expand like BottomRight, and TopRight like BottomLeft
     // Items can't face other directions
-    fn underneath(main_cell: Cell, dims: UVec3, facing: Direction, map_size: UVec2) -> Vec<Cell> {
+    // - cells that would fall off the map are left out, so near the border fewer than
+    //   dims.x * dims.y cells are returned
+    pub fn underneath(
+        main_cell: Cell,
+        dims: UVec3,
+        facing: Direction,
+        map_size: MapSize,
+    ) -> Vec<Cell> {
         if dims.x * dims.y == 1 {
             return vec![main_cell];
         }
 
-        let (col_dir, row_dir) = match facing {
-            Direction::BottomRight => (Direction::TopRight, Direction::TopLeft),
-            Direction::BottomLeft => (Direction::TopLeft, Direction::TopRight),
-            _ => panic!("Items can only face BottomRight or BottomLeft,\n{facing:?} is not valid"),
-        };
-        let mut underneath_cells = Vec::new();
-        let mut current_cell = Some(main_cell);
+        let (col_dir, row_dir) = Direction::footprint_expansion(facing);
+        let mut underneath_cells = Vec::with_capacity((dims.x * dims.y) as usize);
         let mut current_row_cell = Some(main_cell);
 
         for _row in 0..dims.y {
-            for col in 0..dims.x {
-                let is_at_start_of_col = col == 0;
-                if is_at_start_of_col {
-                    current_cell = current_row_cell;
-                }
+            let Some(row_cell) = current_row_cell else {
+                break;
+            };
+            let mut current_cell = Some(row_cell);
+            for _col in 0..dims.x {
+                let Some(cell) = current_cell else {
+                    break;
+                };
+                underneath_cells.push(cell);
+                current_cell = cell.next_cell(col_dir, map_size);
+            }
+            current_row_cell = row_cell.next_cell(row_dir, map_size);
+        }
+        underneath_cells
+    }
 
-                underneath_cells.push(current_cell);
+    // NOTE:
+    // - cells are discovered breadth first, checking TopLeft, TopRight and Top from each cell
+    // - the result is sorted with the render order of Cell, so that two equal sets of cells
+    //   are also equal as Vecs, regardless of the order in which they were discovered
+    pub fn behind(underneath: &[Cell], height: u32, map_size: MapSize) -> Vec<Cell> {
+        Self::behind_towards(underneath, height, map_size, &BehindDirections::default())
+    }
 
-                let has_found_all_cells = underneath_cells.len() == (dims.x * dims.y) as usize;
-                if has_found_all_cells {
-                    return Self::flatten(underneath_cells);
-                }
+    // NOTE:
+    // - the mirror of behind: spreads towards BottomLeft, BottomRight and Bottom, one step for each
+    //   unit of height, and never includes the footprint itself
+    // - items standing on these cells are in front of this item, if they are at least as tall
+    pub fn front(underneath: &[Cell], height: u32, map_size: MapSize) -> Vec<Cell> {
+        let mut front_cells = Self::behind_steps_from(
+            underneath,
+            underneath.to_vec(),
+            &[],
+            height,
+            map_size,
+            &Direction::behind_set().map(Direction::opposite),
+            Direction::Bottom,
+        )
+        .into_iter()
+        .flatten()
+        .collect::<Vec<Cell>>();
+        front_cells.sort();
+        front_cells
+    }
 
-                let is_at_end_of_col = col == dims.x - 1;
-                if is_at_end_of_col {
-                    current_row_cell =
-                        current_row_cell.and_then(|cell| cell.next_cell(row_dir, map_size));
-                } else {
-                    current_cell = current_cell.and_then(|cell| cell.next_cell(col_dir, map_size));
-                }
-            }
-        }
-        Self::flatten(underneath_cells)
+    fn behind_towards(
+        underneath: &[Cell],
+        height: u32,
+        map_size: MapSize,
+        directions: &BehindDirections,
+    ) -> Vec<Cell> {
+        let mut behind_cells = Self::behind_steps_from(
+            underneath,
+            underneath.to_vec(),
+            &[],
+            height,
+            map_size,
+            &directions.0,
+            Direction::Top,
+        )
+        .into_iter()
+        .flatten()
+        .collect::<Vec<Cell>>();
+        behind_cells.sort();
+        behind_cells
     }
 
-    fn flatten(underneath_cells: Vec<Option<Cell>>) -> Vec<Cell> {
-        underneath_cells
-            .into_iter()
-            .flatten()
-            .collect::<Vec<Cell>>()
+    // NOTE:
+    // - returns the cells discovered at each step of the breadth first search, in the order they
+    //   were discovered
+    pub(crate) fn behind_steps(
+        underneath: &[Cell],
+        height: u32,
+        map_size: MapSize,
+    ) -> Vec<Vec<Cell>> {
+        Self::behind_steps_from(
+            underneath,
+            underneath.to_vec(),
+            &[],
+            height,
+            map_size,
+            &BehindDirections::default().0,
+            Direction::Top,
+        )
     }
 
-    fn behind(underneath: &[Cell], height: u32, map_size: UVec2) -> Vec<Cell> {
-        let mut behind_cells = Vec::new();
-        let mut currently_checking = underneath.iter().map(Clone::clone).collect::<Vec<Cell>>();
+    // NOTE:
+    // - continues the breadth first search from the cells in currently_checking, skipping the
+    //   cells already found before
+    // - each step climbs one unit of height towards climb, which is Top for behind and Bottom for
+    //   front
+    fn behind_steps_from(
+        underneath: &[Cell],
+        mut currently_checking: Vec<Cell>,
+        already_behind: &[Cell],
+        height: u32,
+        map_size: MapSize,
+        directions: &[Direction],
+        climb: Direction,
+    ) -> Vec<Vec<Cell>> {
+        let mut steps: Vec<Vec<Cell>> = Vec::new();
         for _step in 0..height {
+            let mut step_cells: Vec<Cell> = Vec::new();
             let mut next_cells_to_check: Vec<Cell> = Vec::new();
+            let is_new = |cell: &Cell, step_cells: &[Cell]| {
+                !underneath.contains(cell)
+                    && !already_behind.contains(cell)
+                    && !step_cells.contains(cell)
+                    && !steps.iter().any(|step| step.contains(cell))
+            };
             for check in &currently_checking {
-                if let Some(top_left_cell) = check.next_cell(Direction::TopLeft, map_size) {
-                    let is_underneath = underneath.contains(&top_left_cell);
-                    if !behind_cells.contains(&top_left_cell) && !is_underneath {
-                        behind_cells.push(top_left_cell);
-                    }
-                }
-                if let Some(top_right_cell) = check.next_cell(Direction::TopRight, map_size) {
-                    let is_underneath = underneath.contains(&top_right_cell);
-                    if !behind_cells.contains(&top_right_cell) && !is_underneath {
-                        behind_cells.push(top_right_cell);
+                for direction in directions {
+                    if let Some(next_cell) = check.next_cell(*direction, map_size) {
+                        if is_new(&next_cell, &step_cells) {
+                            step_cells.push(next_cell);
+                        }
                     }
                 }
-                if let Some(top_cell) = check.next_cell(Direction::Top, map_size) {
-                    let is_underneath = underneath.contains(&top_cell);
-                    if !behind_cells.contains(&top_cell) && !is_underneath {
-                        behind_cells.push(top_cell);
-                    }
-                    if !next_cells_to_check.contains(&top_cell) && !is_underneath {
+                if let Some(top_cell) = check.next_cell(climb, map_size) {
+                    if !next_cells_to_check.contains(&top_cell) && !underneath.contains(&top_cell) {
                         next_cells_to_check.push(top_cell);
                     }
                 }
             }
+            steps.push(step_cells);
             currently_checking = next_cells_to_check;
         }
-        behind_cells
+        steps
+    }
+}
+
+// NOTE:
+// - keeps the first of each group of equal items, like two items spawned on the same footprint by
+//   a scene loader, and returns the entities of the others so they can be despawned
+pub fn dedup_footprints(items: &mut Vec<(Entity, CurrentCells)>) -> Vec<Entity> {
+    let mut kept: Vec<(Entity, CurrentCells)> = Vec::with_capacity(items.len());
+    let mut removed = Vec::new();
+    for (entity, cells) in items.drain(..) {
+        if kept.iter().any(|(_, kept_cells)| *kept_cells == cells) {
+            removed.push(entity);
+        } else {
+            kept.push((entity, cells));
+        }
     }
+    *items = kept;
+    removed
+}
+
+// NOTE:
+// - the quarter turns on the diamond lattice from one facing to the other, going from BottomRight
+//   to BottomLeft, TopLeft, TopRight and back to BottomRight
+fn quarter_turns(from: Direction, to: Direction) -> usize {
+    const TURNS: [Direction; 4] = [
+        Direction::BottomRight,
+        Direction::BottomLeft,
+        Direction::TopLeft,
+        Direction::TopRight,
+    ];
+    let turn_index = |facing: Direction| {
+        TURNS
+            .iter()
+            .position(|turn| *turn == facing)
+            .unwrap_or_else(|| {
+                panic!(
+                    "Items can only face BottomRight or BottomLeft, or TopLeft or TopRight,\n{facing:?} is not valid"
+                )
+            })
+    };
+    (turn_index(to) + 4 - turn_index(from)) % 4
+}
+
+fn turn(coords: IVec2, n_turns: usize) -> IVec2 {
+    (0..n_turns).fold(coords, |coords, _| IVec2::new(coords.y, -coords.x))
+}
+
+// NOTE:
+// - orders diamond coords like Cell does, by row and then by column, so that the largest is the
+//   bottom-most cell
+fn bottom_most(coords: IVec2) -> (i32, i32) {
+    (coords.y - coords.x, coords.x + coords.y)
 }
 
 impl PartialEq for CurrentCells {
@@ -127,35 +965,90 @@ impl PartialEq for CurrentCells {
         self.main_cell == other.main_cell
             && self.dimensions == other.dimensions
             && self.facing == other.facing
+            && self.shape == other.shape
     }
 }
 
 impl Eq for CurrentCells {}
 
 impl PartialOrd for CurrentCells {
+    // NOTE:
+    // - panics on items that occlude each other, use try_cmp to handle those instead
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        let is_other_behind_self = self
-            .behind
-            .iter()
-            .any(|self_behind| other.underneath.contains(self_behind));
+        self.try_cmp(other)
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+}
 
-        let is_self_behind_other = other
-            .behind
-            .iter()
-            .any(|other_behind| self.underneath.contains(other_behind));
+// NOTE:
+// - CurrentCells are equal when their main_cell, dimensions, facing and shape are, as everything
+//   else is derived from those
+// - ByContent also compares the derived fields, to check that a loaded scene reproduced the
+//   exact state that was computed
+#[derive(Clone, Debug)]
+pub struct ByContent(pub CurrentCells);
 
-        match (is_other_behind_self, is_self_behind_other) {
-            (true, true) => panic!("Items cannot be both in front and behind each other"),
-            (true, false) => Some(Ordering::Greater),
-            (false, true) => Some(Ordering::Less),
-            (false, false) => None,
-        }
+impl PartialEq for ByContent {
+    fn eq(&self, other: &Self) -> bool {
+        let (this, other) = (&self.0, &other.0);
+        this == other
+            && this.underneath == other.underneath
+            && this.behind == other.behind
+            && this.front == other.front
+            && this.transparency == other.transparency
     }
 }
 
+impl Eq for ByContent {}
+
 #[cfg(test)]
 mod test_cells_underneath {
     use super::*;
+    use crate::cells::cell::debug_grid;
+
+    #[test]
+    fn underneath_set_contains_footprint() {
+        let cells = CurrentCells::new(
+            Cell::new(1, 3),
+            UVec3::new(2, 2, 1),
+            Direction::BottomRight,
+            MapSize::new(3, 6),
+        );
+
+        let set = cells.underneath_set();
+
+        assert_eq!(set.len(), cells.underneath.len());
+        assert!(cells.underneath.iter().all(|cell| set.contains(cell)));
+        assert!(!set.contains(&Cell::new(0, 0)));
+
+        let other = CurrentCells::new(
+            Cell::new(1, 4),
+            UVec3::new(2, 1, 1),
+            Direction::BottomRight,
+            MapSize::new(3, 6),
+        );
+        let mut occupied = set;
+        occupied.extend(other.underneath_set());
+        let mut shared = cells
+            .underneath
+            .iter()
+            .filter(|cell| other.underneath.contains(cell))
+            .copied()
+            .collect::<Vec<Cell>>();
+        shared.sort();
+        let mut overlap = cells
+            .underneath_set()
+            .intersection(&other.underneath_set())
+            .copied()
+            .collect::<Vec<Cell>>();
+        overlap.sort();
+        assert!(!shared.is_empty());
+        assert_eq!(overlap, shared);
+        assert_eq!(
+            occupied.len(),
+            cells.underneath.len() + other.underneath.len() - shared.len()
+        );
+    }
 
     /*
       |   |   |
@@ -178,7 +1071,7 @@ mod test_cells_underneath {
             main_cell,
             UVec3::new(1, 1, 1),
             Direction::BottomRight,
-            UVec2::new(3, 6),
+            MapSize::new(3, 6),
         );
 
         assert_eq!(actual, expected);
@@ -194,7 +1087,7 @@ mod test_cells_underneath {
             main_cell,
             UVec3::new(1, 1, 1),
             Direction::BottomRight,
-            UVec2::new(3, 6),
+            MapSize::new(3, 6),
         );
 
         assert_eq!(actual, expected);
@@ -210,7 +1103,7 @@ mod test_cells_underneath {
             main_cell,
             UVec3::new(1, 1, 1),
             Direction::BottomRight,
-            UVec2::new(3, 6),
+            MapSize::new(3, 6),
         );
 
         assert_eq!(actual, expected);
@@ -226,10 +1119,15 @@ mod test_cells_underneath {
             main_cell,
             UVec3::new(2, 2, 1),
             Direction::BottomRight,
-            UVec2::new(3, 6),
+            MapSize::new(3, 6),
         );
 
-        assert_eq!(actual, expected);
+        assert_eq!(
+            actual,
+            expected,
+            "\n{}",
+            debug_grid(MapSize::new(3, 6), &actual)
+        );
     }
 
     #[test]
@@ -242,7 +1140,7 @@ mod test_cells_underneath {
             main_cell,
             UVec3::new(1, 2, 1),
             Direction::BottomRight,
-            UVec2::new(3, 6),
+            MapSize::new(3, 6),
         );
 
         assert_eq!(actual, expected);
@@ -258,7 +1156,7 @@ mod test_cells_underneath {
             main_cell,
             UVec3::new(1, 2, 1),
             Direction::BottomLeft,
-            UVec2::new(3, 6),
+            MapSize::new(3, 6),
         );
 
         assert_eq!(actual, expected);
@@ -274,7 +1172,7 @@ mod test_cells_underneath {
             main_cell,
             UVec3::new(2, 1, 1),
             Direction::BottomRight,
-            UVec2::new(3, 6),
+            MapSize::new(3, 6),
         );
 
         assert_eq!(actual, expected);
@@ -290,7 +1188,7 @@ mod test_cells_underneath {
             main_cell,
             UVec3::new(2, 1, 1),
             Direction::BottomLeft,
-            UVec2::new(3, 6),
+            MapSize::new(3, 6),
         );
 
         assert_eq!(actual, expected);
@@ -313,7 +1211,7 @@ mod test_cells_underneath {
             main_cell,
             UVec3::new(2, 3, 1),
             Direction::BottomRight,
-            UVec2::new(3, 6),
+            MapSize::new(3, 6),
         );
 
         assert_eq!(actual, expected);
@@ -336,7 +1234,7 @@ mod test_cells_underneath {
             main_cell,
             UVec3::new(2, 3, 1),
             Direction::BottomLeft,
-            UVec2::new(3, 6),
+            MapSize::new(3, 6),
         );
 
         assert_eq!(actual, expected);
@@ -359,7 +1257,7 @@ mod test_cells_underneath {
             main_cell,
             UVec3::new(3, 2, 1),
             Direction::BottomRight,
-            UVec2::new(3, 6),
+            MapSize::new(3, 6),
         );
 
         assert_eq!(actual, expected);
@@ -382,41 +1280,260 @@ mod test_cells_underneath {
             main_cell,
             UVec3::new(3, 2, 1),
             Direction::BottomLeft,
-            UVec2::new(3, 6),
+            MapSize::new(3, 6),
         );
 
         assert_eq!(actual, expected);
     }
 
     #[test]
-    #[should_panic]
-    fn test_cells_underneath_invalid_facing_direction() {
-        let main_cell = Cell::new(1, 1);
+    fn test_cells_underneath_3x2_facing_top_left() {
+        let main_cell = Cell::new(1, 4);
 
-        CurrentCells::underneath(
+        let expected = vec![
             main_cell,
-            UVec3::new(1, 2, 1),
-            Direction::Top,
-            UVec2::new(3, 6),
-        );
-    }
-
-    #[test]
-    fn test_cells_underneath_too_close_to_map_border() {
-        let main_cell = Cell::new(1, 2);
-        let dims = UVec3::new(3, 2, 1);
+            Cell::new(1, 3),
+            Cell::new(2, 2),
+            Cell::new(0, 3),
+            Cell::new(1, 2),
+            Cell::new(1, 1),
+        ];
+
+        let actual = CurrentCells::underneath(
+            main_cell,
+            UVec3::new(3, 2, 1),
+            Direction::TopLeft,
+            MapSize::new(3, 6),
+        );
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_cells_underneath_3x2_facing_top_right() {
+        let main_cell = Cell::new(1, 3);
+
+        let expected = vec![
+            main_cell,
+            Cell::new(1, 2),
+            Cell::new(0, 1),
+            Cell::new(2, 2),
+            Cell::new(1, 1),
+            Cell::new(1, 0),
+        ];
+
+        let actual = CurrentCells::underneath(
+            main_cell,
+            UVec3::new(3, 2, 1),
+            Direction::TopRight,
+            MapSize::new(3, 6),
+        );
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_behind_facing_top_left_and_top_right() {
+        let map_size = MapSize::new(3, 6);
+        for (main_cell, facing, mirrored) in [
+            (Cell::new(1, 4), Direction::TopLeft, Direction::BottomRight),
+            (Cell::new(1, 3), Direction::TopRight, Direction::BottomLeft),
+        ] {
+            let cells = CurrentCells::new(main_cell, UVec3::new(3, 2, 2), facing, map_size);
+            let expected = CurrentCells::new(main_cell, UVec3::new(3, 2, 2), mirrored, map_size);
+            assert!(!cells.behind.is_empty());
+            assert_eq!(cells.behind, expected.behind);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Items can only face BottomRight or BottomLeft")]
+    fn test_cells_underneath_invalid_facing_direction() {
+        let main_cell = Cell::new(1, 1);
+
+        CurrentCells::underneath(
+            main_cell,
+            UVec3::new(1, 2, 1),
+            Direction::Top,
+            MapSize::new(3, 6),
+        );
+    }
+
+    #[test]
+    fn test_cells_underneath_too_close_to_map_border() {
+        let main_cell = Cell::new(1, 2);
+        let dims = UVec3::new(3, 2, 1);
 
         let actual =
-            CurrentCells::underneath(main_cell, dims, Direction::BottomLeft, UVec2::new(3, 6));
+            CurrentCells::underneath(main_cell, dims, Direction::BottomLeft, MapSize::new(3, 6));
 
         assert_ne!(actual.len(), (dims.x * dims.y) as usize);
     }
+
+    #[test]
+    fn public_helpers_match_new() {
+        let map_size = MapSize::new(3, 7);
+        for (main_cell, dims) in [
+            (Cell::new(1, 5), UVec3::new(2, 2, 2)),
+            (Cell::new(2, 4), UVec3::new(3, 1, 1)),
+        ] {
+            let cells = CurrentCells::new(main_cell, dims, Direction::BottomRight, map_size);
+            let underneath =
+                CurrentCells::underneath(main_cell, dims, Direction::BottomRight, map_size);
+            let behind = CurrentCells::behind(&underneath, dims.z, map_size);
+            assert_eq!(underneath, cells.underneath);
+            assert_eq!(behind, cells.behind);
+        }
+        let clamped = CurrentCells::underneath(
+            Cell::new(2, 4),
+            UVec3::new(3, 1, 1),
+            Direction::BottomRight,
+            map_size,
+        );
+        assert!(clamped.len() < 3);
+    }
 }
 
 #[cfg(test)]
 mod test_behind_cells {
     use super::*;
 
+    #[test]
+    fn test_behind_inclusive_1x1x3_pillar() {
+        let map_size = MapSize::new(3, 7);
+        let exclusive = CurrentCells::new(
+            Cell::new(1, 6),
+            UVec3::new(1, 1, 3),
+            Direction::BottomRight,
+            map_size,
+        );
+        let inclusive = exclusive.clone().with_inclusive_behind();
+
+        assert!(!exclusive.behind.contains(&Cell::new(1, 6)));
+        assert!(inclusive.behind.contains(&Cell::new(1, 6)));
+        assert_eq!(inclusive.behind.len(), exclusive.behind.len() + 1);
+        assert!(exclusive
+            .behind
+            .iter()
+            .all(|cell| inclusive.behind.contains(cell)));
+    }
+
+    #[test]
+    fn test_inclusive_pillar_stays_inclusive() {
+        let map_size = MapSize::new(4, 12);
+        let build = |main_cell, height, facing| {
+            CurrentCells::new(main_cell, UVec3::new(1, 1, height), facing, map_size)
+                .with_inclusive_behind()
+        };
+        let pillar = build(Cell::new(1, 6), 3, Direction::BottomRight);
+
+        let mut stepped = pillar.clone();
+        assert!(stepped.step_forward(map_size));
+        assert!(stepped.inclusive_behind);
+        assert!(stepped.behind.contains(&stepped.main_cell));
+        let expected = build(stepped.main_cell, 3, Direction::BottomRight);
+        assert_eq!(stepped.behind, expected.behind);
+
+        let mut turned = pillar.clone();
+        assert!(turned.rotate_around(pillar.main_cell, Direction::BottomLeft, map_size));
+        assert!(turned.behind.contains(&turned.main_cell));
+        let expected = build(turned.main_cell, 3, Direction::BottomLeft);
+        assert_eq!(turned.behind, expected.behind);
+
+        let mut extended = pillar.clone();
+        extended.extend_behind(1, map_size);
+        assert!(extended.behind.contains(&extended.main_cell));
+        let expected = build(pillar.main_cell, 4, Direction::BottomRight);
+        assert_eq!(extended.behind, expected.behind);
+    }
+
+    #[test]
+    fn test_behind_only_top_is_a_thin_column() {
+        let map_size = MapSize::new(3, 7);
+        let build = |directions: &BehindDirections| {
+            CurrentCells::new_with_behind_directions(
+                Cell::new(1, 6),
+                UVec3::new(1, 1, 2),
+                Direction::BottomRight,
+                map_size,
+                directions,
+            )
+        };
+
+        let default = build(&BehindDirections::default());
+        let only_top = build(&BehindDirections(vec![Direction::Top]));
+
+        assert_eq!(only_top.behind, vec![Cell::new(1, 2), Cell::new(1, 4)]);
+        assert!(only_top
+            .behind
+            .iter()
+            .all(|cell| default.behind.contains(cell)));
+        assert!(only_top.behind.len() < default.behind.len());
+    }
+
+    #[test]
+    fn test_rebuilds_keep_the_behind_directions() {
+        let map_size = MapSize::new(4, 9);
+        let only_top = BehindDirections(vec![Direction::Top]);
+        let build = |main_cell: Cell, height: u32, facing: Direction| {
+            CurrentCells::new_with_behind_directions(
+                main_cell,
+                UVec3::new(2, 1, height),
+                facing,
+                map_size,
+                &only_top,
+            )
+        };
+        let item = build(Cell::new(1, 6), 2, Direction::BottomRight);
+
+        let mut stepped = item.clone();
+        assert!(stepped.step_forward(map_size));
+        let expected = build(stepped.main_cell, 2, Direction::BottomRight);
+        assert_eq!(stepped.behind, expected.behind);
+
+        let mut rotated = item.clone();
+        assert!(rotated.rotate_around(Cell::new(1, 6), Direction::BottomLeft, map_size));
+        let expected = build(rotated.main_cell, 2, Direction::BottomLeft);
+        assert_eq!(rotated.behind, expected.behind);
+
+        let mut grown = item.clone();
+        grown.extend_behind(1, map_size);
+        assert_eq!(
+            grown.behind,
+            build(Cell::new(1, 6), 3, Direction::BottomRight).behind
+        );
+        assert_ne!(
+            grown.behind,
+            CurrentCells::new(
+                Cell::new(1, 6),
+                UVec3::new(2, 1, 3),
+                Direction::BottomRight,
+                map_size
+            )
+            .behind
+        );
+    }
+
+    #[test]
+    fn test_extend_behind_matches_rebuild() {
+        let map_size = MapSize::new(3, 7);
+        for (main_cell, dims) in [
+            (Cell::new(1, 6), UVec3::new(1, 1, 1)),
+            (Cell::new(1, 6), UVec3::new(2, 2, 1)),
+        ] {
+            let mut extended = CurrentCells::new(main_cell, dims, Direction::BottomRight, map_size);
+            extended.extend_behind(2, map_size);
+            let rebuilt = CurrentCells::new(
+                main_cell,
+                UVec3::new(dims.x, dims.y, 3),
+                Direction::BottomRight,
+                map_size,
+            );
+            assert_eq!(extended, rebuilt);
+            assert_eq!(extended.behind, rebuilt.behind);
+        }
+    }
+
     /*
       |   |   |
     |0,0|1,0|2,0|
@@ -432,149 +1549,1060 @@ mod test_behind_cells {
     #[test]
     fn test_behind_1x1x1_even_y() {
         let main_cell = Cell::new(1, 2);
-        let expected = vec![Cell::new(0, 1), Cell::new(1, 1), Cell::new(1, 0)];
-        let actual = CurrentCells::behind(&[main_cell], 1, UVec2::new(3, 7));
+        let expected = vec![Cell::new(1, 0), Cell::new(0, 1), Cell::new(1, 1)];
+        let actual = CurrentCells::behind(&[main_cell], 1, MapSize::new(3, 7));
         assert_eq!(actual, expected);
     }
 
     #[test]
     fn test_behind_1x1x1_odd_y() {
         let main_cell = Cell::new(1, 5);
-        let expected = vec![Cell::new(1, 4), Cell::new(2, 4), Cell::new(1, 3)];
-        let actual = CurrentCells::behind(&[main_cell], 1, UVec2::new(3, 7));
+        let expected = vec![Cell::new(1, 3), Cell::new(1, 4), Cell::new(2, 4)];
+        let actual = CurrentCells::behind(&[main_cell], 1, MapSize::new(3, 7));
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_behind_1x1x2_even_y() {
+        let main_cell = Cell::new(1, 4);
+        let expected = vec![
+            Cell::new(1, 0),
+            Cell::new(0, 1),
+            Cell::new(1, 1),
+            Cell::new(1, 2),
+            Cell::new(0, 3),
+            Cell::new(1, 3),
+        ];
+        let actual = CurrentCells::behind(&[main_cell], 2, MapSize::new(3, 7));
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_behind_1x1x2_odd_y() {
+        let main_cell = Cell::new(0, 5);
+        let expected = vec![
+            Cell::new(0, 1),
+            Cell::new(0, 2),
+            Cell::new(1, 2),
+            Cell::new(0, 3),
+            Cell::new(0, 4),
+            Cell::new(1, 4),
+        ];
+        let actual = CurrentCells::behind(&[main_cell], 2, MapSize::new(3, 7));
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_behind_1x1x3() {
+        let main_cell = Cell::new(1, 6);
+        let expected = vec![
+            Cell::new(1, 0),
+            Cell::new(0, 1),
+            Cell::new(1, 1),
+            Cell::new(1, 2),
+            Cell::new(0, 3),
+            Cell::new(1, 3),
+            Cell::new(1, 4),
+            Cell::new(0, 5),
+            Cell::new(1, 5),
+        ];
+        let actual = CurrentCells::behind(&[main_cell], 3, MapSize::new(3, 7));
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_behind_2x2x1() {
+        let underneath = vec![
+            Cell::new(1, 4),
+            Cell::new(0, 3),
+            Cell::new(1, 3),
+            Cell::new(1, 2),
+        ];
+        let expected = vec![
+            Cell::new(1, 0),
+            Cell::new(0, 1),
+            Cell::new(1, 1),
+            Cell::new(0, 2),
+            Cell::new(2, 2),
+        ];
+        let actual = CurrentCells::behind(&underneath, 1, MapSize::new(3, 7));
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_behind_2x2x2() {
+        let underneath = vec![
+            Cell::new(1, 6),
+            Cell::new(0, 5),
+            Cell::new(1, 5),
+            Cell::new(1, 4),
+        ];
+        let expected = vec![
+            Cell::new(1, 0),
+            Cell::new(0, 1),
+            Cell::new(1, 1),
+            Cell::new(0, 2),
+            Cell::new(1, 2),
+            Cell::new(2, 2),
+            Cell::new(0, 3),
+            Cell::new(1, 3),
+            Cell::new(0, 4),
+            Cell::new(2, 4),
+        ];
+        let actual = CurrentCells::behind(&underneath, 2, MapSize::new(3, 7));
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_behind_2x1x2() {
+        let underneath = vec![Cell::new(1, 5), Cell::new(2, 4)];
+        let expected = vec![
+            Cell::new(2, 0),
+            Cell::new(1, 1),
+            Cell::new(2, 1),
+            Cell::new(1, 2),
+            Cell::new(2, 2),
+            Cell::new(1, 3),
+            Cell::new(2, 3),
+            Cell::new(1, 4),
+        ];
+        let actual = CurrentCells::behind(&underneath, 2, MapSize::new(3, 7));
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_behind_1x2x2() {
+        let underneath = vec![Cell::new(1, 6), Cell::new(0, 5)];
+        let expected = vec![
+            Cell::new(0, 1),
+            Cell::new(0, 2),
+            Cell::new(1, 2),
+            Cell::new(0, 3),
+            Cell::new(1, 3),
+            Cell::new(0, 4),
+            Cell::new(1, 4),
+            Cell::new(1, 5),
+        ];
+        let actual = CurrentCells::behind(&underneath, 2, MapSize::new(3, 7));
+        assert_eq!(actual, expected);
+    }
+
+    // NOTE:
+    // - a 1x4 footprint runs along TopLeft, so it crosses from even to odd rows at every cell,
+    //   and each cell's TopLeft is the next cell of the footprint
+    /*
+      |   |   |   |   |
+    |   |   |   |   |   |
+      |   |   |   |   |   |
+    |   |   |   |   |   |
+      | B |   |   |   |   |
+    | B | B |   |   |   |
+      | U | B |   |   |   |
+    |   | U | B |   |   |
+      |   | U | B |   |   |
+    |   |   | U |   |   |
+      |   |   |   |   |   |
+      |   |   |   |   |
+    */
+    #[test]
+    fn test_behind_1x4x1_across_parities() {
+        let map_size = MapSize::new(5, 10);
+        let item = CurrentCells::new(
+            Cell::new(2, 8),
+            UVec3::new(1, 4, 1),
+            Direction::BottomRight,
+            map_size,
+        );
+        let underneath = vec![
+            Cell::new(2, 8),
+            Cell::new(1, 7),
+            Cell::new(1, 6),
+            Cell::new(0, 5),
+        ];
+        let expected = vec![
+            Cell::new(0, 3),
+            Cell::new(0, 4),
+            Cell::new(1, 4),
+            Cell::new(1, 5),
+            Cell::new(2, 6),
+            Cell::new(2, 7),
+        ];
+        assert_eq!(item.underneath, underneath);
+        assert_eq!(item.behind, expected);
+    }
+
+    /*
+      |   |   |   |   |
+    |   |   |   |   |   |
+      |   |   |   |   |   |
+    |   |   |   |   |   |
+      |   |   |   | B |   |
+    |   |   |   | B | B |
+      |   |   | B | U |   |
+    |   |   | B | U |   |
+      |   | B | U |   |   |
+    |   |   | U |   |   |
+      |   |   |   |   |   |
+      |   |   |   |   |
+    */
+    #[test]
+    fn test_behind_4x1x1_across_parities() {
+        let map_size = MapSize::new(5, 10);
+        let item = CurrentCells::new(
+            Cell::new(2, 8),
+            UVec3::new(4, 1, 1),
+            Direction::BottomRight,
+            map_size,
+        );
+        let expected = vec![
+            Cell::new(3, 3),
+            Cell::new(3, 4),
+            Cell::new(4, 4),
+            Cell::new(2, 5),
+            Cell::new(2, 6),
+            Cell::new(1, 7),
+        ];
+        assert_eq!(item.behind, expected);
+    }
+
+    /*
+      |   |   |   |   |
+    |   |   |   |   |   |
+      | B |   |   |   |   |
+    | B | B |   |   |   |
+      | B | B |   |   |   |
+    | B | B | B |   |   |
+      | U | B | B |   |   |
+    |   | U | B |   |   |
+      |   | U | B |   |   |
+    |   |   | U |   |   |
+      |   |   |   |   |   |
+      |   |   |   |   |
+    */
+    #[test]
+    fn test_behind_1x4x2_across_parities() {
+        let map_size = MapSize::new(5, 10);
+        let item = CurrentCells::new(
+            Cell::new(2, 8),
+            UVec3::new(1, 4, 2),
+            Direction::BottomRight,
+            map_size,
+        );
+        let expected = vec![
+            Cell::new(0, 1),
+            Cell::new(0, 2),
+            Cell::new(1, 2),
+            Cell::new(0, 3),
+            Cell::new(1, 3),
+            Cell::new(0, 4),
+            Cell::new(1, 4),
+            Cell::new(2, 4),
+            Cell::new(1, 5),
+            Cell::new(2, 5),
+            Cell::new(2, 6),
+            Cell::new(2, 7),
+        ];
+        assert_eq!(item.behind, expected);
+    }
+
+    #[test]
+    fn test_behind_independent_of_underneath_order() {
+        let underneath = vec![Cell::new(1, 6), Cell::new(0, 5)];
+        let reversed = vec![Cell::new(0, 5), Cell::new(1, 6)];
+        let actual = CurrentCells::behind(&underneath, 2, MapSize::new(3, 7));
+        let actual_reversed = CurrentCells::behind(&reversed, 2, MapSize::new(3, 7));
+        assert_eq!(actual, actual_reversed);
+    }
+
+    #[test]
+    fn behind_arc_first_step() {
+        let map_size = MapSize::new(3, 7);
+        let even = Cell::new(1, 2).behind_arc(1, map_size);
+        assert_eq!(
+            even,
+            vec![Cell::new(1, 0), Cell::new(0, 1), Cell::new(1, 1)]
+        );
+        let odd = Cell::new(1, 5).behind_arc(1, map_size);
+        assert_eq!(odd, vec![Cell::new(1, 3), Cell::new(1, 4), Cell::new(2, 4)]);
+    }
+
+    #[test]
+    fn behind_arc_second_step() {
+        let map_size = MapSize::new(3, 7);
+        let arc = Cell::new(1, 4).behind_arc(2, map_size);
+        assert_eq!(arc, vec![Cell::new(1, 0), Cell::new(0, 1), Cell::new(1, 1)]);
+        assert!(Cell::new(1, 4).behind_arc(0, map_size).is_empty());
+    }
+}
+
+#[cfg(test)]
+mod test_front_cells {
+    use super::*;
+
+    /*
+      |   |   |
+    |0,0|1,0|2,0|
+      |0,1|1,1|2,1|
+    |0,2|1,2|2,2|
+      |0,3|1,3|2,3|
+    |0,4|1,4|2,4|
+      |0,5|1,5|2,5|
+    |0,6|1,6|2,6|
+      |   |   |
+    */
+
+    #[test]
+    fn test_front_1x1x1_even_y() {
+        let main_cell = Cell::new(1, 4);
+        let expected = vec![Cell::new(0, 5), Cell::new(1, 5), Cell::new(1, 6)];
+        let actual = CurrentCells::front(&[main_cell], 1, MapSize::new(3, 7));
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_front_1x1x1_odd_y() {
+        let main_cell = Cell::new(1, 1);
+        let expected = vec![Cell::new(1, 2), Cell::new(2, 2), Cell::new(1, 3)];
+        let actual = CurrentCells::front(&[main_cell], 1, MapSize::new(3, 7));
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_front_1x1x2_even_y() {
+        let main_cell = Cell::new(1, 2);
+        let expected = vec![
+            Cell::new(0, 3),
+            Cell::new(1, 3),
+            Cell::new(1, 4),
+            Cell::new(0, 5),
+            Cell::new(1, 5),
+            Cell::new(1, 6),
+        ];
+        let actual = CurrentCells::front(&[main_cell], 2, MapSize::new(3, 7));
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_front_2x2x1() {
+        let underneath = vec![
+            Cell::new(1, 4),
+            Cell::new(0, 3),
+            Cell::new(1, 3),
+            Cell::new(1, 2),
+        ];
+        let expected = vec![
+            Cell::new(0, 4),
+            Cell::new(2, 4),
+            Cell::new(0, 5),
+            Cell::new(1, 5),
+            Cell::new(1, 6),
+        ];
+        let actual = CurrentCells::front(&underneath, 1, MapSize::new(3, 7));
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_front_clamped_at_bottom_border() {
+        let map_size = MapSize::new(3, 7);
+        assert!(CurrentCells::front(&[Cell::new(1, 6)], 2, map_size).is_empty());
+        assert_eq!(
+            CurrentCells::front(&[Cell::new(1, 5)], 2, map_size),
+            vec![Cell::new(1, 6), Cell::new(2, 6)]
+        );
+    }
+
+    // NOTE:
+    // - on a map with an odd number of rows, flipping it upside down keeps the stagger of each
+    //   row, and turns the cells behind an item into the cells in front of the flipped item
+    #[test]
+    fn test_front_mirrors_behind() {
+        let map_size = MapSize::new(3, 7);
+        let flip = |cells: Vec<Cell>| {
+            let mut flipped = cells
+                .into_iter()
+                .map(|cell| Cell::new(cell.x, 6 - cell.y))
+                .collect::<Vec<Cell>>();
+            flipped.sort();
+            flipped
+        };
+        for cell in Cell::window(Cell::new(0, 0), Cell::new(2, 6), map_size) {
+            for height in 1..=3 {
+                let flipped_behind =
+                    flip(CurrentCells::behind(&flip(vec![cell]), height, map_size));
+                assert_eq!(
+                    CurrentCells::front(&[cell], height, map_size),
+                    flipped_behind,
+                    "{cell:?} {height}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_front_follows_the_item() {
+        let map_size = MapSize::new(3, 7);
+        let mut cells = CurrentCells::new(
+            Cell::new(1, 3),
+            UVec3::new(2, 1, 1),
+            Direction::BottomRight,
+            map_size,
+        );
+        assert_eq!(
+            cells.front,
+            CurrentCells::front(&cells.underneath, 1, map_size)
+        );
+
+        cells.extend_behind(1, map_size);
+        let rebuilt = CurrentCells::new(
+            Cell::new(1, 3),
+            UVec3::new(2, 1, 2),
+            Direction::BottomRight,
+            map_size,
+        );
+        assert_eq!(cells.front, rebuilt.front);
+        assert!(cells
+            .front
+            .iter()
+            .all(|cell| !cells.underneath.contains(cell)));
+    }
+}
+
+#[cfg(test)]
+mod test_world_positions {
+    use super::*;
+
+    #[test]
+    fn test_behind_world_matches_cells() {
+        let tile_size = Vec2::new(64., 32.);
+        let cells = CurrentCells::new(
+            Cell::new(1, 4),
+            UVec3::new(2, 1, 1),
+            Direction::BottomRight,
+            MapSize::new(3, 7),
+        );
+        let expected = cells
+            .behind
+            .iter()
+            .map(|cell| cell.to_world(tile_size, Vec2::ZERO))
+            .collect::<Vec<Vec2>>();
+        let actual = cells
+            .behind_world(tile_size, Vec2::ZERO)
+            .collect::<Vec<Vec2>>();
+        assert_eq!(actual, expected);
+        assert_eq!(actual.len(), cells.behind.len());
+        assert!(actual.contains(&Vec2::new(64., -32.)));
+    }
+
+    #[test]
+    fn test_underneath_world_matches_cells() {
+        let tile_size = Vec2::new(64., 32.);
+        let cells = CurrentCells::new(
+            Cell::new(1, 4),
+            UVec3::new(2, 1, 1),
+            Direction::BottomRight,
+            MapSize::new(3, 7),
+        );
+        let actual = cells
+            .underneath_world(tile_size, Vec2::ZERO)
+            .collect::<Vec<Vec2>>();
+        assert_eq!(actual, vec![Vec2::new(64., -64.), Vec2::new(96., -48.)]);
+        let origin = Vec2::new(-64., 32.);
+        let shifted = cells
+            .underneath_world(tile_size, origin)
+            .collect::<Vec<Vec2>>();
+        assert_eq!(shifted, vec![Vec2::new(0., -32.), Vec2::new(32., -16.)]);
+    }
+
+    #[test]
+    fn test_outline_1x1() {
+        let tile_size = Vec2::new(64., 32.);
+        let cells = CurrentCells::new(
+            Cell::new(1, 4),
+            UVec3::new(1, 1, 1),
+            Direction::BottomRight,
+            MapSize::new(3, 7),
+        );
+        let actual = cells.footprint_outline_world(tile_size, Vec2::ZERO);
+        let expected = vec![
+            Vec2::new(32., -64.),
+            Vec2::new(64., -80.),
+            Vec2::new(96., -64.),
+            Vec2::new(64., -48.),
+        ];
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_outline_2x2() {
+        let tile_size = Vec2::new(64., 32.);
+        let cells = CurrentCells::new(
+            Cell::new(1, 4),
+            UVec3::new(2, 2, 1),
+            Direction::BottomRight,
+            MapSize::new(3, 7),
+        );
+        let actual = cells.footprint_outline_world(tile_size, Vec2::ZERO);
+        assert_eq!(actual.len(), 4);
+        assert!(actual.contains(&Vec2::new(64., -80.)));
+        assert!(actual.contains(&Vec2::new(64., -16.)));
+    }
+
+    #[test]
+    fn test_outline_2x1() {
+        let tile_size = Vec2::new(64., 32.);
+        let cells = CurrentCells::new(
+            Cell::new(1, 4),
+            UVec3::new(2, 1, 1),
+            Direction::BottomRight,
+            MapSize::new(3, 7),
+        );
+        assert_eq!(
+            cells.footprint_outline_world(tile_size, Vec2::ZERO).len(),
+            4
+        );
+    }
+
+    #[test]
+    fn test_outline_with_origin() {
+        let tile_size = Vec2::new(64., 32.);
+        let origin = Vec2::new(-100., 40.);
+        let cells = CurrentCells::new(
+            Cell::new(1, 4),
+            UVec3::new(1, 1, 1),
+            Direction::BottomRight,
+            MapSize::new(3, 7),
+        );
+        let actual = cells.footprint_outline_world(tile_size, origin);
+        let expected = vec![
+            Vec2::new(-68., -24.),
+            Vec2::new(-36., -40.),
+            Vec2::new(-4., -24.),
+            Vec2::new(-36., -8.),
+        ];
+        assert_eq!(actual, expected);
+        let centre = cells.main_cell.to_world(tile_size, origin);
+        assert_eq!(actual.iter().sum::<Vec2>() / 4., centre);
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::float_cmp)]
+mod test_overlap {
+    use super::*;
+
+    fn item(main_cell: Cell, dims: UVec3) -> CurrentCells {
+        CurrentCells::new(main_cell, dims, Direction::BottomRight, MapSize::new(3, 7))
+    }
+
+    #[test]
+    fn test_overlap_ratio_fully_covered() {
+        let hidden = item(Cell::new(1, 2), UVec3::ONE);
+        let occluder = item(Cell::new(1, 4), UVec3::ONE);
+        assert!(hidden.overlaps(&occluder));
+        assert_eq!(hidden.overlap_ratio(&[occluder]), 1.);
+    }
+
+    #[test]
+    fn test_overlap_ratio_partially_covered() {
+        let hidden = item(Cell::new(1, 3), UVec3::new(2, 1, 1));
+        let occluder = item(Cell::new(1, 4), UVec3::ONE);
+        assert_eq!(
+            hidden.overlapping_cells(&occluder).collect::<Vec<Cell>>(),
+            vec![Cell::new(1, 3)]
+        );
+        assert_eq!(hidden.overlap_ratio(&[occluder]), 0.5);
+    }
+
+    #[test]
+    fn test_overlap_ratio_disjoint() {
+        let hidden = item(Cell::new(1, 2), UVec3::ONE);
+        let occluder = item(Cell::new(0, 1), UVec3::ONE);
+        assert!(!hidden.overlaps(&occluder));
+        assert_eq!(hidden.overlap_ratio(&[occluder]), 0.);
+    }
+}
+
+#[cfg(test)]
+mod test_from_cells {
+    use super::*;
+
+    #[test]
+    fn test_from_cells_matches_new() {
+        let expected = CurrentCells::new(
+            Cell::new(1, 5),
+            UVec3::new(2, 3, 2),
+            Direction::BottomRight,
+            MapSize::new(3, 7),
+        );
+        let actual = CurrentCells::from_cells(expected.underneath.clone(), 2, MapSize::new(3, 7));
+        assert_eq!(actual, expected);
+        assert_eq!(actual.behind, expected.behind);
+    }
+
+    #[test]
+    fn test_from_world_rect_2x2() {
+        let tile_size = Vec2::new(64., 32.);
+        let map_size = MapSize::new(3, 7);
+        let rect = Rect::new(60., -50., 130., -10.);
+        let expected = CurrentCells::new(
+            Cell::new(1, 3),
+            UVec3::new(2, 2, 1),
+            Direction::BottomRight,
+            map_size,
+        );
+        let actual = CurrentCells::from_world_rect(rect, tile_size, Vec2::ZERO, 1, map_size);
+        let mut expected_underneath = expected.underneath.clone();
+        expected_underneath.sort();
         assert_eq!(actual, expected);
+        assert_eq!(actual.underneath, expected_underneath);
+
+        let origin = Vec2::new(200., -100.);
+        let shifted = Rect::from_corners(rect.min + origin, rect.max + origin);
+        let actual = CurrentCells::from_world_rect(shifted, tile_size, origin, 1, map_size);
+        assert_eq!(actual.underneath, expected_underneath);
+    }
+
+    #[test]
+    fn test_moving_an_l_shape_keeps_its_cells() {
+        let map_size = MapSize::new(8, 16);
+        let main_coords = Cell::new(3, 10).diamond_coords();
+        let top_right = Direction::TopRight.diamond_step();
+        let top_left = Direction::TopLeft.diamond_step();
+        let cells = [
+            main_coords,
+            main_coords + top_right,
+            main_coords + top_right * 2,
+            main_coords + top_left,
+        ]
+        .into_iter()
+        .map(|coords| Cell::from_diamond_coords(coords, map_size).unwrap())
+        .collect::<Vec<Cell>>();
+        let item = CurrentCells::from_cells(cells, 1, map_size);
+        assert!(item.shape.is_some());
+        assert_eq!(item.underneath.len(), 4);
+        assert!(item.is_fully_on_map());
+        assert!(!item.clone().clamp_to_map(map_size));
+
+        let mut stepped = item.clone();
+        assert!(stepped.step_forward(map_size));
+        let step = Direction::BottomRight.diamond_step();
+        let mut expected = item
+            .underneath
+            .iter()
+            .map(|cell| Cell::from_diamond_coords(cell.diamond_coords() + step, map_size).unwrap())
+            .collect::<Vec<Cell>>();
+        expected.sort();
+        assert_eq!(stepped.underneath, expected);
+
+        let mut turned = item.clone();
+        assert!(turned.rotate_around(item.main_cell, Direction::BottomLeft, map_size));
+        assert_eq!(turned.underneath.len(), 4);
+        assert_ne!(turned.underneath, item.underneath);
+        assert!(turned.rotate_around(item.main_cell, Direction::BottomRight, map_size));
+        assert_eq!(turned, item);
+
+        let mut clamped = item.rebuilt(Cell::new(0, 0), Direction::BottomRight, map_size);
+        assert!(!clamped.is_fully_on_map());
+        assert!(clamped.clamp_to_map(map_size));
+        assert!(clamped.is_fully_on_map());
+        assert_eq!(clamped.underneath.len(), 4);
+        assert_eq!(clamped.shape, item.shape);
+    }
+
+    #[test]
+    fn test_world_rect_bounds_cover_every_centre_inside() {
+        let tile_size = Vec2::new(64., 32.);
+        let origin = Vec2::new(-40., 25.);
+        let map_size = MapSize::new(4, 9);
+        let rects = [
+            Rect::new(-10., -60., 70., 10.),
+            Rect::new(-120., -200., 20., 80.),
+            Rect::new(90., -170., 400., -90.),
+            Rect::new(0., -40., 1., -39.),
+        ];
+        for rect in rects {
+            let shifted = Rect::from_corners(rect.min + origin, rect.max + origin);
+            let mut expected = (0..map_size.0.y)
+                .flat_map(|y| (0..map_size.0.x).map(move |x| Cell::new(x, y)))
+                .filter(|cell| shifted.contains(cell.to_world(tile_size, origin)))
+                .collect::<Vec<Cell>>();
+            expected.sort();
+            let mut actual =
+                CurrentCells::cells_in_world_rect(shifted, tile_size, origin, map_size);
+            actual.sort();
+            assert_eq!(actual, expected, "{rect:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_new_batch {
+    use super::*;
+
+    #[test]
+    fn test_batch_reports_per_item_errors() {
+        let map_size = MapSize::new(3, 7);
+        let placements = [
+            (Cell::new(1, 4), UVec3::new(2, 1, 1), Direction::BottomRight),
+            (Cell::new(3, 4), UVec3::new(1, 1, 1), Direction::BottomRight),
+            (Cell::new(1, 4), UVec3::new(1, 1, 1), Direction::Top),
+        ];
+        let actual = CurrentCells::new_batch(&placements, map_size);
+        assert_eq!(
+            actual[0],
+            Ok(CurrentCells::new(
+                Cell::new(1, 4),
+                UVec3::new(2, 1, 1),
+                Direction::BottomRight,
+                map_size
+            ))
+        );
+        assert_eq!(
+            actual[1],
+            Err(CellsError::OutOfBounds {
+                cell: Cell::new(3, 4),
+                map_size
+            })
+        );
+        assert_eq!(actual[2], Err(CellsError::InvalidFacing(Direction::Top)));
+    }
+
+    #[test]
+    fn test_footprint_past_the_edge_is_out_of_bounds() {
+        let map_size = MapSize::new(3, 7);
+        let dims = UVec3::new(2, 1, 1);
+        assert!(
+            CurrentCells::try_new(Cell::new(1, 5), dims, Direction::BottomRight, map_size).is_ok()
+        );
+        assert_eq!(
+            CurrentCells::try_new(Cell::new(2, 5), dims, Direction::BottomRight, map_size),
+            Err(CellsError::FootprintOutOfBounds {
+                main_cell: Cell::new(2, 5),
+                dimensions: dims,
+                map_size
+            })
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_rotate_around {
+    use super::*;
+
+    // NOTE:
+    // - both items start on an odd row, the centre of the 3x3 is its middle cell, and the 2x3
+    //   turns around the middle cell of its first column, as it has no single middle cell
+    #[test]
+    fn test_rotate_around_centre() {
+        let map_size = MapSize::new(8, 16);
+        let odd_width = CurrentCells::new(
+            Cell::new(3, 11),
+            UVec3::new(3, 3, 1),
+            Direction::BottomRight,
+            map_size,
+        );
+        let centre = Cell::new(3, 9);
+        let mut rotated = odd_width.clone();
+        assert!(rotated.rotate_around(centre, Direction::BottomLeft, map_size));
+        assert_eq!(rotated.main_cell, Cell::new(3, 11));
+        assert_eq!(rotated.underneath_set(), odd_width.underneath_set());
+
+        let even_width = CurrentCells::new(
+            Cell::new(3, 11),
+            UVec3::new(2, 3, 1),
+            Direction::BottomRight,
+            map_size,
+        );
+        let centre = Cell::new(3, 10);
+        let mut rotated = even_width.clone();
+        assert!(rotated.rotate_around(centre, Direction::BottomLeft, map_size));
+        assert_eq!(rotated.facing, Direction::BottomLeft);
+        assert_eq!(rotated.main_cell, Cell::new(2, 11));
+        let mut expected = vec![
+            Cell::new(2, 11),
+            Cell::new(2, 10),
+            Cell::new(3, 10),
+            Cell::new(2, 9),
+            Cell::new(3, 9),
+            Cell::new(3, 8),
+        ];
+        expected.sort();
+        let mut actual = rotated.underneath.clone();
+        actual.sort();
+        assert_eq!(actual, expected);
+
+        assert!(rotated.rotate_around(centre, Direction::BottomRight, map_size));
+        assert_eq!(rotated, even_width);
+    }
+
+    fn turned_coords(cells: &CurrentCells, pivot: Cell, n_turns: usize) -> HashSet<IVec2> {
+        let pivot_coords = pivot.diamond_coords();
+        cells
+            .underneath
+            .iter()
+            .map(|cell| {
+                let relative = (0..n_turns)
+                    .fold(cell.diamond_coords() - pivot_coords, |coords, _| {
+                        IVec2::new(coords.y, -coords.x)
+                    });
+                pivot_coords + relative
+            })
+            .collect()
+    }
+
+    fn coords(cells: &CurrentCells) -> HashSet<IVec2> {
+        cells
+            .underneath
+            .iter()
+            .map(|cell| cell.diamond_coords())
+            .collect()
+    }
+
+    #[test]
+    fn test_rotate_to_every_facing() {
+        let map_size = MapSize::new(8, 16);
+        let original = CurrentCells::new(
+            Cell::new(3, 10),
+            UVec3::new(2, 3, 1),
+            Direction::BottomRight,
+            map_size,
+        );
+        let centre = original.underneath[2];
+        for (new_facing, n_turns) in [
+            (Direction::BottomLeft, 1),
+            (Direction::TopLeft, 2),
+            (Direction::TopRight, 3),
+        ] {
+            let mut rotated = original.clone();
+            assert!(rotated.rotate_around(centre, new_facing, map_size));
+            assert_eq!(rotated.facing, new_facing);
+            assert_eq!(rotated.dimensions, original.dimensions);
+            assert_eq!(
+                coords(&rotated),
+                turned_coords(&original, centre, n_turns),
+                "{new_facing:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_rotate_top_left_to_bottom_right_is_a_half_turn() {
+        let map_size = MapSize::new(8, 16);
+        let original = CurrentCells::new(
+            Cell::new(3, 10),
+            UVec3::new(2, 3, 1),
+            Direction::TopLeft,
+            map_size,
+        );
+        let pivot = Cell::new(3, 8);
+        let mut rotated = original.clone();
+        assert!(rotated.rotate_around(pivot, Direction::BottomRight, map_size));
+        assert_eq!(coords(&rotated), turned_coords(&original, pivot, 2));
+
+        assert!(rotated.rotate_around(pivot, Direction::TopLeft, map_size));
+        assert_eq!(rotated, original);
+    }
+
+    #[test]
+    #[should_panic(expected = "Items can only face BottomRight or BottomLeft")]
+    fn test_rotate_to_invalid_facing() {
+        let map_size = MapSize::new(8, 16);
+        let mut item = CurrentCells::new(
+            Cell::new(3, 10),
+            UVec3::ONE,
+            Direction::BottomRight,
+            map_size,
+        );
+        item.rotate_around(Cell::new(3, 10), Direction::Top, map_size);
+    }
+
+    #[test]
+    fn test_rotate_off_map() {
+        let map_size = MapSize::new(3, 7);
+        let mut item = CurrentCells::new(
+            Cell::new(0, 6),
+            UVec3::new(1, 3, 1),
+            Direction::BottomRight,
+            map_size,
+        );
+        let expected = item.clone();
+        assert!(!item.rotate_around(Cell::new(0, 6), Direction::BottomLeft, map_size));
+        assert_eq!(item, expected);
     }
+}
+
+#[cfg(test)]
+mod test_step_forward {
+    use super::*;
 
     #[test]
-    fn test_behind_1x1x2_even_y() {
-        let main_cell = Cell::new(1, 4);
-        let expected = vec![
-            Cell::new(0, 3),
-            Cell::new(1, 3),
+    fn test_step_forward_bottom_right() {
+        let map_size = MapSize::new(3, 6);
+        let mut item = CurrentCells::new(
             Cell::new(1, 2),
-            Cell::new(0, 1),
-            Cell::new(1, 1),
-            Cell::new(1, 0),
-        ];
-        let actual = CurrentCells::behind(&[main_cell], 2, UVec2::new(3, 7));
-        assert_eq!(actual, expected);
+            UVec3::new(1, 1, 1),
+            Direction::BottomRight,
+            map_size,
+        );
+
+        assert!(item.step_forward(map_size));
+        assert_eq!(item.main_cell, Cell::new(1, 3));
+        assert!(item.step_forward(map_size));
+        assert_eq!(item.main_cell, Cell::new(2, 4));
+        assert!(item.step_forward(map_size));
+        assert_eq!(item.main_cell, Cell::new(2, 5));
+        assert_eq!(item.underneath, vec![Cell::new(2, 5)]);
+
+        let expected = item.clone();
+        assert!(!item.step_forward(map_size));
+        assert_eq!(item, expected);
     }
+}
+
+#[cfg(test)]
+mod test_volume_cells {
+    use super::*;
 
     #[test]
-    fn test_behind_1x1x2_odd_y() {
-        let main_cell = Cell::new(0, 5);
-        let expected = vec![
-            Cell::new(0, 4),
+    fn test_volume_cells_2x2x2() {
+        let item = CurrentCells::new(
             Cell::new(1, 4),
-            Cell::new(0, 3),
-            Cell::new(0, 2),
-            Cell::new(1, 2),
-            Cell::new(0, 1),
-        ];
-        let actual = CurrentCells::behind(&[main_cell], 2, UVec2::new(3, 7));
-        assert_eq!(actual, expected);
+            UVec3::new(2, 2, 2),
+            Direction::BottomRight,
+            MapSize::new(3, 6),
+        );
+
+        let volume = item.volume_cells().collect::<Vec<(Cell, u32)>>();
+
+        assert_eq!(volume.len(), 8);
+        for level in 0..2 {
+            assert!(item
+                .underneath
+                .iter()
+                .all(|cell| volume.contains(&(*cell, level))));
+        }
+        assert!(!volume.iter().any(|(_, level)| *level >= 2));
     }
+}
+
+#[cfg(test)]
+mod test_by_content {
+    use super::*;
 
     #[test]
-    fn test_behind_1x1x3() {
-        let main_cell = Cell::new(1, 6);
-        let expected = vec![
-            Cell::new(0, 5),
-            Cell::new(1, 5),
+    fn test_by_content_compares_derived_fields() {
+        let item = CurrentCells::new(
             Cell::new(1, 4),
-            Cell::new(0, 3),
-            Cell::new(1, 3),
-            Cell::new(1, 2),
-            Cell::new(0, 1),
-            Cell::new(1, 1),
-            Cell::new(1, 0),
-        ];
-        let actual = CurrentCells::behind(&[main_cell], 3, UVec2::new(3, 7));
-        assert_eq!(actual, expected);
+            UVec3::new(1, 1, 2),
+            Direction::BottomRight,
+            MapSize::new(3, 6),
+        );
+        let inclusive = item.clone().with_inclusive_behind();
+
+        assert_eq!(item, inclusive);
+        assert_ne!(ByContent(item.clone()), ByContent(inclusive));
+
+        let clipped_front = CurrentCells::new(
+            Cell::new(1, 4),
+            UVec3::new(1, 1, 2),
+            Direction::BottomRight,
+            MapSize::new(3, 5),
+        );
+        assert_eq!(item.underneath, clipped_front.underneath);
+        assert_eq!(item.behind, clipped_front.behind);
+        assert_ne!(item.front, clipped_front.front);
+        assert_ne!(ByContent(item.clone()), ByContent(clipped_front));
+        assert_eq!(ByContent(item.clone()), ByContent(item));
     }
+}
+
+#[cfg(test)]
+mod test_dedup_footprints {
+    use super::*;
 
     #[test]
-    fn test_behind_2x2x1() {
-        let underneath = vec![
-            Cell::new(1, 4),
-            Cell::new(0, 3),
-            Cell::new(1, 3),
-            Cell::new(1, 2),
-        ];
-        let expected = vec![
-            Cell::new(0, 2),
-            Cell::new(0, 1),
-            Cell::new(2, 2),
-            Cell::new(1, 1),
-            Cell::new(1, 0),
+    fn test_dedup_identical_footprints() {
+        let map_size = MapSize::new(3, 6);
+        let item = |main_cell| {
+            CurrentCells::new(
+                main_cell,
+                UVec3::new(1, 2, 1),
+                Direction::BottomRight,
+                map_size,
+            )
+        };
+        let [first, duplicate, other] = [0, 1, 2].map(Entity::from_raw);
+        let mut items = vec![
+            (first, item(Cell::new(1, 3))),
+            (duplicate, item(Cell::new(1, 3))),
+            (other, item(Cell::new(1, 5))),
         ];
-        let actual = CurrentCells::behind(&underneath, 1, UVec2::new(3, 7));
-        assert_eq!(actual, expected);
+
+        let removed = dedup_footprints(&mut items);
+
+        assert_eq!(removed, vec![duplicate]);
+        assert_eq!(
+            items
+                .iter()
+                .map(|(entity, _)| *entity)
+                .collect::<Vec<Entity>>(),
+            vec![first, other]
+        );
     }
+}
+
+#[cfg(test)]
+mod test_clamp_to_map {
+    use super::*;
 
     #[test]
-    fn test_behind_2x2x2() {
-        let underneath = vec![
-            Cell::new(1, 6),
-            Cell::new(0, 5),
-            Cell::new(1, 5),
-            Cell::new(1, 4),
-        ];
-        let expected = vec![
-            Cell::new(0, 4),
-            Cell::new(0, 3),
-            Cell::new(2, 4),
-            Cell::new(1, 3),
-            Cell::new(1, 2),
-            Cell::new(0, 2),
-            Cell::new(0, 1),
-            Cell::new(2, 2),
-            Cell::new(1, 1),
-            Cell::new(1, 0),
-        ];
-        let actual = CurrentCells::behind(&underneath, 2, UVec2::new(3, 7));
-        assert_eq!(actual, expected);
+    fn test_clamp_3x2_near_border() {
+        let map_size = MapSize::new(4, 8);
+        let mut item = CurrentCells::new(
+            Cell::new(3, 2),
+            UVec3::new(3, 2, 1),
+            Direction::BottomRight,
+            map_size,
+        );
+        assert!(!item.is_fully_on_map());
+
+        assert!(item.clamp_to_map(map_size));
+        assert!(item.is_fully_on_map());
+        assert_eq!(item.underneath.len(), 6);
+        assert_eq!(item.dimensions, UVec3::new(3, 2, 1));
     }
 
     #[test]
-    fn test_behind_2x1x2() {
-        let underneath = vec![Cell::new(1, 5), Cell::new(2, 4)];
-        let expected = vec![
-            Cell::new(1, 4),
-            Cell::new(1, 3),
-            Cell::new(2, 3),
-            Cell::new(2, 2),
-            Cell::new(1, 2),
-            Cell::new(1, 1),
-            Cell::new(2, 1),
-            Cell::new(2, 0),
-        ];
-        let actual = CurrentCells::behind(&underneath, 2, UVec2::new(3, 7));
-        assert_eq!(actual, expected);
+    fn test_clamp_leaves_fitting_item() {
+        let map_size = MapSize::new(4, 8);
+        let mut item = CurrentCells::new(
+            Cell::new(1, 6),
+            UVec3::new(2, 2, 1),
+            Direction::BottomRight,
+            map_size,
+        );
+        let expected = item.clone();
+        assert!(!item.clamp_to_map(map_size));
+        assert_eq!(item, expected);
     }
 
     #[test]
-    fn test_behind_1x2x2() {
-        let underneath = vec![Cell::new(1, 6), Cell::new(0, 5)];
-        let expected = vec![
-            Cell::new(1, 5),
-            Cell::new(1, 4),
-            Cell::new(0, 4),
-            Cell::new(0, 3),
-            Cell::new(1, 3),
-            Cell::new(1, 2),
-            Cell::new(0, 2),
+    fn test_clamp_too_big_for_map() {
+        let map_size = MapSize::new(2, 2);
+        let mut item = CurrentCells::new(
             Cell::new(0, 1),
-        ];
-        let actual = CurrentCells::behind(&underneath, 2, UVec2::new(3, 7));
-        assert_eq!(actual, expected);
+            UVec3::new(3, 3, 1),
+            Direction::BottomRight,
+            map_size,
+        );
+        let expected = item.clone();
+        assert!(!item.clamp_to_map(map_size));
+        assert_eq!(item, expected);
     }
 }
 
 #[cfg(test)]
 mod test_sort_item {
-    use bevy::ecs::world::World;
-
     use super::*;
 
     /*
@@ -589,10 +2617,8 @@ mod test_sort_item {
       |   |   |
     */
 
-    // TODO: remove the use of World and spawning the entity
-    fn setup(world: &mut World, cell: Cell, dims: UVec3) -> CurrentCells {
-        let _item_entity = world.spawn_empty().id();
-        CurrentCells::new(cell, dims, Direction::BottomRight, UVec2::new(3, 7))
+    fn setup(cell: Cell, dims: UVec3) -> CurrentCells {
+        CurrentCells::new(cell, dims, Direction::BottomRight, MapSize::new(3, 7))
     }
 
     /*
@@ -608,9 +2634,8 @@ mod test_sort_item {
     */
     #[test]
     fn test_1x1x1_vs_1x1x1_a_in_front() {
-        let mut world = World::default();
-        let a = setup(&mut world, Cell::new(1, 4), UVec3::new(1, 1, 1));
-        let b = setup(&mut world, Cell::new(1, 3), UVec3::new(1, 1, 1));
+        let a = setup(Cell::new(1, 4), UVec3::new(1, 1, 1));
+        let b = setup(Cell::new(1, 3), UVec3::new(1, 1, 1));
         assert!(a > b);
     }
 
@@ -627,9 +2652,8 @@ mod test_sort_item {
     */
     #[test]
     fn test_1x1x1_vs_1x1x1_b_in_front() {
-        let mut world = World::default();
-        let a = setup(&mut world, Cell::new(2, 2), UVec3::new(1, 1, 1));
-        let b = setup(&mut world, Cell::new(1, 3), UVec3::new(1, 1, 1));
+        let a = setup(Cell::new(2, 2), UVec3::new(1, 1, 1));
+        let b = setup(Cell::new(1, 3), UVec3::new(1, 1, 1));
         assert!(a < b);
     }
 
@@ -646,10 +2670,48 @@ mod test_sort_item {
     */
     #[test]
     fn test_1x1x1_vs_1x1x1_neither_in_front() {
-        let mut world = World::default();
-        let a = setup(&mut world, Cell::new(1, 1), UVec3::new(1, 1, 1));
-        let b = setup(&mut world, Cell::new(0, 1), UVec3::new(1, 1, 1));
+        let a = setup(Cell::new(1, 1), UVec3::new(1, 1, 1));
+        let b = setup(Cell::new(0, 1), UVec3::new(1, 1, 1));
+        assert!(a.partial_cmp(&b).is_none());
+    }
+
+    #[test]
+    fn test_compare_in_common_map() {
+        let a = setup(Cell::new(2, 4), UVec3::new(1, 1, 1));
+        let b = CurrentCells::new(
+            Cell::new(1, 5),
+            UVec3::new(1, 1, 1),
+            Direction::BottomRight,
+            MapSize::new(2, 7),
+        );
         assert!(a.partial_cmp(&b).is_none());
+        assert_eq!(
+            a.compare_in_map(&b, MapSize::new(3, 7)),
+            Some(Ordering::Less)
+        );
+        assert_eq!(
+            b.compare_in_map(&a, MapSize::new(3, 7)),
+            Some(Ordering::Greater)
+        );
+    }
+
+    #[test]
+    fn test_cutout_fence_touching_solid() {
+        let fence = setup(Cell::new(1, 4), UVec3::new(1, 1, 1));
+        let solid = setup(Cell::new(1, 3), UVec3::new(1, 1, 1));
+        assert!(fence > solid);
+
+        let fence = fence.with_transparency(Transparency::Cutout);
+        assert!(fence.partial_cmp(&solid).is_none());
+        assert!(solid.partial_cmp(&fence).is_none());
+    }
+
+    #[test]
+    fn test_identical_footprints_are_equal() {
+        let a = setup(Cell::new(1, 4), UVec3::new(2, 2, 2));
+        let b = setup(Cell::new(1, 4), UVec3::new(2, 2, 2));
+        assert_eq!(a.partial_cmp(&b), Some(Ordering::Equal));
+        assert_eq!(b.partial_cmp(&a), Some(Ordering::Equal));
     }
 
     /*
@@ -664,14 +2726,27 @@ mod test_sort_item {
       |   |   |
     */
     #[test]
-    #[should_panic]
+    #[should_panic(expected = "Items cannot be both in front and behind each other")]
     fn test_1x1x1_vs_1x1x1_equal() {
-        let mut world = World::default();
-        let a = setup(&mut world, Cell::new(1, 2), UVec3::new(3, 1, 1));
-        let b = setup(&mut world, Cell::new(2, 2), UVec3::new(1, 3, 1));
+        let a = setup(Cell::new(1, 2), UVec3::new(3, 1, 1));
+        let b = setup(Cell::new(2, 2), UVec3::new(1, 3, 1));
         let _ordering = a.partial_cmp(&b);
     }
 
+    #[test]
+    fn test_1x1x1_vs_1x1x1_cycle_is_an_error() {
+        let a = setup(Cell::new(1, 2), UVec3::new(3, 1, 1));
+        let b = setup(Cell::new(2, 2), UVec3::new(1, 3, 1));
+        assert_eq!(
+            a.try_cmp(&b),
+            Err(OcclusionCycleError {
+                first: Cell::new(1, 2),
+                second: Cell::new(2, 2),
+            })
+        );
+        assert!(a.try_cmp(&a).is_ok());
+    }
+
     /*
       |   |   |
     |   |   |   |
@@ -685,9 +2760,8 @@ mod test_sort_item {
     */
     #[test]
     fn test_1x1x2_vs_1x1x1_a_in_front() {
-        let mut world = World::default();
-        let a = setup(&mut world, Cell::new(0, 4), UVec3::new(1, 1, 2));
-        let b = setup(&mut world, Cell::new(0, 1), UVec3::new(1, 1, 1));
+        let a = setup(Cell::new(0, 4), UVec3::new(1, 1, 2));
+        let b = setup(Cell::new(0, 1), UVec3::new(1, 1, 1));
         assert!(a > b);
     }
 
@@ -704,9 +2778,8 @@ mod test_sort_item {
     */
     #[test]
     fn test_2x1x1_vs_1x1x1_a_in_front() {
-        let mut world = World::default();
-        let a = setup(&mut world, Cell::new(1, 5), UVec3::new(2, 1, 1));
-        let b = setup(&mut world, Cell::new(1, 3), UVec3::new(1, 1, 1));
+        let a = setup(Cell::new(1, 5), UVec3::new(2, 1, 1));
+        let b = setup(Cell::new(1, 3), UVec3::new(1, 1, 1));
         assert!(a > b);
     }
 
@@ -723,9 +2796,8 @@ mod test_sort_item {
     */
     #[test]
     fn test_2x4x1_vs_1x1x1_b_in_front() {
-        let mut world = World::default();
-        let a = setup(&mut world, Cell::new(2, 6), UVec3::new(2, 4, 1));
-        let b = setup(&mut world, Cell::new(0, 5), UVec3::new(1, 1, 1));
+        let a = setup(Cell::new(2, 6), UVec3::new(2, 4, 1));
+        let b = setup(Cell::new(0, 5), UVec3::new(1, 1, 1));
         assert!(a < b);
     }
 
@@ -742,9 +2814,8 @@ mod test_sort_item {
     */
     #[test]
     fn test_1x4x1_vs_1x1x2_b_in_front() {
-        let mut world = World::default();
-        let a = setup(&mut world, Cell::new(2, 5), UVec3::new(1, 4, 1));
-        let b = setup(&mut world, Cell::new(1, 6), UVec3::new(1, 1, 2));
+        let a = setup(Cell::new(2, 5), UVec3::new(1, 4, 1));
+        let b = setup(Cell::new(1, 6), UVec3::new(1, 1, 2));
         assert!(a < b);
     }
 
@@ -761,9 +2832,8 @@ mod test_sort_item {
     */
     #[test]
     fn test_3x1x1_vs_1x2x2_a_in_front() {
-        let mut world = World::default();
-        let a = setup(&mut world, Cell::new(0, 3), UVec3::new(3, 1, 1));
-        let b = setup(&mut world, Cell::new(0, 1), UVec3::new(1, 2, 2));
+        let a = setup(Cell::new(0, 3), UVec3::new(3, 1, 1));
+        let b = setup(Cell::new(0, 1), UVec3::new(1, 2, 2));
         assert!(a > b);
     }
 
@@ -780,9 +2850,8 @@ mod test_sort_item {
     */
     #[test]
     fn test_2x1x1_vs_2x1x1_neither_in_front() {
-        let mut world = World::default();
-        let a = setup(&mut world, Cell::new(0, 2), UVec3::new(2, 1, 1));
-        let b = setup(&mut world, Cell::new(1, 5), UVec3::new(2, 1, 2));
+        let a = setup(Cell::new(0, 2), UVec3::new(2, 1, 1));
+        let b = setup(Cell::new(1, 5), UVec3::new(2, 1, 2));
         assert!(a.partial_cmp(&b).is_none());
     }
 
@@ -799,11 +2868,202 @@ mod test_sort_item {
     */
     #[test]
     fn test_abc() {
-        let mut world = World::default();
-        let a = setup(&mut world, Cell::new(0, 3), UVec3::new(2, 2, 1));
-        let b = setup(&mut world, Cell::new(2, 4), UVec3::new(1, 1, 2));
-        let c = setup(&mut world, Cell::new(2, 2), UVec3::new(1, 2, 2));
+        let a = setup(Cell::new(0, 3), UVec3::new(2, 2, 1));
+        let b = setup(Cell::new(2, 4), UVec3::new(1, 1, 2));
+        let c = setup(Cell::new(2, 2), UVec3::new(1, 2, 2));
         assert!(a > c);
         assert!(b > c);
     }
 }
+
+#[cfg(test)]
+mod test_shadow_cells {
+    use super::*;
+
+    #[test]
+    fn test_shadow_1x1x2_falls_forward() {
+        let map_size = MapSize::new(3, 7);
+        let item = CurrentCells::new(
+            Cell::new(1, 2),
+            UVec3::new(1, 1, 2),
+            Direction::BottomRight,
+            map_size,
+        );
+        let expected = vec![
+            Cell::new(0, 3),
+            Cell::new(1, 3),
+            Cell::new(1, 4),
+            Cell::new(0, 5),
+            Cell::new(1, 5),
+            Cell::new(1, 6),
+        ];
+
+        assert_eq!(item.shadow_cells(map_size), expected);
+    }
+
+    #[test]
+    fn test_shadow_stops_at_map_border() {
+        let map_size = MapSize::new(3, 7);
+        let item = CurrentCells::new(
+            Cell::new(1, 6),
+            UVec3::new(1, 1, 2),
+            Direction::BottomRight,
+            map_size,
+        );
+
+        assert!(item.shadow_cells(map_size).is_empty());
+    }
+}
+
+#[cfg(test)]
+mod test_influence_mask {
+    use super::*;
+
+    #[test]
+    fn test_mask_is_footprint_and_behind() {
+        let map_size = MapSize::new(4, 7);
+        let mut item = CurrentCells::new(
+            Cell::new(1, 5),
+            UVec3::new(2, 1, 1),
+            Direction::BottomRight,
+            map_size,
+        );
+        let union = |item: &CurrentCells| {
+            item.underneath
+                .iter()
+                .chain(&item.behind)
+                .copied()
+                .collect::<HashSet<Cell>>()
+        };
+        assert_eq!(item.influence_mask(), &union(&item));
+
+        item.extend_behind(2, map_size);
+        assert_eq!(item.influence_mask(), &union(&item));
+
+        let item = item.with_inclusive_behind();
+        assert_eq!(item.influence_mask(), &union(&item));
+
+        let item = CurrentCells::from_cells(item.underneath.clone(), 2, map_size);
+        assert_eq!(item.influence_mask(), &union(&item));
+    }
+
+    #[test]
+    fn test_disjoint_masks_do_not_interact() {
+        let map_size = MapSize::new(4, 7);
+        let left = CurrentCells::new(
+            Cell::new(0, 6),
+            UVec3::ONE,
+            Direction::BottomRight,
+            map_size,
+        );
+        let right = CurrentCells::new(
+            Cell::new(3, 6),
+            UVec3::ONE,
+            Direction::BottomRight,
+            map_size,
+        );
+        let above = CurrentCells::new(
+            Cell::new(0, 5),
+            UVec3::ONE,
+            Direction::BottomRight,
+            map_size,
+        );
+
+        assert!(left.influence_mask().is_disjoint(right.influence_mask()));
+        assert!(!left.could_interact(&right));
+        assert!(!left.occludes(&right) && !right.occludes(&left));
+        assert!(left.could_interact(&above));
+    }
+}
+
+#[cfg(test)]
+mod test_explain_cmp {
+    use super::*;
+
+    #[test]
+    fn test_explain_neither_in_front() {
+        let map_size = MapSize::new(4, 7);
+        let left = CurrentCells::new(
+            Cell::new(0, 6),
+            UVec3::ONE,
+            Direction::BottomRight,
+            map_size,
+        );
+        let right = CurrentCells::new(
+            Cell::new(3, 6),
+            UVec3::ONE,
+            Direction::BottomRight,
+            map_size,
+        );
+
+        assert_eq!(left.partial_cmp(&right), None);
+        assert_eq!(left.explain_cmp(&right), CmpExplanation::Neither);
+        assert_eq!(left.explain_cmp(&left.clone()), CmpExplanation::Equal);
+    }
+
+    #[test]
+    fn test_explain_a_in_front() {
+        let map_size = MapSize::new(4, 7);
+        let a = CurrentCells::new(
+            Cell::new(1, 5),
+            UVec3::ONE,
+            Direction::BottomRight,
+            map_size,
+        );
+        let b = CurrentCells::new(
+            Cell::new(2, 4),
+            UVec3::new(2, 1, 1),
+            Direction::BottomRight,
+            map_size,
+        );
+
+        assert_eq!(a.partial_cmp(&b), Some(Ordering::Greater));
+        assert_eq!(
+            a.explain_cmp(&b),
+            CmpExplanation::InFront {
+                hidden: vec![Cell::new(2, 4)]
+            }
+        );
+        assert_eq!(
+            b.explain_cmp(&a),
+            CmpExplanation::Behind {
+                hidden: vec![Cell::new(2, 4)]
+            }
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_influence_report {
+    use super::*;
+
+    #[test]
+    fn test_report_1x1x2() {
+        let map_size = MapSize::new(3, 7);
+        let item = CurrentCells::new(
+            Cell::new(1, 4),
+            UVec3::new(1, 1, 2),
+            Direction::BottomRight,
+            map_size,
+        );
+        let expected = vec![
+            (Cell::new(1, 0), InfluenceKind::Behind),
+            (Cell::new(0, 1), InfluenceKind::Behind),
+            (Cell::new(1, 1), InfluenceKind::Behind),
+            (Cell::new(1, 2), InfluenceKind::Behind),
+            (Cell::new(0, 3), InfluenceKind::Behind),
+            (Cell::new(1, 3), InfluenceKind::Behind),
+            (Cell::new(1, 4), InfluenceKind::Footprint),
+        ];
+        assert_eq!(item.influence_report(), expected);
+
+        let with_shadow = item.influence_report_with_shadow(map_size);
+        let shadow = with_shadow
+            .iter()
+            .filter(|(_, kind)| *kind == InfluenceKind::Shadow)
+            .map(|(cell, _)| *cell)
+            .collect::<Vec<Cell>>();
+        assert_eq!(shadow, item.shadow_cells(map_size));
+        assert_eq!(with_shadow.len(), expected.len() + shadow.len());
+    }
+}
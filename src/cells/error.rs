@@ -0,0 +1,78 @@
+use bevy::{ecs::system::Resource, math::UVec3};
+use std::fmt;
+
+use crate::cells::{
+    cell::{Cell, Direction},
+    map::MapSize,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CellsError {
+    OutOfBounds {
+        cell: Cell,
+        map_size: MapSize,
+    },
+    FootprintOutOfBounds {
+        main_cell: Cell,
+        dimensions: UVec3,
+        map_size: MapSize,
+    },
+    InvalidFacing(Direction),
+}
+
+impl fmt::Display for CellsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OutOfBounds { cell, map_size } => {
+                write!(f, "{cell:?} is outside of a map of size {}", map_size.0)
+            }
+            Self::FootprintOutOfBounds {
+                main_cell,
+                dimensions,
+                map_size,
+            } => write!(
+                f,
+                "The {}x{} footprint on {main_cell:?} doesn't fit in a map of size {}",
+                dimensions.x, dimensions.y, map_size.0
+            ),
+            Self::InvalidFacing(facing) => write!(
+                f,
+                "Items can only face BottomRight or BottomLeft, or TopLeft or TopRight, {facing:?} is not valid"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CellsError {}
+
+// NOTE:
+// - two items that both occlude the other, so neither can be drawn first
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OcclusionCycleError {
+    pub first: Cell,
+    pub second: Cell,
+}
+
+impl fmt::Display for OcclusionCycleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Items cannot be both in front and behind each other, at {:?} and {:?}",
+            self.first, self.second
+        )
+    }
+}
+
+impl std::error::Error for OcclusionCycleError {}
+
+// NOTE:
+// - Panic aborts on items that can't be sorted, which is what tests want
+// - LogAndSkip logs a warning and leaves those items out of the sort instead, so a bad item in a
+//   release build doesn't take the whole app down
+// - items with an invalid facing are caught by CurrentCells::try_new, before they get here
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Resource)]
+pub enum PanicPolicy {
+    #[default]
+    Panic,
+    LogAndSkip,
+}
@@ -0,0 +1,253 @@
+use bevy::{
+    ecs::{
+        entity::Entity,
+        query::{Added, Changed, Without},
+        removal_detection::RemovedComponents,
+        system::{Query, Res, ResMut, Resource},
+    },
+    utils::{HashMap, HashSet},
+};
+
+use crate::cells::{broadphase::BroadphaseGrid, current::CurrentCells, sort::NoOcclusion};
+
+// NOTE:
+// - a cache of which items hide which, for scenes that are mostly static, so that the topological
+//   sort doesn't compare every item with every other one each frame
+// - update_occlusion_graph only finds the edges of the items whose CurrentCells changed, against
+//   the items near them in the BroadphaseGrid, so its cost grows with the number of moving items
+//   and not with the size of the scene
+// - the sort itself still orders all the items each frame, but from the cached edges
+#[derive(Debug, Default, Resource)]
+pub struct OcclusionGraph {
+    in_front: HashMap<Entity, HashSet<Entity>>,
+    behind: HashMap<Entity, HashSet<Entity>>,
+    pub comparisons: usize,
+}
+
+impl OcclusionGraph {
+    pub fn remove(&mut self, entity: Entity) {
+        for other in self.in_front.remove(&entity).into_iter().flatten() {
+            if let Some(behind) = self.behind.get_mut(&other) {
+                behind.remove(&entity);
+            }
+        }
+        for other in self.behind.remove(&entity).into_iter().flatten() {
+            if let Some(in_front) = self.in_front.get_mut(&other) {
+                in_front.remove(&entity);
+            }
+        }
+    }
+
+    // NOTE:
+    // - each edge is (behind, in_front), like the ones the topological sort builds itself
+    pub fn edges(&self) -> impl Iterator<Item = (Entity, Entity)> + '_ {
+        self.in_front.iter().flat_map(|(behind, in_front)| {
+            in_front.iter().map(move |in_front| (*behind, *in_front))
+        })
+    }
+
+    // NOTE:
+    // - the edges between the given items only, leaving out the ones to items that aren't sorted,
+    //   like ghosts and items without height
+    pub(crate) fn edges_between(&self, items: &[(Entity, &CurrentCells)]) -> Vec<(Entity, Entity)> {
+        let sorted = items
+            .iter()
+            .map(|(entity, _)| *entity)
+            .collect::<HashSet<Entity>>();
+        items
+            .iter()
+            .filter_map(|(behind, _)| self.in_front.get(behind).map(|in_front| (behind, in_front)))
+            .flat_map(|(behind, in_front)| {
+                in_front
+                    .iter()
+                    .filter(|in_front| sorted.contains(*in_front))
+                    .map(move |in_front| (*behind, *in_front))
+            })
+            .collect()
+    }
+
+    fn add_edge(&mut self, behind: Entity, in_front: Entity) {
+        self.in_front.entry(behind).or_default().insert(in_front);
+        self.behind.entry(in_front).or_default().insert(behind);
+    }
+}
+
+type ChangedItemsQuery<'w, 's, 'a> =
+    Query<'w, 's, (Entity, &'a CurrentCells), (Changed<CurrentCells>, Without<NoOcclusion>)>;
+
+// NOTE:
+// - must run after update_broadphase, so that the grid knows where the changed items are now
+// - an item that loses NoOcclusion gets its edges back like one that moved, as its CurrentCells
+//   may not have changed since it was left out of the graph
+#[allow(clippy::needless_pass_by_value)]
+pub fn update_occlusion_graph(
+    mut graph: ResMut<OcclusionGraph>,
+    grid: Res<BroadphaseGrid>,
+    changed: ChangedItemsQuery,
+    items: Query<&CurrentCells, Without<NoOcclusion>>,
+    no_occlusion: Query<Entity, Added<NoOcclusion>>,
+    mut removed: RemovedComponents<CurrentCells>,
+    mut restored: RemovedComponents<NoOcclusion>,
+) {
+    graph.comparisons = 0;
+    for entity in (&mut removed).into_iter().chain(no_occlusion.iter()) {
+        graph.remove(entity);
+    }
+    let restored = (&mut restored)
+        .into_iter()
+        .filter_map(|entity| items.get(entity).ok().map(|cells| (entity, cells)));
+    for (entity, cells) in changed.iter().chain(restored) {
+        graph.remove(entity);
+        for other in grid.query_near(cells) {
+            let Ok(other_cells) = items.get(other) else {
+                continue;
+            };
+            if other == entity {
+                continue;
+            }
+            graph.comparisons += 1;
+            if cells.occludes(other_cells) {
+                graph.add_edge(other, entity);
+            }
+            if other_cells.occludes(cells) {
+                graph.add_edge(entity, other);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        ecs::{
+            schedule::{IntoSystemConfig, Schedule},
+            world::World,
+        },
+        math::UVec3,
+    };
+
+    use super::*;
+    use crate::cells::{
+        broadphase::update_broadphase,
+        cell::{Cell, Direction},
+        map::MapSize,
+        saved::{CompareTransforms, SortMethod},
+        sort::sort_items_topological,
+    };
+
+    const MAP_SIZE: MapSize = MapSize(bevy::math::UVec2::new(20, 50));
+
+    fn cells(main_cell: Cell) -> CurrentCells {
+        CurrentCells::new(main_cell, UVec3::ONE, Direction::BottomRight, MAP_SIZE)
+    }
+
+    fn all_edges(world: &mut World) -> HashSet<(Entity, Entity)> {
+        let mut items = world.query::<(Entity, &CurrentCells)>();
+        let items = items.iter(world).collect::<Vec<(Entity, &CurrentCells)>>();
+        let mut edges = HashSet::default();
+        for (entity, cells) in &items {
+            for (other, other_cells) in &items {
+                if entity != other && cells.occludes(other_cells) {
+                    edges.insert((*other, *entity));
+                }
+            }
+        }
+        edges
+    }
+
+    #[test]
+    fn moving_item_only_compares_its_neighbourhood() {
+        let mut world = World::default();
+        world.init_resource::<BroadphaseGrid>();
+        world.init_resource::<OcclusionGraph>();
+        let mut schedule = Schedule::default();
+        schedule
+            .add_system(update_broadphase)
+            .add_system(update_occlusion_graph.after(update_broadphase))
+            .add_system(sort_items_topological.after(update_occlusion_graph));
+        for cell in Cell::window(Cell::new(0, 0), Cell::new(19, 49), MAP_SIZE) {
+            if (cell.x + cell.y) % 2 == 0 {
+                world.spawn((cells(cell), CompareTransforms::default()));
+            }
+        }
+        let moving = world
+            .spawn((cells(Cell::new(9, 25)), CompareTransforms::default()))
+            .id();
+        assert_eq!(world.entities().len(), 501);
+
+        schedule.run(&mut world);
+        let full_comparisons = world.resource::<OcclusionGraph>().comparisons;
+
+        *world.get_mut::<CurrentCells>(moving).unwrap() = cells(Cell::new(10, 26));
+        schedule.run(&mut world);
+
+        let graph = world.resource::<OcclusionGraph>();
+        assert!(graph.comparisons < 100, "{}", graph.comparisons);
+        assert!(graph.comparisons * 50 < full_comparisons);
+        let cached = graph.edges().collect::<HashSet<(Entity, Entity)>>();
+        assert_eq!(cached, all_edges(&mut world));
+
+        let z = |world: &World, entity| {
+            world
+                .get::<CompareTransforms>(entity)
+                .unwrap()
+                .get(SortMethod::Topological)
+        };
+        for (behind, in_front) in cached {
+            assert!(z(&world, behind) < z(&world, in_front));
+        }
+    }
+
+    #[test]
+    fn removed_item_leaves_the_graph() {
+        let mut world = World::default();
+        world.init_resource::<BroadphaseGrid>();
+        world.init_resource::<OcclusionGraph>();
+        let mut schedule = Schedule::default();
+        schedule
+            .add_system(update_broadphase)
+            .add_system(update_occlusion_graph.after(update_broadphase));
+        let behind = world.spawn(cells(Cell::new(1, 2))).id();
+        let in_front = world.spawn(cells(Cell::new(1, 4))).id();
+        schedule.run(&mut world);
+        assert_eq!(
+            world
+                .resource::<OcclusionGraph>()
+                .edges()
+                .collect::<Vec<_>>(),
+            vec![(behind, in_front)]
+        );
+
+        world.despawn(in_front);
+        schedule.run(&mut world);
+        assert_eq!(world.resource::<OcclusionGraph>().edges().count(), 0);
+    }
+
+    #[test]
+    fn item_losing_no_occlusion_rejoins_the_graph() {
+        let mut world = World::default();
+        world.init_resource::<BroadphaseGrid>();
+        world.init_resource::<OcclusionGraph>();
+        let mut schedule = Schedule::default();
+        schedule
+            .add_system(update_broadphase)
+            .add_system(update_occlusion_graph.after(update_broadphase));
+        let behind = world.spawn(cells(Cell::new(1, 2))).id();
+        let in_front = world.spawn(cells(Cell::new(1, 4))).id();
+        schedule.run(&mut world);
+
+        world.entity_mut(in_front).insert(NoOcclusion);
+        schedule.run(&mut world);
+        assert_eq!(world.resource::<OcclusionGraph>().edges().count(), 0);
+
+        world.entity_mut(in_front).remove::<NoOcclusion>();
+        schedule.run(&mut world);
+        assert_eq!(
+            world
+                .resource::<OcclusionGraph>()
+                .edges()
+                .collect::<Vec<_>>(),
+            vec![(behind, in_front)]
+        );
+    }
+}
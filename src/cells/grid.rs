@@ -0,0 +1,239 @@
+use bevy::{
+    ecs::{
+        entity::Entity,
+        query::Without,
+        system::{Query, ResMut, Resource},
+    },
+    math::Vec2,
+    utils::{FloatOrd, HashMap},
+};
+
+use crate::cells::{
+    cell::{Cell, Direction},
+    current::CurrentCells,
+    map::MapSize,
+    sort::NoOcclusion,
+};
+
+// NOTE:
+// - keeps the map size in one place, together with which entities stand on each cell
+#[derive(Clone, Debug, Resource)]
+pub struct CellGrid {
+    pub size: MapSize,
+    pub occupied: HashMap<Cell, Vec<Entity>>,
+}
+
+impl CellGrid {
+    pub fn new(size: MapSize) -> Self {
+        Self {
+            size,
+            occupied: HashMap::default(),
+        }
+    }
+
+    pub fn next_cell(&self, cell: Cell, direction: Direction) -> Option<Cell> {
+        cell.next_cell(direction, self.size)
+    }
+
+    pub fn neighbors(&self, cell: Cell) -> Vec<Cell> {
+        Direction::all()
+            .into_iter()
+            .filter_map(|direction| self.next_cell(cell, direction))
+            .collect()
+    }
+
+    pub fn occupant_at(&self, cell: Cell) -> &[Entity] {
+        self.occupied.get(&cell).map_or(&[], Vec::as_slice)
+    }
+
+    // NOTE:
+    // - the front-most occupant of the cell under a world position, for click to select
+    // - z_of gives the z assigned to an entity by the sort, occupants without one are skipped
+    // - origin is the centre of Cell(0, 0), like in Cell::to_world
+    pub fn topmost_at(
+        &self,
+        world_pos: Vec2,
+        tile_size: Vec2,
+        origin: Vec2,
+        z_of: impl Fn(Entity) -> Option<f32>,
+    ) -> Option<Entity> {
+        let cell = Cell::from_world(world_pos, tile_size, origin, self.size)?;
+        self.occupant_at(cell)
+            .iter()
+            .filter_map(|entity| z_of(*entity).map(|z| (*entity, z)))
+            .max_by_key(|(_, z)| FloatOrd(*z))
+            .map(|(entity, _)| entity)
+    }
+
+    pub fn place(&mut self, entity: Entity, cells: &CurrentCells) {
+        for cell in &cells.underneath {
+            let occupants = self.occupied.entry(*cell).or_default();
+            if !occupants.contains(&entity) {
+                occupants.push(entity);
+            }
+        }
+    }
+
+    pub fn remove(&mut self, entity: Entity) {
+        self.occupied.retain(|_, occupants| {
+            occupants.retain(|occupant| *occupant != entity);
+            !occupants.is_empty()
+        });
+    }
+}
+
+// NOTE:
+// - which items stand on each cell, for the sort to find the items behind another one by looking
+//   up its behind cells, instead of comparing it with every other item
+// - update_cell_index rebuilds it every frame, and must run before the sort systems, as items
+//   missing from it are sorted as if nothing stood behind them
+#[derive(Debug, Default, Resource)]
+pub struct CellIndex {
+    occupants: HashMap<Cell, Vec<Entity>>,
+}
+
+impl CellIndex {
+    pub fn from_items<'c>(items: impl IntoIterator<Item = (Entity, &'c CurrentCells)>) -> Self {
+        let mut occupants: HashMap<Cell, Vec<Entity>> = HashMap::default();
+        for (entity, cells) in items {
+            for cell in &cells.underneath {
+                occupants.entry(*cell).or_default().push(entity);
+            }
+        }
+        Self { occupants }
+    }
+
+    pub fn occupants(&self, cell: Cell) -> &[Entity] {
+        self.occupants.get(&cell).map_or(&[], Vec::as_slice)
+    }
+
+    // NOTE:
+    // - every item standing on any of the cells, once each
+    pub fn standing_on<'c>(&self, cells: impl IntoIterator<Item = &'c Cell>) -> Vec<Entity> {
+        let mut entities = cells
+            .into_iter()
+            .flat_map(|cell| self.occupants(*cell))
+            .copied()
+            .collect::<Vec<Entity>>();
+        entities.sort();
+        entities.dedup();
+        entities
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub fn update_cell_index(
+    mut index: ResMut<CellIndex>,
+    items: Query<(Entity, &CurrentCells), Without<NoOcclusion>>,
+) {
+    *index = CellIndex::from_items(items.iter());
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::UVec3;
+
+    use super::*;
+    use crate::cells::saved::{CompareTransforms, SortMethod};
+
+    #[test]
+    fn place_and_remove_update_occupancy() {
+        let mut grid = CellGrid::new(MapSize::new(3, 7));
+        let entity = Entity::from_raw(0);
+        let cells = CurrentCells::new(
+            Cell::new(1, 4),
+            UVec3::new(2, 1, 1),
+            Direction::BottomRight,
+            grid.size,
+        );
+
+        grid.place(entity, &cells);
+        assert_eq!(grid.occupant_at(Cell::new(1, 4)), &[entity]);
+        assert_eq!(grid.occupant_at(Cell::new(1, 3)), &[entity]);
+        assert!(grid.occupant_at(Cell::new(0, 0)).is_empty());
+
+        grid.remove(entity);
+        assert!(grid.occupant_at(Cell::new(1, 4)).is_empty());
+        assert!(grid.occupied.is_empty());
+    }
+
+    #[test]
+    fn cell_index_lists_overlapping_footprints() {
+        let map_size = MapSize::new(3, 7);
+        let (long, wide) = (Entity::from_raw(0), Entity::from_raw(1));
+        let long_cells = CurrentCells::new(
+            Cell::new(1, 2),
+            UVec3::new(3, 1, 1),
+            Direction::BottomRight,
+            map_size,
+        );
+        let wide_cells = CurrentCells::new(
+            Cell::new(2, 2),
+            UVec3::new(1, 3, 1),
+            Direction::BottomRight,
+            map_size,
+        );
+        let index = CellIndex::from_items([(long, &long_cells), (wide, &wide_cells)]);
+
+        assert_eq!(index.occupants(Cell::new(1, 1)), &[long, wide]);
+        assert_eq!(index.occupants(Cell::new(1, 2)), &[long]);
+        assert_eq!(index.occupants(Cell::new(2, 2)), &[wide]);
+        assert!(index.occupants(Cell::new(0, 6)).is_empty());
+        assert_eq!(index.standing_on(&long_cells.behind), vec![wide]);
+        assert_eq!(
+            index.standing_on(long_cells.underneath.iter()),
+            vec![long, wide]
+        );
+    }
+
+    #[test]
+    fn neighbors_respect_size() {
+        let grid = CellGrid::new(MapSize::new(3, 7));
+        assert_eq!(grid.neighbors(Cell::new(0, 0)).len(), 3);
+        assert_eq!(grid.neighbors(Cell::new(1, 3)).len(), 8);
+        assert_eq!(grid.next_cell(Cell::new(2, 6), Direction::Bottom), None);
+    }
+
+    #[test]
+    fn topmost_at_picks_the_front_item() {
+        let mut grid = CellGrid::new(MapSize::new(4, 7));
+        let (rug, table) = (Entity::from_raw(0), Entity::from_raw(1));
+        let rug_cells = CurrentCells::new(
+            Cell::new(1, 5),
+            UVec3::new(2, 2, 1),
+            Direction::BottomRight,
+            grid.size,
+        );
+        let table_cells = CurrentCells::new(
+            Cell::new(1, 5),
+            UVec3::ONE,
+            Direction::BottomRight,
+            grid.size,
+        );
+        grid.place(rug, &rug_cells);
+        grid.place(table, &table_cells);
+        let mut compare = [CompareTransforms::default(), CompareTransforms::default()];
+        compare[0].set(SortMethod::Topological, 1.);
+        compare[1].set(SortMethod::Topological, 2.);
+        let z_of = |entity: Entity| {
+            compare
+                .get(entity.index() as usize)
+                .map(|compare| compare.get(SortMethod::Topological))
+        };
+
+        let tile_size = Vec2::new(64., 32.);
+        let origin = Vec2::new(320., 160.);
+        let shared = Cell::new(1, 5).to_world(tile_size, origin);
+        assert_eq!(
+            grid.topmost_at(shared, tile_size, origin, z_of),
+            Some(table)
+        );
+        let rug_only = Cell::new(1, 4).to_world(tile_size, origin);
+        assert_eq!(
+            grid.topmost_at(rug_only, tile_size, origin, z_of),
+            Some(rug)
+        );
+        let outside = Vec2::new(-500., 500.);
+        assert_eq!(grid.topmost_at(outside, tile_size, origin, z_of), None);
+    }
+}
@@ -0,0 +1,75 @@
+use bevy::{ecs::entity::Entity, math::UVec3};
+
+use crate::cells::{
+    cell::{Cell, Direction},
+    current::CurrentCells,
+    map::MapSize,
+    saved::SortMethod,
+    sort::sort_order,
+};
+
+// NOTE:
+// - sorts plain CurrentCells with the same functions the systems use, and returns the indices of
+//   the items from the furthest back to the furthest in front
+/// # Panics
+///
+/// Panics if the sort panics for the given method.
+pub fn sort_scene(method: SortMethod, items: &[CurrentCells]) -> Vec<usize> {
+    let entities = (0..items.len())
+        .map(|index| Entity::from_raw(u32::try_from(index).expect("Too many items in the scene")))
+        .collect::<Vec<Entity>>();
+    let items = entities
+        .iter()
+        .copied()
+        .zip(items)
+        .collect::<Vec<(Entity, &CurrentCells)>>();
+    sort_order(method, &items, &[])
+        .into_iter()
+        .map(|entity| entity.index() as usize)
+        .collect()
+}
+
+// NOTE:
+// - a scene of items on one map, to unit test a sort without building a World and a Schedule
+// - items all face BottomRight and are referred to by the order in which they were added
+#[derive(Clone, Debug)]
+pub struct SceneHarness {
+    map_size: MapSize,
+    items: Vec<CurrentCells>,
+}
+
+impl SceneHarness {
+    pub fn new(map_size: MapSize) -> Self {
+        Self {
+            map_size,
+            items: Vec::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_item(mut self, main_cell: Cell, dimensions: UVec3) -> Self {
+        self.add(main_cell, dimensions);
+        self
+    }
+
+    pub fn add(&mut self, main_cell: Cell, dimensions: UVec3) -> usize {
+        self.items.push(CurrentCells::new(
+            main_cell,
+            dimensions,
+            Direction::BottomRight,
+            self.map_size,
+        ));
+        self.items.len() - 1
+    }
+
+    pub fn items(&self) -> &[CurrentCells] {
+        &self.items
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the sort panics for the given method.
+    pub fn sort(&self, method: SortMethod) -> Vec<usize> {
+        sort_scene(method, &self.items)
+    }
+}
@@ -0,0 +1,176 @@
+use bevy::prelude::*;
+
+use crate::cells::{
+    cell::Cell,
+    current::CurrentCells,
+    sort::{brute_force_order, FixedZ, Ghost, NoOcclusion, SortConfig},
+};
+
+type LodItemsQuery<'w, 's, 'a> = Query<
+    'w,
+    's,
+    (Entity, &'a CurrentCells, &'a mut Transform),
+    (Without<Ghost>, Without<FixedZ>, Without<NoOcclusion>),
+>;
+type Band<'a> = Vec<(Entity, &'a CurrentCells)>;
+
+// NOTE:
+// - items within near_distance diagonal steps of the focus cell are sorted exactly, the others
+//   are only sorted by row, which is much cheaper for large scenes
+#[derive(Clone, Copy, Debug, Resource)]
+pub struct LodFocus {
+    pub cell: Cell,
+    pub near_distance: u32,
+}
+
+impl LodFocus {
+    #[allow(clippy::cast_sign_loss)]
+    pub fn is_near(&self, cells: &CurrentCells) -> bool {
+        let delta = (cells.main_cell.diamond_coords() - self.cell.diamond_coords()).abs();
+        delta.max_element() as u32 / 2 <= self.near_distance
+    }
+}
+
+/// # Panics
+///
+/// Panics if two items of the exact band are both in front and behind each other.
+#[allow(clippy::needless_pass_by_value)]
+pub fn sort_items_lod(
+    focus: Res<LodFocus>,
+    config: Option<Res<SortConfig>>,
+    mut items: LodItemsQuery,
+) {
+    let config = config.as_deref().copied().unwrap_or_default();
+    let order = lod_order(
+        &focus,
+        &items
+            .iter()
+            .filter(|(_, cells, _)| cells.dimensions.z > 0)
+            .map(|(entity, cells, _)| (entity, cells))
+            .collect::<Band>(),
+    );
+    let step = config.z_step_spread(order.len());
+    for (index, entity) in order.into_iter().enumerate() {
+        let (_, _, mut transform) = items.get_mut(entity).expect("Entity must exist");
+        transform.translation.z = config.z_at(index, step);
+    }
+}
+
+// NOTE:
+// - far items are in row-major order, and the near items go where the focus cell would be
+//   among them, so the near band gets its own z range without overlapping the far ones
+// - the far items that could interact with a near item, the ring at the edge of the band, are
+//   sorted exactly with the near items, otherwise a far item behind a near one but on a lower row
+//   than the focus cell would be put in front of it
+// - two far items further out can still be in the wrong order, like a tall far item and a far
+//   item on the row below it, that's the precision traded for speed
+// - panics if two items of the exact band are both in front and behind each other
+pub fn lod_order(focus: &LodFocus, items: &[(Entity, &CurrentCells)]) -> Vec<Entity> {
+    let (mut near, far): (Band, Band) = items.iter().partition(|(_, cells)| focus.is_near(cells));
+    let (ring, mut far): (Band, Band) = far.into_iter().partition(|(_, cells)| {
+        near.iter()
+            .any(|(_, near_cells)| near_cells.could_interact(cells))
+    });
+    near.extend(ring);
+    far.sort_by_key(|(entity, cells)| (cells.main_cell, *entity));
+    let n_far_behind = far.partition_point(|(_, cells)| cells.main_cell < focus.cell);
+
+    let far_entities = far.into_iter().map(|(entity, _)| entity);
+    far_entities
+        .clone()
+        .take(n_far_behind)
+        .chain(brute_force_order(&near))
+        .chain(far_entities.skip(n_far_behind))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cells::{cell::Direction, map::MapSize};
+
+    use super::*;
+
+    fn spawn(world: &mut World, main_cell: Cell) -> Entity {
+        let cells = CurrentCells::new(
+            main_cell,
+            UVec3::ONE,
+            Direction::BottomRight,
+            MapSize::new(16, 32),
+        );
+        world.spawn((cells, Transform::default())).id()
+    }
+
+    #[test]
+    fn near_band_is_exact_and_far_band_is_by_row() {
+        let mut world = World::default();
+        let focus = LodFocus {
+            cell: Cell::new(8, 16),
+            near_distance: 2,
+        };
+        world.insert_resource(focus);
+        let far_top = spawn(&mut world, Cell::new(1, 2));
+        let far_top_right = spawn(&mut world, Cell::new(12, 2));
+        let near_behind = spawn(&mut world, Cell::new(8, 15));
+        let near_in_front = spawn(&mut world, Cell::new(8, 16));
+        let far_bottom = spawn(&mut world, Cell::new(2, 30));
+
+        let mut schedule = Schedule::default();
+        schedule.add_system(sort_items_lod);
+        schedule.run(&mut world);
+
+        let z = |entity| world.get::<Transform>(entity).unwrap().translation.z;
+        let mut by_z = [
+            far_top,
+            far_top_right,
+            near_behind,
+            near_in_front,
+            far_bottom,
+        ];
+        by_z.sort_by(|a, b| z(*a).total_cmp(&z(*b)));
+        assert_eq!(
+            by_z,
+            [
+                far_top,
+                far_top_right,
+                near_behind,
+                near_in_front,
+                far_bottom
+            ]
+        );
+    }
+    #[test]
+    fn far_item_at_the_band_edge_keeps_its_exact_order() {
+        let map_size = MapSize::new(16, 32);
+        let focus = LodFocus {
+            cell: Cell::new(8, 16),
+            near_distance: 2,
+        };
+        let step = |coords, direction: Direction| coords + direction.diamond_step();
+        let near_coords = step(
+            step(focus.cell.diamond_coords(), Direction::BottomRight),
+            Direction::BottomRight,
+        );
+        let far_coords = step(
+            step(
+                step(near_coords, Direction::TopRight),
+                Direction::BottomRight,
+            ),
+            Direction::BottomRight,
+        );
+        let build = |coords, dims| {
+            let main_cell = Cell::from_diamond_coords(coords, map_size).unwrap();
+            CurrentCells::new(main_cell, dims, Direction::BottomRight, map_size)
+        };
+        let near = build(near_coords, UVec3::ONE);
+        let far = build(far_coords, UVec3::new(1, 4, 1));
+        assert!(focus.is_near(&near));
+        assert!(!focus.is_near(&far));
+        assert!(far.main_cell > focus.cell);
+
+        let mut world = World::default();
+        let [near_entity, far_entity] = [(); 2].map(|()| world.spawn_empty().id());
+        let items = [(near_entity, &near), (far_entity, &far)];
+        assert_eq!(lod_order(&focus, &items), brute_force_order(&items));
+        assert_eq!(lod_order(&focus, &items), vec![far_entity, near_entity]);
+    }
+}
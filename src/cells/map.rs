@@ -0,0 +1,144 @@
+use bevy::{
+    ecs::{
+        change_detection::DetectChanges,
+        component::Component,
+        query::Changed,
+        system::{Commands, Query, Res, Resource},
+    },
+    math::{IVec2, UVec2},
+};
+
+use crate::cells::current::CurrentCells;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Resource)]
+pub struct MapSize(pub UVec2);
+
+impl MapSize {
+    pub fn new(x: u32, y: u32) -> Self {
+        Self(UVec2::new(x, y))
+    }
+
+    pub fn as_ivec2(self) -> IVec2 {
+        self.0.as_ivec2()
+    }
+}
+
+impl From<UVec2> for MapSize {
+    fn from(size: UVec2) -> Self {
+        Self(size)
+    }
+}
+
+// NOTE:
+// - put this on the tilemap entity, and sync_map_size keeps the MapSize resource in step with it
+#[derive(Clone, Copy, Debug, Component)]
+pub struct MapGrid {
+    pub size: UVec2,
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub fn sync_map_size(mut commands: Commands, grids: Query<&MapGrid, Changed<MapGrid>>) {
+    if let Ok(grid) = grids.get_single() {
+        commands.insert_resource(MapSize(grid.size));
+    }
+}
+
+// NOTE:
+// - footprints are clamped to the map, so they have to be rebuilt when the map changes size
+// - the rebuilt items keep their shape, transparency and behind directions
+pub fn rebuild_current_cells(map_size: Option<Res<MapSize>>, mut items: Query<&mut CurrentCells>) {
+    let Some(map_size) = map_size else {
+        return;
+    };
+    if !map_size.is_changed() {
+        return;
+    }
+    for mut cells in &mut items {
+        *cells = cells.rebuilt(cells.main_cell, cells.facing, *map_size);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        ecs::{
+            schedule::{apply_system_buffers, IntoSystemConfigs, Schedule},
+            world::World,
+        },
+        math::UVec3,
+    };
+
+    use super::*;
+    use crate::cells::{
+        cell::{Cell, Direction},
+        current::BehindDirections,
+    };
+
+    #[test]
+    fn map_size_from_uvec2() {
+        let map_size: MapSize = UVec2::new(3, 7).into();
+        assert_eq!(map_size, MapSize::new(3, 7));
+        assert_eq!(map_size.as_ivec2(), IVec2::new(3, 7));
+    }
+
+    #[test]
+    fn map_grid_size_propagates() {
+        let mut world = World::default();
+        let grid = world
+            .spawn(MapGrid {
+                size: UVec2::new(3, 7),
+            })
+            .id();
+        let mut schedule = Schedule::default();
+        schedule.add_systems((sync_map_size, apply_system_buffers, rebuild_current_cells).chain());
+
+        schedule.run(&mut world);
+        let dims = UVec3::new(2, 2, 1);
+        let build = |world: &World| {
+            let map_size = *world.resource::<MapSize>();
+            CurrentCells::new(Cell::new(2, 3), dims, Direction::BottomRight, map_size)
+        };
+        let item = world.spawn(build(&world)).id();
+        assert_eq!(build(&world).underneath.len(), 3);
+
+        world.get_mut::<MapGrid>(grid).unwrap().size = UVec2::new(5, 7);
+        schedule.run(&mut world);
+        assert_eq!(*world.resource::<MapSize>(), MapSize::new(5, 7));
+        assert_eq!(build(&world).underneath.len(), 4);
+        assert_eq!(world.get::<CurrentCells>(item).unwrap().underneath.len(), 4);
+    }
+
+    #[test]
+    fn rebuild_keeps_behind_directions() {
+        let mut world = World::default();
+        let grid = world
+            .spawn(MapGrid {
+                size: UVec2::new(3, 7),
+            })
+            .id();
+        let mut schedule = Schedule::default();
+        schedule.add_systems((sync_map_size, apply_system_buffers, rebuild_current_cells).chain());
+        schedule.run(&mut world);
+
+        let behind_directions = BehindDirections(vec![Direction::TopLeft]);
+        let build = |world: &World| {
+            let map_size = *world.resource::<MapSize>();
+            CurrentCells::new_with_behind_directions(
+                Cell::new(1, 4),
+                UVec3::new(1, 1, 1),
+                Direction::BottomRight,
+                map_size,
+                &behind_directions,
+            )
+        };
+        let item = world.spawn(build(&world)).id();
+
+        world.get_mut::<MapGrid>(grid).unwrap().size = UVec2::new(5, 9);
+        schedule.run(&mut world);
+        assert_eq!(world.get::<CurrentCells>(item).unwrap(), &build(&world));
+        assert_eq!(
+            world.get::<CurrentCells>(item).unwrap().behind_directions,
+            Some(behind_directions.clone())
+        );
+    }
+}
@@ -1,4 +1,14 @@
+pub mod broadphase;
 pub mod cell;
 pub mod current;
+pub mod error;
+pub mod footprint;
+pub mod graph;
+pub mod grid;
+pub mod harness;
+pub mod lod;
+pub mod map;
+pub mod order;
+pub mod plugin;
 pub mod saved;
 pub mod sort;
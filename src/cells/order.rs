@@ -0,0 +1,138 @@
+use bevy::{ecs::component::Component, prelude::Entity, utils::HashMap};
+
+use crate::cells::{current::CurrentCells, saved::SortMethod, sort::sort_order};
+
+// NOTE:
+// - an id that is the same for the same item on every client, unlike Entity
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Component)]
+pub struct StableId(pub u64);
+
+// NOTE:
+// - the sorted items, from the furthest back to the furthest in front, by StableId
+// - the items are given to the sort by StableId, and every method keeps that order between the
+//   items it leaves free to go in any order, so that the same items give the same order in every
+//   world, whatever their Entity
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SortOrder(pub Vec<StableId>);
+
+impl SortOrder {
+    /// # Panics
+    ///
+    /// Panics if the sort panics for the given method.
+    pub fn compute(method: SortMethod, items: &[(Entity, StableId, &CurrentCells)]) -> Self {
+        let mut items = items.to_vec();
+        items.sort_by_key(|(_, id, _)| *id);
+        let stable_ids = items
+            .iter()
+            .map(|(entity, id, _)| (*entity, *id))
+            .collect::<HashMap<Entity, StableId>>();
+        let cells = items
+            .iter()
+            .map(|(entity, _, cells)| (*entity, *cells))
+            .collect::<Vec<(Entity, &CurrentCells)>>();
+        let order = sort_order(method, &cells, &[])
+            .into_iter()
+            .map(|entity| stable_ids[&entity])
+            .collect();
+        Self(order)
+    }
+
+    // NOTE:
+    // - each id is written as 8 little endian bytes
+    pub fn serialize(&self) -> Vec<u8> {
+        self.0.iter().flat_map(|id| id.0.to_le_bytes()).collect()
+    }
+
+    // NOTE:
+    // - returns None if the bytes don't hold a whole number of ids
+    pub fn deserialize(bytes: &[u8]) -> Option<Self> {
+        let chunks = bytes.chunks_exact(8);
+        if !chunks.remainder().is_empty() {
+            return None;
+        }
+        let ids = chunks
+            .map(|chunk| {
+                let mut id = [0; 8];
+                id.copy_from_slice(chunk);
+                StableId(u64::from_le_bytes(id))
+            })
+            .collect();
+        Some(Self(ids))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        ecs::world::World,
+        math::{UVec2, UVec3},
+    };
+
+    use super::*;
+    use crate::cells::{
+        cell::{Cell, Direction},
+        map::MapSize,
+    };
+
+    const ITEMS: [(u64, UVec2); 7] = [
+        (40, UVec2::new(1, 5)),
+        (10, UVec2::new(1, 4)),
+        (30, UVec2::new(2, 4)),
+        (20, UVec2::new(1, 3)),
+        (50, UVec2::new(1, 2)),
+        (70, UVec2::new(2, 4)),
+        (60, UVec2::new(0, 0)),
+    ];
+
+    fn order(method: SortMethod, spawn_order: impl Iterator<Item = usize>) -> SortOrder {
+        let mut world = World::default();
+        for index in spawn_order {
+            let (id, cell) = ITEMS[index];
+            let cells = CurrentCells::new(
+                Cell::new(cell.x, cell.y),
+                UVec3::ONE,
+                Direction::BottomRight,
+                MapSize::new(3, 6),
+            );
+            world.spawn((StableId(id), cells));
+        }
+        let mut query = world.query::<(Entity, &StableId, &CurrentCells)>();
+        let items = query
+            .iter(&world)
+            .map(|(entity, id, cells)| (entity, *id, cells))
+            .collect::<Vec<(Entity, StableId, &CurrentCells)>>();
+        SortOrder::compute(method, &items)
+    }
+
+    #[test]
+    fn same_stable_ids_give_same_order() {
+        let forwards = order(SortMethod::Topological, 0..ITEMS.len()).serialize();
+        let backwards = order(SortMethod::Topological, (0..ITEMS.len()).rev()).serialize();
+
+        assert_eq!(forwards, backwards);
+        assert_eq!(forwards.len(), ITEMS.len() * 8);
+    }
+
+    #[test]
+    fn spawn_order_does_not_change_any_method() {
+        let shuffled = [3, 0, 6, 2, 5, 1, 4];
+        for method in SortMethod::all() {
+            let forwards = order(method, 0..ITEMS.len());
+            assert_eq!(forwards.0.len(), ITEMS.len());
+            assert_eq!(
+                forwards,
+                order(method, (0..ITEMS.len()).rev()),
+                "{method:?}"
+            );
+            assert_eq!(forwards, order(method, shuffled.into_iter()), "{method:?}");
+        }
+    }
+
+    #[test]
+    fn serialize_round_trip() {
+        let order = SortOrder(vec![StableId(3), StableId(u64::MAX), StableId(0)]);
+
+        assert_eq!(SortOrder::deserialize(&order.serialize()), Some(order));
+        assert_eq!(SortOrder::deserialize(&[0; 7]), None);
+    }
+}
@@ -0,0 +1,104 @@
+use bevy::{prelude::*, transform::TransformSystem};
+
+use crate::cells::{
+    broadphase::{update_broadphase, BroadphaseGrid},
+    cell::{Cell, Direction},
+    graph::{update_occlusion_graph, OcclusionGraph},
+    grid::{update_cell_index, CellIndex},
+    saved::{SavedCells, SortMethod},
+    sort::{clear_dynamic_edges, insert_sort_rank, sort_items, ActiveSortMethod},
+};
+
+// NOTE:
+// - set it to false to skip the sort, like while a menu covers the scene, the items then keep the
+//   z they had
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Resource)]
+pub struct SortThisFrame(pub bool);
+
+impl Default for SortThisFrame {
+    fn default() -> Self {
+        Self(true)
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn sort_this_frame(sort: Res<SortThisFrame>) -> bool {
+    sort.0
+}
+
+// NOTE:
+// - sorts the items with sort_items, which writes their z straight into Transform
+// - the sort runs in PostUpdate, after the items have moved in Update and before their Transform
+//   is propagated, so the z is right on the frame they moved
+// - the method can still be changed later through ActiveSortMethod
+// - the DynamicEdges are cleared after the sort, on every frame, including the ones that skip it
+// - with_occlusion_graph keeps an OcclusionGraph up to date every frame, even the ones that skip
+//   the sort, so that it never misses an item that moved, and the topological sort then takes
+//   its edges from it
+// - without it, a CellIndex is rebuilt every frame before the sort instead, for the topological
+//   sort to look up the items behind each item. Only one of the two is kept, as the graph already
+//   holds every edge the index would find
+pub struct IsometricSortPlugin {
+    method: SortMethod,
+    occlusion_graph: bool,
+}
+
+impl IsometricSortPlugin {
+    pub fn new(method: SortMethod) -> Self {
+        Self {
+            method,
+            occlusion_graph: false,
+        }
+    }
+
+    #[must_use]
+    pub fn with_occlusion_graph(mut self) -> Self {
+        self.occlusion_graph = true;
+        self
+    }
+}
+
+impl Default for IsometricSortPlugin {
+    fn default() -> Self {
+        Self::new(ActiveSortMethod::default().0)
+    }
+}
+
+impl Plugin for IsometricSortPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Cell>()
+            .register_type::<Direction>()
+            .register_type::<SavedCells>()
+            .insert_resource(ActiveSortMethod(self.method))
+            .init_resource::<SortThisFrame>()
+            .add_system(insert_sort_rank.in_base_set(CoreSet::PostUpdate))
+            .add_system(
+                sort_items
+                    .run_if(sort_this_frame)
+                    .in_base_set(CoreSet::PostUpdate)
+                    .before(TransformSystem::TransformPropagate),
+            )
+            .add_system(
+                clear_dynamic_edges
+                    .in_base_set(CoreSet::PostUpdate)
+                    .after(sort_items),
+            );
+        if self.occlusion_graph {
+            app.init_resource::<BroadphaseGrid>()
+                .init_resource::<OcclusionGraph>()
+                .add_system(update_broadphase.in_base_set(CoreSet::PostUpdate))
+                .add_system(
+                    update_occlusion_graph
+                        .in_base_set(CoreSet::PostUpdate)
+                        .after(update_broadphase)
+                        .before(sort_items),
+                );
+        } else {
+            app.init_resource::<CellIndex>().add_system(
+                update_cell_index
+                    .in_base_set(CoreSet::PostUpdate)
+                    .before(sort_items),
+            );
+        }
+    }
+}
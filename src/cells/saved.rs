@@ -1,16 +1,29 @@
 use bevy::{
-    ecs::{component::Component, entity::Entity, reflect::ReflectComponent, system::Resource},
+    ecs::{
+        component::Component,
+        entity::Entity,
+        query::Without,
+        reflect::ReflectComponent,
+        system::{Commands, Query, Res, ResMut, Resource},
+    },
+    log::warn,
     math::UVec3,
     reflect::Reflect,
-    utils::HashMap,
+    render::color::Color,
+    scene::{DynamicEntity, DynamicScene},
+    sprite::Sprite,
+    utils::FloatOrd,
 };
+use std::{collections::BTreeMap, fmt::Write, time::Duration};
 
 use crate::cells::{
     cell::{Cell, Direction},
     current::CurrentCells,
+    map::MapSize,
 };
 
-#[derive(Component, Reflect)]
+#[derive(Clone, Copy, Debug, PartialEq, Component, Reflect)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[reflect(Component)]
 pub struct SavedCells {
     pub main_cell: Cell,
@@ -38,6 +51,41 @@ impl From<&CurrentCells> for SavedCells {
     }
 }
 
+// NOTE:
+// - a scene holding only the SavedCells of each item, numbered in the order they are given
+// - Transforms and Entity ids change from run to run, so leaving them out means that saving an
+//   unchanged level gives the same file, and moving one item only changes the lines of that item
+// - the fields of SavedCells are written in a fixed order, with facing as a bare variant name
+pub fn level_scene<'s>(items: impl IntoIterator<Item = &'s SavedCells>) -> DynamicScene {
+    let entities = items
+        .into_iter()
+        .zip(0..)
+        .map(|(saved, entity)| DynamicEntity {
+            entity,
+            components: vec![Box::new(*saved)],
+        })
+        .collect();
+    DynamicScene { entities }
+}
+
+// NOTE:
+// - scenes only store SavedCells, so this rebuilds the CurrentCells of the items they spawn
+// - SavedCells are kept, so that the scene can be saved again
+#[allow(clippy::needless_pass_by_value)]
+pub fn saved_to_current_cells(
+    mut commands: Commands,
+    map_size: Option<Res<MapSize>>,
+    saved: Query<(Entity, &SavedCells), Without<CurrentCells>>,
+) {
+    let Some(map_size) = map_size else {
+        return;
+    };
+    for (entity, saved) in saved.iter() {
+        let current = CurrentCells::new(saved.main_cell, saved.dimensions, saved.facing, *map_size);
+        commands.entity(entity).insert(current);
+    }
+}
+
 #[derive(Component)]
 pub struct Check;
 
@@ -48,39 +96,195 @@ pub struct EntitiesNearby {
     pub behind: Vec<Entity>,
 }
 
-#[derive(Debug, Component)]
+impl EntitiesNearby {
+    // NOTE:
+    // - the nearby items that the method placed on the wrong side of the corresponding item
+    // - items without CompareTransforms are skipped, like in check_z
+    pub fn misordered<'c>(
+        &self,
+        method: SortMethod,
+        compare_of: impl Fn(Entity) -> Option<&'c CompareTransforms>,
+    ) -> Vec<Misordered> {
+        let Some(corresponding) = compare_of(self.corresponding) else {
+            return Vec::new();
+        };
+        let behind = self.behind.iter().map(|entity| (*entity, true));
+        let in_front = self.in_front.iter().map(|entity| (*entity, false));
+        behind
+            .chain(in_front)
+            .filter_map(|(entity, should_be_behind)| {
+                let z_delta = compare_of(entity)?.z_delta(corresponding, method);
+                let is_correct = if should_be_behind {
+                    z_delta < 0.
+                } else {
+                    z_delta > 0.
+                };
+                (!is_correct).then_some(Misordered {
+                    entity,
+                    should_be_behind,
+                    z_delta,
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Misordered {
+    pub entity: Entity,
+    pub should_be_behind: bool,
+    pub z_delta: f32,
+}
+
+// NOTE:
+// - one z per SortMethod, indexed by the method itself, so that each item doesn't need its own map
+// - every z starts at 0.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Component)]
 pub struct CompareTransforms {
-    pub map: HashMap<SortMethod, f32>,
+    zs: [f32; SortMethod::COUNT],
 }
 
-impl Default for CompareTransforms {
-    fn default() -> Self {
-        Self {
-            map: SortMethod::all()
-                .iter()
-                .fold(HashMap::new(), |mut map, method| {
-                    map.insert(*method, 0.);
-                    map
-                }),
+impl CompareTransforms {
+    pub fn get(&self, method: SortMethod) -> f32 {
+        self.zs[method as usize]
+    }
+
+    pub fn set(&mut self, method: SortMethod, z: f32) {
+        self.zs[method as usize] = z;
+    }
+
+    pub fn z_delta(&self, other: &Self, method: SortMethod) -> f32 {
+        self.get(method) - other.get(method)
+    }
+
+    // NOTE:
+    // - the z of every method in all(), like the map this used to store them in
+    pub fn map(&self) -> BTreeMap<SortMethod, f32> {
+        SortMethod::all()
+            .into_iter()
+            .map(|method| (method, self.get(method)))
+            .collect()
+    }
+}
+
+pub const DIVERGENT_COLOR: Color = Color::RED;
+pub const CONVERGENT_COLOR: Color = Color::WHITE;
+
+pub fn highlight_divergent_ranks(mut items: Query<(Entity, &CompareTransforms, &mut Sprite)>) {
+    let divergent = divergent_ranks(items.iter().map(|(entity, compare, _)| (entity, compare)));
+    for (entity, _, mut sprite) in &mut items {
+        sprite.color = if divergent.contains(&entity) {
+            DIVERGENT_COLOR
+        } else {
+            CONVERGENT_COLOR
+        };
+    }
+}
+
+pub fn divergent_ranks<'c>(
+    items: impl Iterator<Item = (Entity, &'c CompareTransforms)>,
+) -> Vec<Entity> {
+    let items = items.collect::<Vec<(Entity, &CompareTransforms)>>();
+    let ranks = SortMethod::all().map(|method| {
+        let mut ranked = items
+            .iter()
+            .map(|(entity, compare)| (*entity, compare.get(method)))
+            .collect::<Vec<(Entity, f32)>>();
+        ranked.sort_by_key(|(_, z)| FloatOrd(*z));
+        ranked
+            .into_iter()
+            .map(|(entity, _)| entity)
+            .collect::<Vec<Entity>>()
+    });
+
+    items
+        .iter()
+        .map(|(entity, _)| *entity)
+        .filter(|entity| {
+            let mut method_ranks = ranks.iter().map(|ranked| {
+                ranked
+                    .iter()
+                    .position(|ranked_entity| ranked_entity == entity)
+            });
+            let first_rank = method_ranks.next().flatten();
+            method_ranks.any(|rank| rank != first_rank)
+        })
+        .collect()
+}
+
+// NOTE:
+// - for development builds: while this resource exists, cross_check_sort compares the z that
+//   the Topological and PartialCmp systems stored in CompareTransforms every frame
+// - only pairs where one item hides the other are checked, as the methods are free to order the
+//   other pairs differently
+// - disagreements holds the pairs of the last check, each as (in_front, behind)
+#[derive(Debug, Default, Resource)]
+pub struct CrossCheckSort {
+    pub disagreements: Vec<(Entity, Entity)>,
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub fn cross_check_sort(
+    check: Option<ResMut<CrossCheckSort>>,
+    items: Query<(Entity, &CurrentCells, &CompareTransforms)>,
+) {
+    let Some(mut check) = check else {
+        return;
+    };
+    check.disagreements.clear();
+    let items = items
+        .iter()
+        .collect::<Vec<(Entity, &CurrentCells, &CompareTransforms)>>();
+    for (index, (this_entity, this_cells, this_compare)) in items.iter().enumerate() {
+        for (other_entity, other_cells, other_compare) in &items[index + 1..] {
+            let pair = if this_cells.occludes(other_cells) {
+                (*this_entity, *other_entity)
+            } else if other_cells.occludes(this_cells) {
+                (*other_entity, *this_entity)
+            } else {
+                continue;
+            };
+            let is_in_front = |method| this_compare.z_delta(other_compare, method) > 0.;
+            if is_in_front(SortMethod::Topological) != is_in_front(SortMethod::PartialCmp) {
+                warn!(
+                    "Topological and PartialCmp disagree on {:?} in front of {:?}",
+                    pair.0, pair.1
+                );
+                check.disagreements.push(pair);
+            }
         }
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum SortMethod {
     Topological,
     PartialCmp,
+    BruteForceExact,
+    // NOTE:
+    // - leaves z untouched, it isn't part of all() as there is nothing to compare
+    None,
 }
 
 impl SortMethod {
-    pub fn all() -> [Self; 2] {
-        [Self::Topological, Self::PartialCmp]
+    // NOTE:
+    // - every variant in declaration order, so that each one's index is the variant as usize
+    pub const VARIANTS: [Self; 4] = [
+        Self::Topological,
+        Self::PartialCmp,
+        Self::BruteForceExact,
+        Self::None,
+    ];
+    pub const COUNT: usize = Self::VARIANTS.len();
+
+    pub fn all() -> [Self; 3] {
+        [Self::Topological, Self::PartialCmp, Self::BruteForceExact]
     }
 }
 
 #[derive(Debug, Resource)]
 pub struct Results {
-    pub map: HashMap<SortMethod, Vec<Corrects>>,
+    pub map: BTreeMap<SortMethod, Vec<Corrects>>,
 }
 
 impl Default for Results {
@@ -88,7 +292,7 @@ impl Default for Results {
         Self {
             map: SortMethod::all()
                 .iter()
-                .fold(HashMap::new(), |mut map, method| {
+                .fold(BTreeMap::new(), |mut map, method| {
                     map.insert(*method, Vec::new());
                     map
                 }),
@@ -96,6 +300,39 @@ impl Default for Results {
     }
 }
 
+impl Results {
+    pub fn report(&self) -> String {
+        self.map
+            .iter()
+            .fold(String::new(), |mut report, (method, corrects)| {
+                writeln!(report, "======================").unwrap();
+                writeln!(report, "{method:?}").unwrap();
+                for corrects in corrects {
+                    writeln!(report, "{}", corrects.are_both_true()).unwrap();
+                }
+                report
+            })
+    }
+}
+
+// NOTE:
+// - how long the last run of each sort system took, next to how correct it was in Results
+#[derive(Debug, Default, Resource)]
+pub struct Timings {
+    pub map: BTreeMap<SortMethod, Duration>,
+}
+
+impl Timings {
+    pub fn report(&self) -> String {
+        self.map
+            .iter()
+            .fold(String::new(), |mut report, (method, duration)| {
+                writeln!(report, "{method:?}: {duration:?}").unwrap();
+                report
+            })
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct Corrects {
     pub all_behind: bool,
@@ -107,3 +344,315 @@ impl Corrects {
         self.all_behind && self.all_in_front
     }
 }
+
+#[cfg(test)]
+mod test_compare_transforms {
+    use super::*;
+
+    #[test]
+    #[allow(clippy::cast_precision_loss, clippy::float_cmp)]
+    fn get_set_round_trip() {
+        let mut compare = CompareTransforms::default();
+        let methods = SortMethod::VARIANTS;
+        for method in methods {
+            assert_eq!(compare.get(method), 0.);
+        }
+        for (index, method) in methods.into_iter().enumerate() {
+            compare.set(method, index as f32 + 0.5);
+        }
+        for (index, method) in methods.into_iter().enumerate() {
+            assert_eq!(compare.get(method), index as f32 + 0.5);
+        }
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn map_holds_every_compared_method() {
+        let mut compare = CompareTransforms::default();
+        compare.set(SortMethod::PartialCmp, 2.);
+        let map = compare.map();
+        assert_eq!(map.keys().copied().collect::<Vec<_>>(), SortMethod::all());
+        assert_eq!(map[&SortMethod::PartialCmp], 2.);
+        assert_eq!(map[&SortMethod::Topological], 0.);
+    }
+
+    #[test]
+    fn count_matches_the_variants() {
+        assert_eq!(SortMethod::COUNT, SortMethod::VARIANTS.len());
+        for (index, method) in SortMethod::VARIANTS.into_iter().enumerate() {
+            assert_eq!(method as usize, index);
+        }
+        let compared = SortMethod::VARIANTS
+            .into_iter()
+            .filter(|method| *method != SortMethod::None)
+            .collect::<Vec<SortMethod>>();
+        assert_eq!(SortMethod::COUNT, SortMethod::all().len() + 1);
+        assert_eq!(compared, SortMethod::all());
+    }
+}
+
+#[cfg(test)]
+mod test_divergent_ranks {
+    use bevy::ecs::{schedule::Schedule, world::World};
+
+    use super::*;
+
+    fn compare(topological: f32, partial_cmp: f32) -> CompareTransforms {
+        let mut compare = CompareTransforms::default();
+        compare.set(SortMethod::Topological, topological);
+        compare.set(SortMethod::PartialCmp, partial_cmp);
+        compare
+    }
+
+    #[test]
+    fn highlight_only_divergent_items() {
+        let mut world = World::default();
+        let matching = world.spawn((compare(0., 0.), Sprite::default())).id();
+        let swapped_a = world.spawn((compare(1., 2.), Sprite::default())).id();
+        let swapped_b = world.spawn((compare(2., 1.), Sprite::default())).id();
+
+        let mut schedule = Schedule::default();
+        schedule.add_system(highlight_divergent_ranks);
+        schedule.run(&mut world);
+
+        let color = |entity| world.get::<Sprite>(entity).unwrap().color;
+        assert_eq!(color(matching), CONVERGENT_COLOR);
+        assert_eq!(color(swapped_a), DIVERGENT_COLOR);
+        assert_eq!(color(swapped_b), DIVERGENT_COLOR);
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::float_cmp)]
+mod test_misordered {
+    use bevy::utils::HashMap;
+
+    use super::*;
+
+    fn compare(z: f32) -> CompareTransforms {
+        let mut compare = CompareTransforms::default();
+        compare.set(SortMethod::Topological, z);
+        compare
+    }
+
+    #[test]
+    fn z_delta_per_method() {
+        let mut a = compare(3.);
+        a.set(SortMethod::PartialCmp, 1.);
+        let b = compare(1.);
+        assert_eq!(a.z_delta(&b, SortMethod::Topological), 2.);
+        assert_eq!(a.z_delta(&b, SortMethod::PartialCmp), 1.);
+    }
+
+    #[test]
+    fn flags_misordered_item() {
+        let corresponding = Entity::from_raw(0);
+        let behind_ok = Entity::from_raw(1);
+        let behind_wrong = Entity::from_raw(2);
+        let in_front_ok = Entity::from_raw(3);
+        let zs = HashMap::from_iter([
+            (corresponding, compare(2.)),
+            (behind_ok, compare(1.)),
+            (behind_wrong, compare(2.5)),
+            (in_front_ok, compare(3.)),
+        ]);
+        let check = EntitiesNearby {
+            corresponding,
+            in_front: vec![in_front_ok],
+            behind: vec![behind_ok, behind_wrong],
+        };
+
+        let actual = check.misordered(SortMethod::Topological, |entity| zs.get(&entity));
+        let expected = vec![Misordered {
+            entity: behind_wrong,
+            should_be_behind: true,
+            z_delta: 0.5,
+        }];
+        assert_eq!(actual, expected);
+    }
+}
+
+#[cfg(test)]
+mod test_results {
+    use super::*;
+
+    fn results() -> Results {
+        let mut results = Results::default();
+        for (method, all_behind) in [
+            (SortMethod::PartialCmp, false),
+            (SortMethod::Topological, true),
+        ] {
+            results.map.get_mut(&method).unwrap().push(Corrects {
+                all_behind,
+                all_in_front: true,
+            });
+        }
+        results
+    }
+
+    #[test]
+    fn report_is_stable() {
+        let expected = "======================\nTopological\ntrue\n\
+            ======================\nPartialCmp\nfalse\n\
+            ======================\nBruteForceExact\n";
+        assert_eq!(results().report(), expected);
+        assert_eq!(results().report(), results().report());
+    }
+}
+
+#[cfg(test)]
+mod test_scene_round_trip {
+    use bevy::{
+        app::AppTypeRegistry,
+        ecs::{
+            entity::EntityMap,
+            schedule::{apply_system_buffers, IntoSystemConfigs, Schedule},
+            world::World,
+        },
+        scene::{serde::SceneDeserializer, DynamicScene},
+    };
+    use serde::de::DeserializeSeed;
+
+    use super::*;
+    use crate::cells::sort::{
+        insert_compare_transforms, sort_items_partial_cmp, sort_items_topological,
+    };
+
+    const ITEMS: [(Cell, UVec3); 6] = [
+        (Cell { x: 0, y: 3 }, UVec3::new(2, 2, 1)),
+        (Cell { x: 1, y: 6 }, UVec3::new(1, 2, 1)),
+        (Cell { x: 2, y: 1 }, UVec3::new(1, 1, 2)),
+        (Cell { x: 1, y: 5 }, UVec3::new(1, 1, 2)),
+        (Cell { x: 0, y: 6 }, UVec3::new(1, 1, 1)),
+        (Cell { x: 2, y: 3 }, UVec3::new(1, 3, 1)),
+    ];
+
+    fn empty_world() -> World {
+        let mut world = World::default();
+        let registry = AppTypeRegistry::default();
+        {
+            let mut registry = registry.write();
+            registry.register::<Cell>();
+            registry.register::<Direction>();
+            registry.register::<SavedCells>();
+            registry.register::<UVec3>();
+        }
+        world.insert_resource(registry);
+        world.insert_resource(MapSize::new(4, 7));
+        world
+    }
+
+    fn load(ron: &str, registry: &AppTypeRegistry) -> World {
+        let mut world = empty_world();
+        let mut deserializer = ron::de::Deserializer::from_str(ron).unwrap();
+        let scene = SceneDeserializer {
+            type_registry: &registry.read(),
+        }
+        .deserialize(&mut deserializer)
+        .unwrap();
+        scene
+            .write_to_world(&mut world, &mut EntityMap::default())
+            .unwrap();
+        world
+    }
+
+    fn save_level(world: &mut World, registry: &AppTypeRegistry) -> String {
+        let mut query = world.query::<&SavedCells>();
+        level_scene(query.iter(world))
+            .serialize_ron(&registry.0)
+            .unwrap()
+    }
+
+    fn sort(world: &mut World) {
+        let mut schedule = Schedule::default();
+        schedule.add_systems(
+            (
+                saved_to_current_cells,
+                apply_system_buffers,
+                insert_compare_transforms,
+                apply_system_buffers,
+                sort_items_topological,
+                sort_items_partial_cmp,
+            )
+                .chain(),
+        );
+        schedule.run(world);
+    }
+
+    fn sorted_items(world: &mut World, method: SortMethod) -> Vec<(Cell, UVec3)> {
+        let mut query = world.query::<(&CurrentCells, &CompareTransforms)>();
+        let mut items = query
+            .iter(world)
+            .map(|(cells, compare)| (cells.main_cell, cells.dimensions, compare.get(method)))
+            .collect::<Vec<(Cell, UVec3, f32)>>();
+        items.sort_by_key(|(.., z)| FloatOrd(*z));
+        items
+            .into_iter()
+            .map(|(main_cell, dimensions, _)| (main_cell, dimensions))
+            .collect()
+    }
+
+    #[test]
+    fn reloaded_scene_sorts_like_the_original() {
+        let mut original = empty_world();
+        for (main_cell, dimensions) in ITEMS {
+            original.spawn(SavedCells {
+                main_cell,
+                dimensions,
+                facing: Direction::BottomRight,
+            });
+        }
+        let registry = original.resource::<AppTypeRegistry>().clone();
+        let ron = DynamicScene::from_world(&original, &registry)
+            .serialize_ron(&registry.0)
+            .unwrap();
+        sort(&mut original);
+
+        let mut reloaded = load(&ron, &registry);
+        sort(&mut reloaded);
+
+        for method in [SortMethod::Topological, SortMethod::PartialCmp] {
+            let expected = sorted_items(&mut original, method);
+            assert_eq!(expected.len(), ITEMS.len());
+            assert_eq!(sorted_items(&mut reloaded, method), expected);
+        }
+    }
+
+    #[test]
+    fn resaving_unchanged_level_is_identical() {
+        let mut original = empty_world();
+        for (main_cell, dimensions) in ITEMS {
+            original.spawn(SavedCells {
+                main_cell,
+                dimensions,
+                facing: Direction::BottomLeft,
+            });
+        }
+        let registry = original.resource::<AppTypeRegistry>().clone();
+        let saved = save_level(&mut original, &registry);
+
+        let mut reloaded = load(&saved, &registry);
+        sort(&mut reloaded);
+
+        assert_eq!(save_level(&mut reloaded, &registry), saved);
+        assert!(saved.contains("facing: BottomLeft,"));
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod test_serde {
+    use super::*;
+
+    #[test]
+    fn saved_cells_round_trip_through_json() {
+        let saved = SavedCells {
+            main_cell: Cell::new(3, 7),
+            dimensions: UVec3::new(2, 1, 3),
+            facing: Direction::BottomLeft,
+        };
+        let json = serde_json::to_string(&saved).unwrap();
+        assert!(json.contains(r#""main_cell":{"x":3,"y":7}"#), "{json}");
+        let loaded: SavedCells = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, saved);
+    }
+}
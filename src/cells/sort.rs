@@ -1,294 +1,2825 @@
-use bevy::prelude::*;
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::*,
+    utils::{FloatOrd, HashMap, HashSet, Instant},
+};
+use std::cmp::Ordering;
 use topological_sort::TopologicalSort;
 
 use crate::cells::{
+    cell::Cell,
     current::CurrentCells,
-    saved::{CompareTransforms, SortMethod},
+    error::PanicPolicy,
+    graph::OcclusionGraph,
+    grid::CellIndex,
+    saved::{CompareTransforms, SortMethod, Timings},
 };
 
-pub fn sort_items_topological(mut items: Query<(Entity, &CurrentCells, &mut CompareTransforms)>) {
-    let mut map = TopologicalSort::<Entity>::default();
+// NOTE:
+// - a ghost is a placement preview: it gets a z that places it correctly among the other items,
+//   but it is never used to sort them, so showing it doesn't reshuffle the real scene
+#[derive(Component)]
+pub struct Ghost;
 
-    let n_items = items.iter().filter(|(_, cells, _)| cells.dimensions.z > 0).count();
+// NOTE:
+// - for items that have a footprint for gameplay, like trigger volumes and spawn markers, but
+//   must never change the render order
+// - they keep their CurrentCells, so occupancy lookups like CellGrid still find them, but the
+//   sort and the broadphase skip them
+#[derive(Component)]
+pub struct NoOcclusion;
 
-    for (this_entity, this_item, _) in items.iter() {
-        if this_item.dimensions.z == 0 {
-            continue;
+const BASE_Z: f32 = 0.;
+const Z_SPAN: f32 = 5.;
+
+// NOTE:
+// - the sorted items are spread over z_span, starting from base_z, and the StaticLayer items
+//   over the z_span right below base_z
+// - a wider z_span keeps the steps between items large enough for f32 in scenes with thousands of
+//   items, or fits the sort in the depth range that the renderer expects
+#[derive(Clone, Copy, Debug, PartialEq, Resource)]
+pub struct SortConfig {
+    pub base_z: f32,
+    pub z_span: f32,
+}
+
+impl Default for SortConfig {
+    fn default() -> Self {
+        Self {
+            base_z: BASE_Z,
+            z_span: Z_SPAN,
         }
+    }
+}
 
-        items
+impl SortConfig {
+    #[allow(clippy::cast_precision_loss)]
+    pub(crate) fn z_step_spread(self, n_steps: usize) -> f32 {
+        self.z_span / n_steps.max(1) as f32
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    pub(crate) fn z_at(self, rank: usize, step: f32) -> f32 {
+        self.base_z + rank as f32 * step
+    }
+}
+
+// NOTE:
+// - for items that never move, like ground tiles, which form a base layer behind everything else
+// - they are sorted among themselves into the z range just below the other items, and only when
+//   one of them is added or its CurrentCells change, otherwise their z stays as it is
+// - they don't take part in the sort of the other items, as nothing can be behind them
+#[derive(Component)]
+pub struct StaticLayer;
+
+// NOTE:
+// - forces an order between this item and others, regardless of their geometry
+// - these edges are added to the geometric ones, and win over a geometric edge between the same
+//   two items that goes the other way
+#[derive(Component, Default)]
+pub struct ForceOrder {
+    pub behind: Vec<Entity>,
+    pub in_front: Vec<Entity>,
+}
+
+// NOTE:
+// - edges pushed by gameplay systems for a single frame, like a bullet in front of whatever it
+//   just passed, that sort_items and sort_items_topological merge with the ForceOrder ones
+// - the sorts only read them, clear_dynamic_edges then clears them once every sort has run, so
+//   that each sort of the frame sees the same edges
+// - each edge is (behind, in_front)
+// - like ForceOrder, an edge that creates a cycle goes through PanicPolicy
+#[derive(Debug, Default, Resource)]
+pub struct DynamicEdges {
+    pub edges: Vec<(Entity, Entity)>,
+}
+
+impl DynamicEdges {
+    pub fn push(&mut self, behind: Entity, in_front: Entity) {
+        self.edges.push((behind, in_front));
+    }
+
+    fn to_force_orders(&self) -> Vec<(Entity, ForceOrder)> {
+        self.edges
             .iter()
-            .filter(|(_, cells, _)| cells.dimensions.z > 0)
-            .filter(|(_, item, _)| {
-                item.underneath
-                    .iter()
-                    .any(|under| this_item.behind.contains(under))
+            .map(|(behind, in_front)| {
+                let order = ForceOrder {
+                    behind: vec![*behind],
+                    ..default()
+                };
+                (*in_front, order)
             })
-            .for_each(|(entity_behind, _, _)| map.add_dependency(entity_behind, this_entity));
+            .collect()
     }
+}
 
-    for (index, entity) in map.enumerate() {
-        assign_z(
-            index,
-            entity,
-            n_items,
-            SortMethod::Topological,
-            &mut items,
-        );
+// NOTE:
+// - selects the method used by sort_items, which writes the z straight into Transform
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Resource)]
+pub struct ActiveSortMethod(pub SortMethod);
+
+impl Default for ActiveSortMethod {
+    fn default() -> Self {
+        Self(SortMethod::Topological)
     }
 }
 
-pub fn sort_items_partial_cmp(mut items: Query<(Entity, &CurrentCells, &mut CompareTransforms)>) {
-    let mut items_to_sort = items
-        .iter()
-        .filter(|(_, cells, _)| cells.dimensions.z > 0)
-        .map(|(entity, cells, _)| (entity, cells.clone()))
-        .collect::<Vec<(Entity, CurrentCells)>>();
-    items_to_sort.sort_by(|(_, a), (_, b)| b.main_cell.cmp(&a.main_cell));
-    // items_to_sort.sort_by(|(_, a), (_, b)| a.prod_dims().cmp(&b.prod_dims()));
-    items_to_sort.sort_by(|(_, a), (_, b)| {
-        a.partial_cmp(b)
-            .or_else(|| a.main_cell.partial_cmp(&b.main_cell))
-            .expect("Ordering must be Some")
-    });
+// NOTE:
+// - pins an item to a fixed z, outside of the sorted range, like a sky or a fog overlay
+// - the item still takes part in the sort, so the items around it keep their order
+#[derive(Clone, Copy, Debug, PartialEq, Component)]
+pub struct FixedZ(pub f32);
 
-    for (index, (entity, _)) in items_to_sort.iter().enumerate() {
-        assign_z(
-            index,
-            *entity,
-            items_to_sort.len(),
-            SortMethod::PartialCmp,
-            &mut items,
-        );
+// NOTE:
+// - Spread gives each item its own step, spreading all of them evenly over the z_span of
+//   SortConfig
+// - Ranked gives the same z to all the items at the same depth of the topological sort, and keeps
+//   ranks at least min_separation apart, widening z_span if needed, so that deep scenes don't
+//   end up with steps too small for f32
+// - other sort methods have no ranks, so each item is its own rank
+#[derive(Clone, Copy, Debug, Default, PartialEq, Resource)]
+pub enum ZLayering {
+    #[default]
+    Spread,
+    Ranked {
+        min_separation: f32,
+    },
+}
+
+// NOTE:
+// - the rank that the last sort gave this item, which is its position in the sorted order, or
+//   its depth in the topological sort with ZLayering::Ranked
+// - items only get a SortRank if they have one already, insert_sort_rank adds it to new items
+// - only the sort of the ActiveSortMethod writes it, so that apps running several sort systems
+//   side by side get the ranks of the one they render with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Component)]
+pub struct SortRank(pub usize);
+
+// NOTE:
+// - the last tiebreak between items that the sort is free to put in any order, like items in the
+//   same rank of the topological sort, or items on the same main_cell with PartialCmp
+// - lower priorities go behind, items without one count as SortPriority(0), and items with the
+//   same priority fall back to their Entity
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Component)]
+pub struct SortPriority(pub u64);
+
+// NOTE:
+// - snaps the z of sorted items to multiples of the given step, so that renderers batching by z
+//   get fewer distinct values
+// - items that don't hide each other can share a z, while an item that hides another one in
+//   the same bucket is moved to the next bucket, and the items after it follow
+// - ghosts and FixedZ items are not snapped, and DepthPeeling spreads the translucent items that
+//   share a bucket within that bucket
+// - a step that isn't a positive finite number is skipped with a warning, as it has no buckets
+#[derive(Clone, Copy, Debug, PartialEq, Resource)]
+pub struct ZQuantize(pub f32);
+
+impl ZQuantize {
+    pub fn is_valid(self) -> bool {
+        self.0.is_finite() && self.0 > 0.
     }
 }
 
-#[allow(clippy::cast_precision_loss)]
-fn assign_z(
-    index: usize,
-    entity: Entity,
-    n_items: usize,
-    method: SortMethod,
-    items: &mut Query<(Entity, &CurrentCells, &mut CompareTransforms)>,
-) {
-    let base_z = 0.;
-    let z_span = 5.;
-    let new_z = base_z + ((index as f32 / n_items as f32) * z_span);
-    let (_, _, mut compare) = items.get_mut(entity).expect("Entity must exist");
-    let z = compare.map.get_mut(&method).unwrap();
-    *z = new_z;
+// NOTE:
+// - a see-through item that is alpha blended, standing base_level units above the ground, like a
+//   glass pane on a shelf
+// - alpha blending needs these items drawn from the lowest to the highest, even when they share a
+//   footprint and the sort is free to put them in any order, which DepthPeeling takes care of
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Component)]
+pub struct Translucent {
+    pub base_level: u32,
 }
 
-#[cfg(test)]
-mod sort_all_items {
-    use bevy::{prelude::*, utils::FloatOrd};
+// NOTE:
+// - after the sort, reorders the Translucent items that share a footprint by their base_level,
+//   reusing the zs the sort gave them
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Resource)]
+pub struct DepthPeeling;
 
-    use crate::cells::{
-        cell::{Cell, Direction},
-        current::CurrentCells,
-    };
+// NOTE:
+// - presort_by_size orders the items by their volume, smallest first, before the partial_cmp
+//   pass, which otherwise starts from the render order of their main_cell
+// - it helps when items of different sizes share a main_cell, like a crate on a rug, which
+//   partial_cmp can't tell apart: the smaller one always goes behind, instead of whichever one
+//   was spawned first
+// - partial_cmp is not a total order, so the starting order also changes which items get
+//   compared, and in busy scenes the presort can misplace more items, so it's off by default
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Resource)]
+pub struct PartialCmpConfig {
+    pub presort_by_size: bool,
+}
 
-    use super::*;
+// NOTE:
+// - keeps the z of each item from one sort to the next whenever the new order allows it, so that
+//   inserting an item doesn't renumber the others, which breaks the batches of 2D renderers
+// - the items that keep their z are the longest run of items whose previous zs are still in the
+//   new order, the others are spread between the zs of their kept neighbours
+// - every item gets its own z, like with ZLayering::Spread
+// - the zs are spread evenly again, renumbering every item once, when inserts squeeze two of them
+//   closer than MIN_STABLE_GAP of a step, or items added in front of or behind all the others
+//   push them out of the z_span
+// - it runs before ZQuantize, so that the kept zs still land in the same buckets
+#[derive(Debug, Default, Resource)]
+pub struct ZStability {
+    previous: HashMap<SortMethod, HashMap<Entity, f32>>,
+}
 
-    #[derive(Debug)]
-    struct Item {
-        expected_index: usize,
-        main_cell: Cell,
-        dimensions: UVec3,
-    }
+// NOTE:
+// - the items that the last sort couldn't order, because they are part of a cycle of items hiding
+//   each other, or in front of one, which the topological sort leaves out
+// - they still get a z, after all the other items, in the order they were given in
+#[derive(Debug, Default, Resource)]
+pub struct SortDiagnostics {
+    pub unsorted: Vec<Entity>,
+}
 
-    impl Item {
-        fn new(expected_index: usize, main_cell: Cell, dimensions: UVec3) -> Self {
-            Self {
-                expected_index,
-                main_cell,
-                dimensions,
-            }
+// NOTE:
+// - the items each sort system last warned about leaving unsorted, for the sorted and the static
+//   items, so that a cycle that stays in place is only reported again once it changes
+#[derive(Default)]
+struct UnsortedWarnings {
+    items: HashSet<Entity>,
+    statics: HashSet<Entity>,
+}
+
+#[derive(SystemParam)]
+pub struct SortSettings<'w, 's> {
+    layering: Option<Res<'w, ZLayering>>,
+    policy: Option<Res<'w, PanicPolicy>>,
+    quantize: Option<Res<'w, ZQuantize>>,
+    depth_peeling: Option<Res<'w, DepthPeeling>>,
+    partial_cmp: Option<Res<'w, PartialCmpConfig>>,
+    stability: Option<ResMut<'w, ZStability>>,
+    config: Option<Res<'w, SortConfig>>,
+    diagnostics: Option<ResMut<'w, SortDiagnostics>>,
+    graph: Option<Res<'w, OcclusionGraph>>,
+    index: Option<Res<'w, CellIndex>>,
+    active: Option<Res<'w, ActiveSortMethod>>,
+    dynamic: Option<Res<'w, DynamicEdges>>,
+    warned: Local<'s, UnsortedWarnings>,
+}
+
+impl SortSettings<'_, '_> {
+    fn edge_sources(&self) -> EdgeSources<'_> {
+        EdgeSources {
+            graph: self.graph.as_deref(),
+            index: self.index.as_deref(),
         }
     }
 
-    fn setup<M>(
-        world: &mut World,
-        schedule: &mut Schedule,
-        items: &[Item],
-        sort_system: impl IntoSystemConfig<M>,
-    ) -> Vec<Entity> {
-        schedule
-            .add_system(apply_system_buffers)
-            .add_system(sort_system.after(apply_system_buffers));
+    fn dynamic_orders(&self) -> Vec<(Entity, ForceOrder)> {
+        self.dynamic
+            .as_deref()
+            .map_or_else(Vec::new, DynamicEdges::to_force_orders)
+    }
 
-        let mut expected = items
-            .iter()
-            .map(|item| {
-                (
-                    item.expected_index,
-                    add_item(world, item.main_cell, item.dimensions),
-                )
-            })
-            .collect::<Vec<(usize, Entity)>>();
-        expected.sort_by(|(a, _), (b, _)| a.cmp(&b));
-        expected
-            .into_iter()
-            .map(|(_, entity)| entity)
-            .collect::<Vec<Entity>>()
+    fn is_active(&self, method: SortMethod) -> bool {
+        self.active.as_deref().copied().unwrap_or_default().0 == method
     }
 
-    fn add_item(world: &mut World, main_cell: Cell, dimensions: UVec3) -> Entity {
-        let cells = CurrentCells::new(
-            main_cell,
-            dimensions,
-            Direction::BottomRight,
-            UVec2::new(4, 7),
-        );
-        world.spawn((cells, CompareTransforms::default())).id()
+    fn get(&self) -> Settings {
+        Settings {
+            layering: self.layering.as_deref().copied().unwrap_or_default(),
+            policy: self.policy.as_deref().copied().unwrap_or_default(),
+            quantize: self.quantize.as_deref().copied().filter(|quantize| {
+                let is_valid = quantize.is_valid();
+                if !is_valid {
+                    warn!(
+                        "Skipping ZQuantize, its step must be positive: {}",
+                        quantize.0
+                    );
+                }
+                is_valid
+            }),
+            depth_peeling: self.depth_peeling.is_some(),
+            partial_cmp: self.partial_cmp.as_deref().copied().unwrap_or_default(),
+            config: self.config.as_deref().copied().unwrap_or_default(),
+        }
     }
+}
 
-    fn actual_order(world: &mut World, method: SortMethod) -> Vec<Entity> {
-        let mut entities = world
-            .query::<(Entity, &CompareTransforms)>()
-            .iter(world)
-            .collect::<Vec<(Entity, &CompareTransforms)>>();
+#[derive(Clone, Copy, Debug, Default)]
+struct Settings {
+    layering: ZLayering,
+    policy: PanicPolicy,
+    quantize: Option<ZQuantize>,
+    depth_peeling: bool,
+    partial_cmp: PartialCmpConfig,
+    config: SortConfig,
+}
 
-        entities.sort_by(|(_, a), (_, b)| {
-            FloatOrd(*a.map.get(&method).unwrap()).cmp(&FloatOrd(*b.map.get(&method).unwrap()))
-        });
+type ItemsQuery<'w, 's, 'a, T> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'a CurrentCells,
+        Option<&'a FixedZ>,
+        Option<&'a mut SortRank>,
+        Option<&'a Translucent>,
+        Option<&'a SortPriority>,
+        &'a mut T,
+    ),
+    (Without<Ghost>, Without<NoOcclusion>, Without<StaticLayer>),
+>;
+type GhostsQuery<'w, 's, 'a, T> = Query<'w, 's, (&'a CurrentCells, &'a mut T), With<Ghost>>;
 
-        entities
-            .into_iter()
-            .map(|(entity, _)| entity)
-            .collect::<Vec<Entity>>()
-    }
+type StaticQuery<'w, 's, 'a, T> = Query<
+    'w,
+    's,
+    (Entity, &'a CurrentCells, Option<&'a FixedZ>, &'a mut T),
+    (With<StaticLayer>, Without<Ghost>, Without<NoOcclusion>),
+>;
 
-    fn run_simple<M>(world: &mut World, system: impl IntoSystemConfig<M>) -> Vec<Entity> {
-        let mut schedule = Schedule::default();
+type StaticChanged = Or<(Added<StaticLayer>, Changed<CurrentCells>)>;
 
-        let items = vec![
-            Item::new(1, Cell::new(0, 3), UVec3::new(2, 2, 1)),
-            Item::new(0, Cell::new(2, 2), UVec3::new(1, 2, 2)),
-            Item::new(2, Cell::new(1, 5), UVec3::new(1, 1, 2)),
-        ];
-        let expected_order = setup(world, &mut schedule, &items, system);
+#[derive(SystemParam)]
+pub struct StaticItems<'w, 's, T: Component> {
+    items: StaticQuery<'w, 's, 'static, T>,
+    changed: Query<'w, 's, (), (With<StaticLayer>, StaticChanged)>,
+}
 
-        schedule.run(world);
+#[allow(clippy::needless_pass_by_value)]
+pub fn insert_sort_rank(
+    mut commands: Commands,
+    added: Query<Entity, (Added<CurrentCells>, Without<SortRank>)>,
+) {
+    for entity in added.iter() {
+        commands.entity(entity).insert(SortRank::default());
+    }
+}
 
-        expected_order
+// NOTE:
+// - the sort systems only see items with CompareTransforms, so this makes any new item sortable
+#[allow(clippy::needless_pass_by_value)]
+pub fn insert_compare_transforms(
+    mut commands: Commands,
+    added: Query<Entity, (Added<CurrentCells>, Without<CompareTransforms>)>,
+) {
+    for entity in added.iter() {
+        commands.entity(entity).insert(CompareTransforms::default());
     }
+}
 
-    fn run_busy<M>(world: &mut World, system: impl IntoSystemConfig<M>) -> Vec<Entity> {
-        let mut schedule = Schedule::default();
+// NOTE:
+// - for apps that run the sort systems writing into CompareTransforms, like sort_items_topological,
+//   and render with the z of one of them: copies the z of the active method into Transform
+// - sort_items writes into Transform directly, so it doesn't need this
+// - items without a Transform are left alone
+#[allow(clippy::needless_pass_by_value)]
+pub fn copy_z_to_transform(
+    active: Res<ActiveSortMethod>,
+    mut items: Query<(&CompareTransforms, &mut Transform), Changed<CompareTransforms>>,
+) {
+    for (compare, mut transform) in &mut items {
+        transform.translation.z = compare.get(active.0);
+    }
+}
 
-        let items = vec![
-            Item::new(2, Cell::new(0, 3), UVec3::new(2, 2, 1)),
-            Item::new(4, Cell::new(1, 6), UVec3::new(1, 2, 1)),
-            Item::new(0, Cell::new(2, 1), UVec3::new(1, 1, 2)),
-            Item::new(3, Cell::new(1, 5), UVec3::new(1, 1, 2)),
-            Item::new(5, Cell::new(0, 6), UVec3::new(1, 1, 1)),
-            Item::new(1, Cell::new(2, 3), UVec3::new(1, 3, 1)),
-        ];
-        let expected_order = setup(world, &mut schedule, &items, system);
+// NOTE:
+// - runs once per frame after every sort system, which only read the DynamicEdges
+pub fn clear_dynamic_edges(dynamic: Option<ResMut<DynamicEdges>>) {
+    if let Some(mut dynamic) = dynamic {
+        dynamic.edges.clear();
+    }
+}
 
-        schedule.run(world);
+/// # Panics
+///
+/// Panics if the sort panics for the active method.
+#[allow(clippy::needless_pass_by_value)]
+pub fn sort_items(
+    active: Res<ActiveSortMethod>,
+    mut settings: SortSettings,
+    timings: Option<ResMut<Timings>>,
+    mut items: ItemsQuery<Transform>,
+    mut ghosts: GhostsQuery<Transform>,
+    mut statics: StaticItems<Transform>,
+    forced: Query<(Entity, &ForceOrder), Without<Ghost>>,
+) {
+    let start = Instant::now();
+    let dynamic_orders = settings.dynamic_orders();
+    sort_and_assign_z(
+        active.0,
+        &mut settings,
+        &mut items,
+        &mut ghosts,
+        &mut statics,
+        forced.iter().chain(
+            dynamic_orders
+                .iter()
+                .map(|(entity, order)| (*entity, order)),
+        ),
+        |transform, z| transform.translation.z = z,
+    );
+    record_timing(timings, active.0, start);
+}
 
-        expected_order
-    }
+/// # Panics
+///
+/// Panics if the forced order creates a cycle with the items' geometry, unless the
+/// `PanicPolicy` is `LogAndSkip`.
+#[allow(clippy::needless_pass_by_value)]
+pub fn sort_items_topological(
+    mut settings: SortSettings,
+    timings: Option<ResMut<Timings>>,
+    mut items: ItemsQuery<CompareTransforms>,
+    mut ghosts: GhostsQuery<CompareTransforms>,
+    mut statics: StaticItems<CompareTransforms>,
+    forced: Query<(Entity, &ForceOrder), Without<Ghost>>,
+) {
+    let start = Instant::now();
+    let dynamic_orders = settings.dynamic_orders();
+    sort_and_assign_z(
+        SortMethod::Topological,
+        &mut settings,
+        &mut items,
+        &mut ghosts,
+        &mut statics,
+        forced.iter().chain(
+            dynamic_orders
+                .iter()
+                .map(|(entity, order)| (*entity, order)),
+        ),
+        |compare, z| compare.set(SortMethod::Topological, z),
+    );
+    record_timing(timings, SortMethod::Topological, start);
+}
 
-    fn run_add_later<M>(
-        world: &mut World,
-        system: impl IntoSystemConfig<M>,
-        method: SortMethod,
-    ) -> Vec<Option<usize>> {
-        let mut schedule = Schedule::default();
+/// # Panics
+///
+/// Panics if two items are both in front and behind each other.
+pub fn sort_items_partial_cmp(
+    mut settings: SortSettings,
+    timings: Option<ResMut<Timings>>,
+    mut items: ItemsQuery<CompareTransforms>,
+    mut ghosts: GhostsQuery<CompareTransforms>,
+    mut statics: StaticItems<CompareTransforms>,
+) {
+    let start = Instant::now();
+    sort_and_assign_z(
+        SortMethod::PartialCmp,
+        &mut settings,
+        &mut items,
+        &mut ghosts,
+        &mut statics,
+        std::iter::empty(),
+        |compare, z| compare.set(SortMethod::PartialCmp, z),
+    );
+    record_timing(timings, SortMethod::PartialCmp, start);
+}
 
-        let initial = vec![
-            Item::new(0, Cell::new(0, 4), UVec3::new(1, 1, 1)),
-            Item::new(0, Cell::new(3, 3), UVec3::new(1, 1, 1)),
-            Item::new(0, Cell::new(3, 4), UVec3::new(1, 1, 1)),
-            Item::new(0, Cell::new(1, 5), UVec3::new(1, 1, 1)),
-        ];
-        let later = Item::new(0, Cell::new(2, 4), UVec3::new(2, 2, 1));
-        let initial_entities = setup(world, &mut schedule, &initial, system);
+/// # Panics
+///
+/// Panics if two items are both in front and behind each other.
+pub fn sort_items_brute_force(
+    mut settings: SortSettings,
+    timings: Option<ResMut<Timings>>,
+    mut items: ItemsQuery<CompareTransforms>,
+    mut ghosts: GhostsQuery<CompareTransforms>,
+    mut statics: StaticItems<CompareTransforms>,
+) {
+    let start = Instant::now();
+    sort_and_assign_z(
+        SortMethod::BruteForceExact,
+        &mut settings,
+        &mut items,
+        &mut ghosts,
+        &mut statics,
+        std::iter::empty(),
+        |compare, z| compare.set(SortMethod::BruteForceExact, z),
+    );
+    record_timing(timings, SortMethod::BruteForceExact, start);
+}
 
-        schedule.run(world);
+// NOTE:
+// - keeps the sort systems in place while sorting is disabled, like for a flat UI layer
+// - it leaves every z as it was, like sort_items does while ActiveSortMethod is None
+pub fn sort_items_none() {}
 
-        let later_entity = add_item(world, later.main_cell, later.dimensions);
+// NOTE:
+// - spreads the static items over the z_span right below base_z, so they stay behind the others
+// - they are ranked like the other items, with the same ZLayering and edge sources, and a
+//   FixedZ or a height of 0 is handled the same way too
+fn sort_static_layer<T: Component>(
+    method: SortMethod,
+    settings: &mut SortSettings,
+    statics: &mut StaticItems<T>,
+    assign: &impl Fn(&mut T, f32),
+) {
+    if statics.changed.is_empty() {
+        return;
+    }
+    let Settings {
+        layering,
+        policy,
+        partial_cmp,
+        config,
+        ..
+    } = settings.get();
+    let statics = &mut statics.items;
+    let mut items_to_sort = statics
+        .iter()
+        .filter(|(_, cells, ..)| cells.dimensions.z > 0)
+        .map(|(entity, cells, ..)| (entity, cells))
+        .collect::<Vec<(Entity, &CurrentCells)>>();
+    items_to_sort.sort_by_key(|(entity, _)| *entity);
+    let sources = settings.edge_sources();
+    let mut ranks = sort_ranks(
+        method,
+        layering,
+        policy,
+        partial_cmp,
+        &items_to_sort,
+        &[],
+        sources,
+    );
+    rank_unsorted(
+        &items_to_sort,
+        &mut ranks,
+        layering,
+        &mut settings.warned.statics,
+    );
+    let n_steps = match layering {
+        ZLayering::Spread => items_to_sort.len(),
+        ZLayering::Ranked { .. } => ranks.len(),
+    };
+    let step = z_step(config, layering, n_steps);
+    for (rank, entities) in ranks.into_iter().enumerate() {
+        for entity in entities {
+            if let Ok((_, _, fixed, mut output)) = statics.get_mut(entity) {
+                let z = fixed.map_or(config.z_at(rank, step) - config.z_span, |FixedZ(z)| *z);
+                assign(&mut output, z);
+            }
+        }
+    }
+}
 
-        schedule.run(world);
+fn record_timing(timings: Option<ResMut<Timings>>, method: SortMethod, start: Instant) {
+    if let Some(mut timings) = timings {
+        timings.map.insert(method, start.elapsed());
+    }
+}
 
-        let actual = actual_order(world, method);
-        let position_last_item = actual.iter().position(|entity| *entity == later_entity);
-        let position_entity_index_2 = actual
+// NOTE:
+// - the items in the order the sort starts from, by SortPriority and then by Entity
+fn items_to_sort<'c, T: Component>(
+    items: &'c ItemsQuery<T>,
+    policy: PanicPolicy,
+) -> Vec<(Entity, &'c CurrentCells)> {
+    let mut prioritised = items
+        .iter()
+        .filter(|(_, cells, ..)| cells.dimensions.z > 0)
+        .map(|(entity, cells, .., priority, _)| (entity, cells, priority.copied()))
+        .collect::<Vec<(Entity, &CurrentCells, Option<SortPriority>)>>();
+    prioritised.sort_by_key(|(entity, _, priority)| (priority.unwrap_or_default(), *entity));
+    let mut items_to_sort = prioritised
+        .into_iter()
+        .map(|(entity, cells, _)| (entity, cells))
+        .collect::<Vec<(Entity, &CurrentCells)>>();
+    if policy == PanicPolicy::LogAndSkip {
+        let contradicting = contradicting_items(&items_to_sort);
+        items_to_sort.retain(|(entity, _)| !contradicting.contains(entity));
+    }
+    items_to_sort
+}
+
+fn sort_and_assign_z<'f, T: Component>(
+    method: SortMethod,
+    settings: &mut SortSettings,
+    items: &mut ItemsQuery<T>,
+    ghosts: &mut GhostsQuery<T>,
+    statics: &mut StaticItems<T>,
+    forced: impl Iterator<Item = (Entity, &'f ForceOrder)>,
+    assign: impl Fn(&mut T, f32),
+) {
+    if method == SortMethod::None {
+        return;
+    }
+    let Settings {
+        layering,
+        policy,
+        quantize,
+        depth_peeling,
+        partial_cmp,
+        config,
+    } = settings.get();
+    sort_static_layer(method, settings, statics, &assign);
+
+    let items_to_sort = items_to_sort(items, policy);
+    let fixed_zs = items
+        .iter()
+        .filter_map(|(entity, _, fixed, ..)| fixed.map(|FixedZ(z)| (entity, *z)))
+        .collect::<HashMap<Entity, f32>>();
+
+    let forced_edges = forced_edges(&items_to_sort, forced);
+    let mut ranks = sort_ranks(
+        method,
+        layering,
+        policy,
+        partial_cmp,
+        &items_to_sort,
+        &forced_edges,
+        settings.edge_sources(),
+    );
+    let warned = &mut settings.warned.items;
+    let unsorted = rank_unsorted(&items_to_sort, &mut ranks, layering, warned);
+    if let Some(diagnostics) = settings.diagnostics.as_deref_mut() {
+        diagnostics.unsorted = unsorted;
+    }
+    let n_steps = match layering {
+        ZLayering::Spread => items_to_sort.len(),
+        ZLayering::Ranked { .. } => ranks.len(),
+    };
+    let step = z_step(config, layering, n_steps);
+    let entity_ranks = ranks
+        .into_iter()
+        .enumerate()
+        .flat_map(|(rank, entities)| entities.into_iter().map(move |entity| (entity, rank)))
+        .collect::<Vec<(Entity, usize)>>();
+    let mut ranked_zs = entity_ranks
+        .iter()
+        .map(|(entity, rank)| (*entity, config.z_at(*rank, step)))
+        .collect::<Vec<(Entity, f32)>>();
+    if let Some(stability) = settings.stability.as_deref_mut() {
+        let previous = stability.previous.entry(method).or_default();
+        stabilise_zs(&mut ranked_zs, previous, step, config);
+    }
+    if let Some(quantize) = quantize {
+        quantize_zs(&mut ranked_zs, &items_to_sort, quantize);
+    }
+    // NOTE:
+    // - translucent items on the same footprint never hide each other, so quantize_zs can put
+    //   them in the same bucket, which is why they are peeled last, within that bucket
+    if depth_peeling {
+        let translucent = items
             .iter()
-            .position(|entity| *entity == initial_entities[2]);
-        let position_entity_index_3 = actual
+            .filter_map(|(entity, .., translucent, _, _)| {
+                translucent.map(|t| (entity, t.base_level))
+            })
+            .collect::<Vec<(Entity, u32)>>();
+        let peel_step = quantize.map_or(step, |ZQuantize(step)| step);
+        peel_translucent(&mut ranked_zs, &items_to_sort, &translucent, peel_step);
+    }
+    let item_zs = {
+        let cells = items_to_sort
+            .into_iter()
+            .collect::<HashMap<Entity, &CurrentCells>>();
+        ranked_zs
             .iter()
-            .position(|entity| *entity == initial_entities[3]);
-
-        vec![
-            position_last_item,
-            position_entity_index_2,
-            position_entity_index_3,
-        ]
+            .filter_map(|(entity, z)| {
+                let z = fixed_zs.get(entity).copied().unwrap_or(*z);
+                cells.get(entity).map(|cells| (*cells, z))
+            })
+            .collect::<Vec<(&CurrentCells, f32)>>()
+    };
+    let z_on_top = config.z_at(n_steps, step);
+    for (ghost, mut output) in ghosts.iter_mut() {
+        assign(&mut output, ghost_z(ghost, &item_zs, step, z_on_top));
     }
 
-    #[test]
-    fn simple_topological() {
-        let mut world = World::default();
-        let expected_order = run_simple(&mut world, sort_items_topological);
-        assert_eq!(
-            actual_order(&mut world, SortMethod::Topological),
-            expected_order
-        );
+    let zs = ranked_zs
+        .into_iter()
+        .filter(|(entity, _)| !fixed_zs.contains_key(entity))
+        .chain(fixed_zs.iter().map(|(entity, z)| (*entity, *z)));
+    for (entity, z) in zs {
+        match (items.get_mut(entity), policy) {
+            (Ok((.., mut output)), _) => assign(&mut output, z),
+            (Err(error), PanicPolicy::LogAndSkip) => warn!("Skipping {entity:?}: {error}"),
+            (Err(error), PanicPolicy::Panic) => panic!("Entity must exist: {error}"),
+        }
     }
+    if settings.is_active(method) {
+        assign_sort_ranks(items, entity_ranks);
+    }
+}
 
-    #[test]
-    fn simple_partial_cmp() {
-        let mut world = World::default();
-        let expected_order = run_simple(&mut world, sort_items_partial_cmp);
-        assert_eq!(
-            actual_order(&mut world, SortMethod::PartialCmp),
-            expected_order
-        );
+fn assign_sort_ranks<T: Component>(items: &mut ItemsQuery<T>, entity_ranks: Vec<(Entity, usize)>) {
+    for (entity, rank) in entity_ranks {
+        if let Ok((_, _, _, Some(mut sort_rank), ..)) = items.get_mut(entity) {
+            sort_rank.0 = rank;
+        }
     }
+}
 
-    #[test]
-    fn busy_topological() {
-        let mut world = World::default();
-        let expected_order = run_busy(&mut world, sort_items_topological);
-        assert_eq!(
-            actual_order(&mut world, SortMethod::Topological),
-            expected_order
-        );
+// NOTE:
+// - the items that are both in front and behind another item, which would make partial_cmp
+//   panic, skipping only the second item of each pair
+fn contradicting_items(items: &[(Entity, &CurrentCells)]) -> Vec<Entity> {
+    let mut contradicting: Vec<Entity> = Vec::new();
+    for (index, (this_entity, this_item)) in items.iter().enumerate() {
+        if contradicting.contains(this_entity) {
+            continue;
+        }
+        for (other_entity, other_item) in &items[index + 1..] {
+            if contradicting.contains(other_entity) {
+                continue;
+            }
+            if let Err(error) = this_item.try_cmp(other_item) {
+                warn!("Skipping {other_entity:?} against {this_entity:?}: {error}");
+                contradicting.push(*other_entity);
+            }
+        }
     }
+    contradicting
+}
 
-    #[test]
-    fn busy_partial_cmp() {
-        let mut world = World::default();
-        let expected_order = run_busy(&mut world, sort_items_partial_cmp);
-        assert_eq!(
-            actual_order(&mut world, SortMethod::PartialCmp),
-            expected_order
+// NOTE:
+// - finds the items that the sort left out of the ranks, and ranks them after all the others
+// - warns about them only when they differ from the ones warned about last time
+fn rank_unsorted(
+    items: &[(Entity, &CurrentCells)],
+    ranks: &mut Vec<Vec<Entity>>,
+    layering: ZLayering,
+    warned: &mut HashSet<Entity>,
+) -> Vec<Entity> {
+    let ranked = ranks.iter().flatten().copied().collect::<HashSet<Entity>>();
+    let unsorted = items
+        .iter()
+        .filter(|(entity, _)| !ranked.contains(entity))
+        .collect::<Vec<&(Entity, &CurrentCells)>>();
+    let unsorted_set = unsorted
+        .iter()
+        .map(|(entity, _)| *entity)
+        .collect::<HashSet<Entity>>();
+    if unsorted_set != *warned && !unsorted.is_empty() {
+        warn!(
+            "{} items left unsorted (in or in front of a cycle), at {:?}",
+            unsorted.len(),
+            unsorted
+                .iter()
+                .map(|(_, cells)| cells.main_cell)
+                .collect::<Vec<Cell>>()
         );
     }
+    *warned = unsorted_set;
+    let unsorted = unsorted
+        .into_iter()
+        .map(|(entity, _)| *entity)
+        .collect::<Vec<Entity>>();
+    match layering {
+        ZLayering::Spread => ranks.extend(unsorted.iter().map(|entity| vec![*entity])),
+        ZLayering::Ranked { .. } => ranks.push(unsorted.clone()),
+    }
+    unsorted
+}
 
-    #[test]
+// NOTE:
+// - where the topological sort finds which items hide which: the cached OcclusionGraph, or else
+//   the items standing on the behind cells of each item in the CellIndex, or else comparing every
+//   two items
+// - the CellIndex is ignored while there's an OcclusionGraph, which already holds every edge it
+//   would find, so IsometricSortPlugin only keeps one of the two up to date
+#[derive(Clone, Copy)]
+struct EdgeSources<'a> {
+    graph: Option<&'a OcclusionGraph>,
+    index: Option<&'a CellIndex>,
+}
+
+impl EdgeSources<'_> {
+    fn edges(self, items: &[(Entity, &CurrentCells)]) -> Vec<(Entity, Entity)> {
+        match (self.graph, self.index) {
+            (Some(graph), _) => graph.edges_between(items),
+            (None, Some(index)) => indexed_geometric_edges(items, index),
+            (None, None) => geometric_edges(items),
+        }
+    }
+}
+
+fn sort_ranks(
+    method: SortMethod,
+    layering: ZLayering,
+    policy: PanicPolicy,
+    partial_cmp: PartialCmpConfig,
+    items: &[(Entity, &CurrentCells)],
+    forced_edges: &[(Entity, Entity)],
+    sources: EdgeSources<'_>,
+) -> Vec<Vec<Entity>> {
+    match method {
+        SortMethod::Topological => {
+            let ranks =
+                topological_ranks_with_edges(items, sources.edges(items), forced_edges, policy);
+            match layering {
+                ZLayering::Spread => ranks
+                    .into_iter()
+                    .flatten()
+                    .map(|entity| vec![entity])
+                    .collect(),
+                ZLayering::Ranked { .. } => ranks,
+            }
+        }
+        SortMethod::PartialCmp => partial_cmp_order_with_config(items, partial_cmp)
+            .into_iter()
+            .map(|entity| vec![entity])
+            .collect(),
+        _ => sort_order(method, items, forced_edges)
+            .into_iter()
+            .map(|entity| vec![entity])
+            .collect(),
+    }
+}
+
+/// # Panics
+///
+/// Panics if `method` is `SortMethod::None`, which leaves the items as they are instead of sorting
+/// them.
+pub fn sort_order(
+    method: SortMethod,
+    items: &[(Entity, &CurrentCells)],
+    forced_edges: &[(Entity, Entity)],
+) -> Vec<Entity> {
+    match method {
+        SortMethod::Topological => topological_order(items, forced_edges),
+        SortMethod::PartialCmp => partial_cmp_order(items),
+        SortMethod::BruteForceExact => brute_force_order(items),
+        SortMethod::None => panic!("SortMethod::None doesn't sort the items"),
+    }
+}
+
+/// # Panics
+///
+/// Panics if the forced edges create a cycle with the items' geometry.
+pub fn topological_order(
+    items: &[(Entity, &CurrentCells)],
+    forced_edges: &[(Entity, Entity)],
+) -> Vec<Entity> {
+    topological_ranks(items, forced_edges)
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+// NOTE:
+// - each rank holds the items that only depend on items in the ranks before it
+// - items in the same rank keep the order they were given in
+// - items in a cycle, and the items in front of them, are left out
+/// # Panics
+///
+/// Panics if the forced edges create a cycle with the items' geometry.
+pub fn topological_ranks(
+    items: &[(Entity, &CurrentCells)],
+    forced_edges: &[(Entity, Entity)],
+) -> Vec<Vec<Entity>> {
+    topological_ranks_with_edges(
+        items,
+        geometric_edges(items),
+        forced_edges,
+        PanicPolicy::Panic,
+    )
+}
+
+fn topological_ranks_with_edges(
+    items: &[(Entity, &CurrentCells)],
+    geometric_edges: Vec<(Entity, Entity)>,
+    forced_edges: &[(Entity, Entity)],
+    policy: PanicPolicy,
+) -> Vec<Vec<Entity>> {
+    let position = items
+        .iter()
+        .enumerate()
+        .map(|(index, (entity, _))| (*entity, index))
+        .collect::<HashMap<Entity, usize>>();
+    let mut map = topological_map(items, geometric_edges, forced_edges, policy);
+    let mut ranks = Vec::new();
+    loop {
+        let mut rank = map.pop_all();
+        if rank.is_empty() {
+            return ranks;
+        }
+        rank.sort_by_key(|entity| position[entity]);
+        ranks.push(rank);
+    }
+}
+
+// NOTE:
+// - each edge is (behind, in_front), between every two items where one hides the other
+fn geometric_edges(items: &[(Entity, &CurrentCells)]) -> Vec<(Entity, Entity)> {
+    #[cfg(feature = "trace")]
+    let _dependencies_span = info_span!("topological_dependencies").entered();
+
+    let mut geometric_edges = Vec::new();
+    for (this_entity, this_item) in items {
+        items
+            .iter()
+            .filter(|(entity, item)| entity != this_entity && this_item.occludes(item))
+            .for_each(|(entity_behind, _)| {
+                geometric_edges.push((*entity_behind, *this_entity));
+            });
+    }
+    geometric_edges
+}
+
+// NOTE:
+// - the same edges as geometric_edges, but only checking the items that stand on the cells
+//   behind each item
+fn indexed_geometric_edges(
+    items: &[(Entity, &CurrentCells)],
+    index: &CellIndex,
+) -> Vec<(Entity, Entity)> {
+    let by_entity = items
+        .iter()
+        .copied()
+        .collect::<HashMap<Entity, &CurrentCells>>();
+    let mut geometric_edges = Vec::new();
+    for (this_entity, this_item) in items {
+        for entity_behind in index.standing_on(&this_item.behind) {
+            let Some(item_behind) = by_entity.get(&entity_behind) else {
+                continue;
+            };
+            if entity_behind != *this_entity && this_item.occludes(item_behind) {
+                geometric_edges.push((entity_behind, *this_entity));
+            }
+        }
+    }
+    geometric_edges
+}
+
+// NOTE:
+// - a forced edge that creates a cycle with the other edges panics, or with LogAndSkip is left
+//   out with a warning, so that the geometric edge it contradicted is kept
+fn topological_map(
+    items: &[(Entity, &CurrentCells)],
+    mut geometric_edges: Vec<(Entity, Entity)>,
+    forced_edges: &[(Entity, Entity)],
+    policy: PanicPolicy,
+) -> TopologicalSort<Entity> {
+    let mut map = TopologicalSort::<Entity>::default();
+
+    let mut forced_edges = forced_edges.to_vec();
+    let mut index = 0;
+    while let Some((behind, in_front)) = forced_edges.get(index).copied() {
+        let other_edges = geometric_edges
+            .iter()
+            .filter(|(behind, in_front)| !forced_edges.contains(&(*in_front, *behind)))
+            .chain(forced_edges.iter())
+            .filter(|edge| **edge != (behind, in_front))
+            .copied()
+            .collect::<Vec<(Entity, Entity)>>();
+        if !has_path(in_front, behind, &other_edges) {
+            index += 1;
+            continue;
+        }
+        match policy {
+            PanicPolicy::Panic => {
+                panic!("Forced order between {behind:?} and {in_front:?} creates a cycle")
+            }
+            PanicPolicy::LogAndSkip => {
+                warn!("Skipping forced order between {behind:?} and {in_front:?}: creates a cycle");
+                forced_edges.remove(index);
+            }
+        }
+    }
+    geometric_edges.retain(|(behind, in_front)| !forced_edges.contains(&(*in_front, *behind)));
+
+    // NOTE:
+    // - items without any edge would never be added by add_dependency, so every item is inserted
+    //   first, and the ones that don't overlap anything end up in the first rank
+    for (entity, _) in items {
+        map.insert(*entity);
+    }
+    for (behind, in_front) in geometric_edges.iter().chain(&forced_edges) {
+        map.add_dependency(*behind, *in_front);
+    }
+
+    map
+}
+
+/// # Panics
+///
+/// Panics if two items are both in front and behind each other.
+pub fn partial_cmp_order(items: &[(Entity, &CurrentCells)]) -> Vec<Entity> {
+    partial_cmp_sorted(items, PartialCmpConfig::default(), None)
+}
+
+// NOTE:
+// - like partial_cmp_order, but items that don't overlap fall back to the height on screen of
+//   base_of, lower ones in front, like the visual base of their sprite from
+//   Cell::sprite_base_world, and only then to the render order of their main_cell
+/// # Panics
+///
+/// Panics if two items are both in front and behind each other.
+pub fn partial_cmp_order_by_base(
+    items: &[(Entity, &CurrentCells)],
+    base_of: impl Fn(Entity, &CurrentCells) -> Vec2,
+) -> Vec<Entity> {
+    partial_cmp_sorted(items, PartialCmpConfig::default(), Some(&base_of))
+}
+
+/// # Panics
+///
+/// Panics if two items are both in front and behind each other.
+pub fn partial_cmp_order_with_config(
+    items: &[(Entity, &CurrentCells)],
+    config: PartialCmpConfig,
+) -> Vec<Entity> {
+    partial_cmp_sorted(items, config, None)
+}
+
+type BaseOf<'b> = dyn Fn(Entity, &CurrentCells) -> Vec2 + 'b;
+
+fn partial_cmp_sorted(
+    items: &[(Entity, &CurrentCells)],
+    config: PartialCmpConfig,
+    base_of: Option<&BaseOf>,
+) -> Vec<Entity> {
+    #[cfg(feature = "trace")]
+    let _sort_span = info_span!("partial_cmp_sort").entered();
+
+    let mut items_to_sort = items.to_vec();
+    items_to_sort.sort_by_key(|(_, cells)| std::cmp::Reverse(cells.main_cell));
+    if config.presort_by_size {
+        items_to_sort.sort_by_key(|(_, cells)| cells.prod_dims());
+    }
+    items_to_sort.sort_by(|(a_entity, a), (b_entity, b)| {
+        a.partial_cmp(b)
+            .or_else(|| {
+                let base_of = base_of?;
+                let screen_y =
+                    |entity: Entity, cells: &CurrentCells| FloatOrd(base_of(entity, cells).y);
+                Some(screen_y(*b_entity, b).cmp(&screen_y(*a_entity, a)))
+                    .filter(|order| order.is_ne())
+            })
+            .or_else(|| a.main_cell.partial_cmp(&b.main_cell))
+            .expect("Ordering must be Some")
+    });
+    items_to_sort
+        .into_iter()
+        .map(|(entity, _)| entity)
+        .collect()
+}
+
+// NOTE:
+// - slow but exact, meant as a reference to validate the other methods against
+// - every pair of items is compared, then the items are taken one at a time among those with
+//   nothing left behind them, picking the first one in the render order of Cell, and then in the
+//   order they were given in
+/// # Panics
+///
+/// Panics if two items are both in front and behind each other, or if the items form a cycle.
+pub fn brute_force_order(items: &[(Entity, &CurrentCells)]) -> Vec<Entity> {
+    let mut edges = Vec::new();
+    for (index, (this_entity, this_item)) in items.iter().enumerate() {
+        for (other_entity, other_item) in &items[index + 1..] {
+            match this_item.partial_cmp(other_item) {
+                Some(Ordering::Less) => edges.push((*this_entity, *other_entity)),
+                Some(Ordering::Greater) => edges.push((*other_entity, *this_entity)),
+                _ => {}
+            }
+        }
+    }
+
+    let mut remaining = items.to_vec();
+    let mut order = Vec::with_capacity(items.len());
+    while !remaining.is_empty() {
+        let is_behind_remaining = |entity: &Entity| {
+            edges.iter().any(|(behind, in_front)| {
+                in_front == entity && remaining.iter().any(|(other, _)| other == behind)
+            })
+        };
+        let next = remaining
+            .iter()
+            .enumerate()
+            .filter(|(_, (entity, _))| !is_behind_remaining(entity))
+            .min_by_key(|(_, (_, cells))| cells.main_cell)
+            .map(|(index, _)| index)
+            .expect("Items must not form a cycle");
+        let (entity, _) = remaining.remove(next);
+        order.push(entity);
+    }
+    order
+}
+
+fn forced_edges<'f>(
+    items: &[(Entity, &CurrentCells)],
+    forced: impl Iterator<Item = (Entity, &'f ForceOrder)>,
+) -> Vec<(Entity, Entity)> {
+    let is_sortable = |entity: &Entity| items.iter().any(|(item, _)| item == entity);
+    forced
+        .filter(|(entity, _)| is_sortable(entity))
+        .flat_map(|(entity, order)| {
+            let behind = order.behind.iter().map(move |behind| (*behind, entity));
+            let in_front = order
+                .in_front
+                .iter()
+                .map(move |in_front| (entity, *in_front));
+            behind.chain(in_front)
+        })
+        .filter(|(behind, in_front)| is_sortable(behind) && is_sortable(in_front))
+        .collect()
+}
+
+fn has_path(from: Entity, to: Entity, edges: &[(Entity, Entity)]) -> bool {
+    let mut visited = vec![from];
+    let mut to_visit = vec![from];
+    while let Some(current) = to_visit.pop() {
+        if current == to {
+            return true;
+        }
+        for (_, next) in edges.iter().filter(|(behind, _)| *behind == current) {
+            if !visited.contains(next) {
+                visited.push(*next);
+                to_visit.push(*next);
+            }
+        }
+    }
+    false
+}
+
+// NOTE:
+// - zs must be in sorted order, from the furthest back
+#[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+fn quantize_zs(
+    zs: &mut [(Entity, f32)],
+    items: &[(Entity, &CurrentCells)],
+    ZQuantize(step): ZQuantize,
+) {
+    let cells = items
+        .iter()
+        .copied()
+        .collect::<HashMap<Entity, &CurrentCells>>();
+    let cells_of = |entity: &Entity| *cells.get(entity).expect("Entity must be one of the items");
+    let mut bucket = i64::MIN;
+    let mut in_bucket: Vec<&CurrentCells> = Vec::new();
+    for (entity, z) in zs.iter_mut() {
+        let cells = cells_of(entity);
+        let mut target = ((*z / step).round() as i64).max(bucket);
+        if target == bucket && in_bucket.iter().any(|other| cells.occludes(other)) {
+            target += 1;
+        }
+        if target != bucket {
+            bucket = target;
+            in_bucket.clear();
+        }
+        in_bucket.push(cells);
+        *z = bucket as f32 * step;
+    }
+}
+
+// NOTE:
+// - the items of a group keep the zs the sort gave them, handed out again from the lowest
+//   base_level to the highest, and by their previous z between items at the same base_level
+// - items of a group that got the same z, like with ZLayering::Ranked, are spread within the
+//   step after it, so that each one gets its own z
+#[allow(clippy::cast_precision_loss)]
+fn peel_translucent(
+    zs: &mut [(Entity, f32)],
+    items: &[(Entity, &CurrentCells)],
+    translucent: &[(Entity, u32)],
+    step: f32,
+) {
+    let cells = items
+        .iter()
+        .copied()
+        .collect::<HashMap<Entity, &CurrentCells>>();
+    let mut groups: HashMap<&[Cell], Vec<(Entity, u32)>> = HashMap::new();
+    for (entity, base_level) in translucent {
+        if let Some(cells) = cells.get(entity) {
+            groups
+                .entry(&cells.underneath)
+                .or_default()
+                .push((*entity, *base_level));
+        }
+    }
+
+    let indices = zs
+        .iter()
+        .enumerate()
+        .map(|(index, (entity, _))| (*entity, index))
+        .collect::<HashMap<Entity, usize>>();
+    for group in groups.into_values().filter(|group| group.len() > 1) {
+        let z_of = |entity: &Entity| indices.get(entity).map(|index| zs[*index].1);
+        let mut members = group
+            .iter()
+            .filter_map(|(entity, base_level)| z_of(entity).map(|z| (*entity, *base_level, z)))
+            .collect::<Vec<(Entity, u32, f32)>>();
+        members.sort_by_key(|(_, base_level, z)| (*base_level, FloatOrd(*z)));
+        let mut group_zs = members.iter().map(|(.., z)| *z).collect::<Vec<f32>>();
+        group_zs.sort_by_key(|z| FloatOrd(*z));
+        let nudge = step / group_zs.len() as f32;
+        for index in 1..group_zs.len() {
+            if group_zs[index] <= group_zs[index - 1] {
+                group_zs[index] = group_zs[index - 1] + nudge;
+            }
+        }
+        for ((entity, ..), z) in members.iter().zip(group_zs) {
+            zs[indices[entity]].1 = z;
+        }
+    }
+}
+
+const MIN_STABLE_GAP: f32 = 1. / 64.;
+
+#[allow(clippy::cast_precision_loss)]
+fn stabilise_zs(
+    zs: &mut [(Entity, f32)],
+    previous: &mut HashMap<Entity, f32>,
+    step: f32,
+    config: SortConfig,
+) {
+    let kept = longest_increasing(
+        &zs.iter()
+            .enumerate()
+            .filter_map(|(index, (entity, _))| previous.get(entity).map(|z| (index, *z)))
+            .collect::<Vec<(usize, f32)>>(),
+    );
+    let mut lower: Option<f32> = None;
+    let mut start = 0;
+    for end in 0..=zs.len() {
+        let upper = kept.binary_search(&end).ok().map(|_| previous[&zs[end].0]);
+        if end < zs.len() && upper.is_none() {
+            continue;
+        }
+        let gap = &mut zs[start..end];
+        let n_gap = gap.len() as f32;
+        for (offset, (_, z)) in gap.iter_mut().enumerate() {
+            let offset = offset as f32 + 1.;
+            *z = match (lower, upper) {
+                (Some(lower), Some(upper)) => lower + (upper - lower) * offset / (n_gap + 1.),
+                (Some(lower), None) => lower + step * offset,
+                (None, Some(upper)) => upper - step * (n_gap + 1. - offset),
+                (None, None) => *z,
+            };
+        }
+        if let Some(upper) = upper {
+            zs[end].1 = upper;
+        }
+        lower = upper;
+        start = end + 1;
+    }
+
+    let spread_step = step.max(config.z_step_spread(zs.len()));
+    let top = config.base_z + config.z_span.max(spread_step * zs.len() as f32);
+    let out_of_range = zs.iter().any(|(_, z)| *z < config.base_z || *z > top);
+    let squeezed = zs
+        .windows(2)
+        .any(|pair| pair[1].1 - pair[0].1 < spread_step * MIN_STABLE_GAP);
+    if out_of_range || squeezed {
+        for (rank, (_, z)) in zs.iter_mut().enumerate() {
+            *z = config.z_at(rank, spread_step);
+        }
+    }
+    *previous = zs.iter().copied().collect();
+}
+
+// NOTE:
+// - the indices of the longest run of values that strictly increase, in patience sorting
+fn longest_increasing(values: &[(usize, f32)]) -> Vec<usize> {
+    let mut tails: Vec<usize> = Vec::new();
+    let mut parents: Vec<Option<usize>> = Vec::with_capacity(values.len());
+    for (position, (_, value)) in values.iter().enumerate() {
+        let length = tails.partition_point(|tail| values[*tail].1 < *value);
+        parents.push(length.checked_sub(1).map(|parent| tails[parent]));
+        if length == tails.len() {
+            tails.push(position);
+        } else {
+            tails[length] = position;
+        }
+    }
+    let mut run = Vec::with_capacity(tails.len());
+    let mut current = tails.last().copied();
+    while let Some(position) = current {
+        run.push(values[position].0);
+        current = parents[position];
+    }
+    run.reverse();
+    run
+}
+
+fn z_step(config: SortConfig, layering: ZLayering, n_steps: usize) -> f32 {
+    let step = config.z_step_spread(n_steps);
+    match layering {
+        ZLayering::Spread => step,
+        ZLayering::Ranked { min_separation } => step.max(min_separation),
+    }
+}
+
+// NOTE:
+// - a ghost goes half a step in front of the items behind it, or half a step behind the items
+//   in front of it, or in between if it has both
+// - a ghost that doesn't overlap anything goes on top, as nothing can hide it
+fn ghost_z(
+    ghost: &CurrentCells,
+    item_zs: &[(&CurrentCells, f32)],
+    step: f32,
+    z_on_top: f32,
+) -> f32 {
+    let half_step = step / 2.;
+
+    let max_behind_z = item_zs
+        .iter()
+        .filter(|(cells, _)| ghost.occludes(cells))
+        .map(|(_, z)| *z)
+        .reduce(f32::max);
+    let min_in_front_z = item_zs
+        .iter()
+        .filter(|(cells, _)| cells.occludes(ghost))
+        .map(|(_, z)| *z)
+        .reduce(f32::min);
+
+    match (max_behind_z, min_in_front_z) {
+        (Some(behind), Some(in_front)) => f32::midpoint(behind, in_front),
+        (Some(behind), None) => behind + half_step,
+        (None, Some(in_front)) => in_front - half_step,
+        (None, None) => z_on_top,
+    }
+}
+
+#[cfg(test)]
+mod sort_all_items {
+    use bevy::{ecs::schedule::ExecutorKind, prelude::*, sprite::Anchor, utils::FloatOrd};
+    use std::fmt::Write;
+
+    use crate::cells::{
+        cell::{Cell, Direction},
+        current::CurrentCells,
+        grid::{update_cell_index, CellGrid},
+        harness::SceneHarness,
+        map::MapSize,
+        order::{SortOrder, StableId},
+        saved::{cross_check_sort, CrossCheckSort},
+    };
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct Item {
+        expected_index: usize,
+        main_cell: Cell,
+        dimensions: UVec3,
+    }
+
+    impl Item {
+        fn new(expected_index: usize, main_cell: Cell, dimensions: UVec3) -> Self {
+            Self {
+                expected_index,
+                main_cell,
+                dimensions,
+            }
+        }
+    }
+
+    fn setup<M>(
+        world: &mut World,
+        schedule: &mut Schedule,
+        items: &[Item],
+        sort_system: impl IntoSystemConfig<M>,
+    ) -> Vec<Entity> {
+        schedule
+            .add_system(apply_system_buffers)
+            .add_system(sort_system.after(apply_system_buffers));
+
+        let mut expected = items
+            .iter()
+            .map(|item| {
+                (
+                    item.expected_index,
+                    add_item(world, item.main_cell, item.dimensions),
+                )
+            })
+            .collect::<Vec<(usize, Entity)>>();
+        expected.sort_by_key(|(index, _)| *index);
+        expected
+            .into_iter()
+            .map(|(_, entity)| entity)
+            .collect::<Vec<Entity>>()
+    }
+
+    fn add_item(world: &mut World, main_cell: Cell, dimensions: UVec3) -> Entity {
+        let cells = CurrentCells::new(
+            main_cell,
+            dimensions,
+            Direction::BottomRight,
+            MapSize::new(4, 7),
+        );
+        world
+            .spawn((cells, CompareTransforms::default(), SortRank::default()))
+            .id()
+    }
+
+    fn z_of(world: &World, entity: Entity, method: SortMethod) -> f32 {
+        world.get::<CompareTransforms>(entity).unwrap().get(method)
+    }
+
+    fn actual_order(world: &mut World, method: SortMethod) -> Vec<Entity> {
+        let mut entities = world
+            .query::<(Entity, &CompareTransforms)>()
+            .iter(world)
+            .collect::<Vec<(Entity, &CompareTransforms)>>();
+
+        entities.sort_by_key(|(_, compare)| FloatOrd(compare.get(method)));
+
+        entities
+            .into_iter()
+            .map(|(entity, _)| entity)
+            .collect::<Vec<Entity>>()
+    }
+
+    // NOTE:
+    // - on a mismatch, lists both orders side by side with the main_cell of each entity
+    fn assert_order_matches(world: &mut World, method: SortMethod, expected: &[Entity]) {
+        let actual = actual_order(world, method);
+        if actual == expected {
+            return;
+        }
+        let describe = |entity: Option<&Entity>| {
+            entity.map_or_else(String::new, |entity| {
+                let main_cell = world
+                    .get::<CurrentCells>(*entity)
+                    .map(|cells| cells.main_cell);
+                format!("{entity:?} at {main_cell:?}")
+            })
+        };
+        let mut diff = String::new();
+        for index in 0..actual.len().max(expected.len()) {
+            let (expected, actual) = (expected.get(index), actual.get(index));
+            let marker = if expected == actual { " " } else { "x" };
+            writeln!(
+                diff,
+                "{marker} {index}: expected {}, actual {}",
+                describe(expected),
+                describe(actual)
+            )
+            .unwrap();
+        }
+        panic!("{method:?} order doesn't match:\n{diff}");
+    }
+
+    // NOTE:
+    // - each scene comes with the expected order of its items, by the order they were added
+    fn simple_scene() -> (SceneHarness, Vec<usize>) {
+        let scene = SceneHarness::new(MapSize::new(4, 7))
+            .with_item(Cell::new(0, 3), UVec3::new(2, 2, 1))
+            .with_item(Cell::new(2, 2), UVec3::new(1, 2, 2))
+            .with_item(Cell::new(1, 5), UVec3::new(1, 1, 2));
+        (scene, vec![1, 0, 2])
+    }
+
+    fn busy_scene() -> (SceneHarness, Vec<usize>) {
+        let scene = SceneHarness::new(MapSize::new(4, 7))
+            .with_item(Cell::new(0, 3), UVec3::new(2, 2, 1))
+            .with_item(Cell::new(1, 6), UVec3::new(1, 2, 1))
+            .with_item(Cell::new(2, 1), UVec3::new(1, 1, 2))
+            .with_item(Cell::new(1, 5), UVec3::new(1, 1, 2))
+            .with_item(Cell::new(0, 6), UVec3::new(1, 1, 1))
+            .with_item(Cell::new(2, 3), UVec3::new(1, 3, 1));
+        (scene, vec![2, 5, 0, 3, 1, 4])
+    }
+
+    fn run_busy<M>(world: &mut World, system: impl IntoSystemConfig<M>) -> Vec<Entity> {
+        let mut schedule = Schedule::default();
+
+        let items = vec![
+            Item::new(2, Cell::new(0, 3), UVec3::new(2, 2, 1)),
+            Item::new(4, Cell::new(1, 6), UVec3::new(1, 2, 1)),
+            Item::new(0, Cell::new(2, 1), UVec3::new(1, 1, 2)),
+            Item::new(3, Cell::new(1, 5), UVec3::new(1, 1, 2)),
+            Item::new(5, Cell::new(0, 6), UVec3::new(1, 1, 1)),
+            Item::new(1, Cell::new(2, 3), UVec3::new(1, 3, 1)),
+        ];
+        let expected_order = setup(world, &mut schedule, &items, system);
+
+        schedule.run(world);
+
+        expected_order
+    }
+
+    fn run_add_later<M>(
+        world: &mut World,
+        system: impl IntoSystemConfig<M>,
+        method: SortMethod,
+    ) -> Vec<Option<usize>> {
+        let mut schedule = Schedule::default();
+
+        let initial = vec![
+            Item::new(0, Cell::new(0, 4), UVec3::new(1, 1, 1)),
+            Item::new(0, Cell::new(3, 3), UVec3::new(1, 1, 1)),
+            Item::new(0, Cell::new(3, 4), UVec3::new(1, 1, 1)),
+            Item::new(0, Cell::new(1, 5), UVec3::new(1, 1, 1)),
+        ];
+        let later = Item::new(0, Cell::new(2, 4), UVec3::new(2, 2, 1));
+        let initial_entities = setup(world, &mut schedule, &initial, system);
+
+        schedule.run(world);
+
+        let later_entity = add_item(world, later.main_cell, later.dimensions);
+
+        schedule.run(world);
+
+        let actual = actual_order(world, method);
+        let position_last_item = actual.iter().position(|entity| *entity == later_entity);
+        let position_entity_index_2 = actual
+            .iter()
+            .position(|entity| *entity == initial_entities[2]);
+        let position_entity_index_3 = actual
+            .iter()
+            .position(|entity| *entity == initial_entities[3]);
+
+        vec![
+            position_last_item,
+            position_entity_index_2,
+            position_entity_index_3,
+        ]
+    }
+
+    #[test]
+    fn simple_topological() {
+        let (scene, expected_order) = simple_scene();
+        assert_eq!(scene.sort(SortMethod::Topological), expected_order);
+    }
+
+    #[test]
+    fn simple_partial_cmp() {
+        let (scene, expected_order) = simple_scene();
+        assert_eq!(scene.sort(SortMethod::PartialCmp), expected_order);
+    }
+
+    #[test]
+    fn simple_brute_force() {
+        let (scene, expected_order) = simple_scene();
+        assert_eq!(scene.sort(SortMethod::BruteForceExact), expected_order);
+    }
+
+    #[test]
+    fn busy_topological() {
+        let mut world = World::default();
+        let expected_order = run_busy(&mut world, sort_items_topological);
+        assert_order_matches(&mut world, SortMethod::Topological, &expected_order);
+    }
+
+    #[test]
+    #[should_panic(expected = "x 0: expected")]
+    fn order_mismatch_is_described() {
+        let mut world = World::default();
+        let mut expected_order = run_busy(&mut world, sort_items_topological);
+        expected_order.reverse();
+        assert_order_matches(&mut world, SortMethod::Topological, &expected_order);
+    }
+
+    #[test]
+    fn busy_sort_rank_matches_sort_order() {
+        let mut world = World::default();
+        run_busy(&mut world, sort_items_topological);
+
+        let mut query = world.query::<(Entity, &CurrentCells, &SortRank)>();
+        let items = query
+            .iter(&world)
+            .map(|(entity, cells, _)| (entity, StableId(u64::from(entity.index())), cells))
+            .collect::<Vec<(Entity, StableId, &CurrentCells)>>();
+        let order = SortOrder::compute(SortMethod::Topological, &items);
+
+        for (entity, _, rank) in query.iter(&world) {
+            let position = order
+                .0
+                .iter()
+                .position(|id| *id == StableId(u64::from(entity.index())));
+            assert_eq!(Some(rank.0), position);
+        }
+    }
+
+    #[test]
+    fn sort_rank_follows_the_active_method() {
+        let mut world = World::default();
+        world.insert_resource(ActiveSortMethod(SortMethod::PartialCmp));
+        // NOTE:
+        // - none of these items hide each other, so the topological sort would give all of them
+        //   the same rank
+        world.insert_resource(ZLayering::Ranked { min_separation: 0. });
+        for main_cell in [
+            Cell::new(0, 0),
+            Cell::new(3, 0),
+            Cell::new(0, 6),
+            Cell::new(3, 6),
+        ] {
+            add_item(&mut world, main_cell, UVec3::ONE);
+        }
+        let mut schedule = Schedule::default();
+        schedule.add_system(sort_items_topological.after(sort_items_partial_cmp));
+        schedule.add_system(sort_items_partial_cmp);
+        schedule.run(&mut world);
+
+        let order = actual_order(&mut world, SortMethod::PartialCmp);
+        for (position, entity) in order.into_iter().enumerate() {
+            assert_eq!(world.get::<SortRank>(entity), Some(&SortRank(position)));
+        }
+    }
+
+    #[test]
+    fn busy_partial_cmp() {
+        let (scene, expected_order) = busy_scene();
+        assert_eq!(scene.sort(SortMethod::PartialCmp), expected_order);
+    }
+
+    #[test]
+    fn same_row_partial_cmp_is_stable() {
+        let map_size = MapSize::new(4, 7);
+        let left = CurrentCells::new(
+            Cell::new(0, 3),
+            UVec3::ONE,
+            Direction::BottomRight,
+            map_size,
+        );
+        let right = CurrentCells::new(
+            Cell::new(2, 3),
+            UVec3::ONE,
+            Direction::BottomRight,
+            map_size,
+        );
+        let lower = CurrentCells::new(
+            Cell::new(1, 5),
+            UVec3::ONE,
+            Direction::BottomRight,
+            map_size,
+        );
+        let (a, b, c) = (
+            Entity::from_raw(0),
+            Entity::from_raw(1),
+            Entity::from_raw(2),
+        );
+
+        let expected = vec![a, b, c];
+        assert_eq!(
+            partial_cmp_order(&[(a, &left), (b, &right), (c, &lower)]),
+            expected
+        );
+        assert_eq!(
+            partial_cmp_order(&[(c, &lower), (b, &right), (a, &left)]),
+            expected
+        );
+    }
+
+    #[test]
+    fn anchored_sprite_sorts_by_its_visual_base() {
+        let map_size = MapSize::new(4, 7);
+        let tile_size = Vec2::new(64., 32.);
+        let tree = CurrentCells::new(
+            Cell::new(0, 1),
+            UVec3::ONE,
+            Direction::BottomRight,
+            map_size,
+        );
+        let bush = CurrentCells::new(
+            Cell::new(3, 5),
+            UVec3::ONE,
+            Direction::BottomRight,
+            map_size,
+        );
+        let (tree_entity, bush_entity) = (Entity::from_raw(0), Entity::from_raw(1));
+        let items = [(tree_entity, &tree), (bush_entity, &bush)];
+        let base_of = |entity: Entity, cells: &CurrentCells| {
+            let (anchor, sprite_size) = if entity == tree_entity {
+                (Anchor::Center, Vec2::new(64., 192.))
+            } else {
+                (Anchor::BottomCenter, Vec2::new(64., 32.))
+            };
+            cells
+                .main_cell
+                .sprite_base_world(tile_size, Vec2::ZERO, &anchor, sprite_size)
+        };
+
+        assert_eq!(partial_cmp_order(&items), vec![tree_entity, bush_entity]);
+        let anchored = partial_cmp_order_by_base(&items, base_of);
+        assert_eq!(anchored, vec![bush_entity, tree_entity]);
+        assert!(base_of(tree_entity, &tree).y < base_of(bush_entity, &bush).y);
+    }
+
+    #[test]
+    fn presort_by_size_orders_co_located_items() {
+        let map_size = MapSize::new(8, 14);
+        let cells = [
+            (Cell::new(3, 8), UVec3::new(1, 1, 2)),
+            (Cell::new(3, 10), UVec3::new(1, 1, 1)),
+            (Cell::new(3, 9), UVec3::new(1, 1, 2)),
+            (Cell::new(3, 8), UVec3::new(1, 1, 1)),
+        ]
+        .map(|(main_cell, dims)| {
+            CurrentCells::new(main_cell, dims, Direction::BottomRight, map_size)
+        });
+        let items = (0..)
+            .map(Entity::from_raw)
+            .zip(&cells)
+            .collect::<Vec<(Entity, &CurrentCells)>>();
+        let reversed = items
+            .iter()
+            .rev()
+            .copied()
+            .collect::<Vec<(Entity, &CurrentCells)>>();
+        let (tall, short) = (items[0].0, items[3].0);
+        let order_with = |items: &[(Entity, &CurrentCells)], presort_by_size| {
+            partial_cmp_order_with_config(items, PartialCmpConfig { presort_by_size })
+        };
+        let position = |order: &[Entity], entity| order.iter().position(|e| *e == entity);
+
+        let without = order_with(&items, false);
+        assert_ne!(without, order_with(&reversed, false));
+        assert!(position(&without, tall) < position(&without, short));
+
+        let presorted = order_with(&items, true);
+        assert_eq!(presorted, order_with(&reversed, true));
+        assert!(position(&presorted, short) < position(&presorted, tall));
+        for (entity, cells) in &items {
+            for (other, other_cells) in &items {
+                if cells.occludes(other_cells) {
+                    assert!(position(&presorted, *other) < position(&presorted, *entity));
+                    assert!(position(&without, *other) < position(&without, *entity));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn busy_brute_force() {
+        let (scene, expected_order) = busy_scene();
+        assert_eq!(scene.sort(SortMethod::BruteForceExact), expected_order);
+    }
+
+    // NOTE:
+    // - the fixtures have no contradictions, so all the methods must find the same order
+    #[test]
+    fn methods_agree_on_fixtures() {
+        for (scene, _) in [simple_scene(), busy_scene()] {
+            let topological = scene.sort(SortMethod::Topological);
+            let partial_cmp = scene.sort(SortMethod::PartialCmp);
+
+            assert_eq!(
+                topological, partial_cmp,
+                "Topological order {topological:?} differs from PartialCmp order {partial_cmp:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn cross_check_flags_only_disagreements() {
+        let mut world = World::default();
+        world.init_resource::<CrossCheckSort>();
+        run_busy(&mut world, sort_items_topological);
+        let mut schedule = Schedule::default();
+        schedule.add_systems((sort_items_partial_cmp, cross_check_sort).chain());
+
+        schedule.run(&mut world);
+        assert!(world.resource::<CrossCheckSort>().disagreements.is_empty());
+
+        let mut query = world.query::<(Entity, &CurrentCells)>();
+        let items = query.iter(&world).collect::<Vec<(Entity, &CurrentCells)>>();
+        let (in_front, behind) = items
+            .iter()
+            .find_map(|(in_front, in_front_cells)| {
+                items
+                    .iter()
+                    .find(|(_, cells)| in_front_cells.occludes(cells))
+                    .map(|(behind, _)| (*in_front, *behind))
+            })
+            .unwrap();
+        let behind_z = z_of(&world, behind, SortMethod::PartialCmp);
+        let mut check = Schedule::default();
+        check.add_system(cross_check_sort);
+        world
+            .get_mut::<CompareTransforms>(in_front)
+            .unwrap()
+            .set(SortMethod::PartialCmp, behind_z - 1.);
+
+        check.run(&mut world);
+        let disagreements = &world.resource::<CrossCheckSort>().disagreements;
+        assert!(disagreements.contains(&(in_front, behind)));
+    }
+
+    #[test]
     fn add_later_topological() {
         let mut world = World::default();
-        let positions = run_add_later(&mut world, sort_items_topological, SortMethod::Topological);
-        let position_last_item = positions[0];
-        let position_item_2 = positions[1];
-        let position_item_3 = positions[2];
-        assert!(position_last_item < position_item_2);
-        assert!(position_last_item < position_item_3);
+        let positions = run_add_later(&mut world, sort_items_topological, SortMethod::Topological);
+        let position_last_item = positions[0];
+        let position_item_2 = positions[1];
+        let position_item_3 = positions[2];
+        assert!(position_last_item < position_item_2);
+        assert!(position_last_item < position_item_3);
+    }
+
+    #[test]
+    fn add_later_partial_cmp() {
+        let mut world = World::default();
+        let positions = run_add_later(&mut world, sort_items_partial_cmp, SortMethod::PartialCmp);
+        let position_last_item = positions[0];
+        let position_item_2 = positions[1];
+        let position_item_3 = positions[2];
+        assert!(position_last_item < position_item_2);
+        assert!(position_last_item < position_item_3);
+    }
+
+    fn run_with_ghost<M>(world: &mut World, system: impl IntoSystemConfig<M>, method: SortMethod) {
+        let mut schedule = Schedule::default();
+
+        let items = vec![
+            Item::new(1, Cell::new(0, 3), UVec3::new(2, 2, 1)),
+            Item::new(0, Cell::new(2, 2), UVec3::new(1, 2, 2)),
+            Item::new(2, Cell::new(1, 5), UVec3::new(1, 1, 2)),
+        ];
+        let expected_order = setup(world, &mut schedule, &items, system);
+
+        schedule.run(world);
+
+        let zs_before = expected_order
+            .iter()
+            .map(|entity| z_of(world, *entity, method))
+            .collect::<Vec<f32>>();
+
+        let ghost = add_item(world, Cell::new(2, 4), UVec3::new(1, 1, 1));
+        world.entity_mut(ghost).insert(Ghost);
+
+        schedule.run(world);
+
+        let zs_after = expected_order
+            .iter()
+            .map(|entity| z_of(world, *entity, method))
+            .collect::<Vec<f32>>();
+        assert_eq!(zs_before, zs_after);
+
+        let ghost_z = z_of(world, ghost, method);
+        assert!(ghost_z > zs_after[0]);
+        assert!(ghost_z < zs_after[2]);
+    }
+
+    #[test]
+    fn ghost_topological() {
+        let mut world = World::default();
+        run_with_ghost(&mut world, sort_items_topological, SortMethod::Topological);
+    }
+
+    #[test]
+    fn ghost_partial_cmp() {
+        let mut world = World::default();
+        run_with_ghost(&mut world, sort_items_partial_cmp, SortMethod::PartialCmp);
+    }
+
+    fn run_simple_forced(
+        world: &mut World,
+        force: impl Fn(&[Entity]) -> (Entity, ForceOrder),
+    ) -> Vec<Entity> {
+        let mut schedule = Schedule::default();
+
+        let items = vec![
+            Item::new(1, Cell::new(0, 3), UVec3::new(2, 2, 1)),
+            Item::new(0, Cell::new(2, 2), UVec3::new(1, 2, 2)),
+            Item::new(2, Cell::new(1, 5), UVec3::new(1, 1, 2)),
+        ];
+        schedule.set_executor_kind(ExecutorKind::SingleThreaded);
+        let geometric_order = setup(world, &mut schedule, &items, sort_items_topological);
+        let (entity, force_order) = force(&geometric_order);
+        world.entity_mut(entity).insert(force_order);
+
+        schedule.run(world);
+
+        geometric_order
+    }
+
+    #[test]
+    fn forced_order_overrides_geometry() {
+        let mut world = World::default();
+        let geometric_order = run_simple_forced(&mut world, |order| {
+            (
+                order[1],
+                ForceOrder {
+                    in_front: vec![order[0]],
+                    ..default()
+                },
+            )
+        });
+        let expected_order = vec![geometric_order[1], geometric_order[0], geometric_order[2]];
+        assert_eq!(
+            actual_order(&mut world, SortMethod::Topological),
+            expected_order
+        );
+    }
+
+    #[test]
+    fn dynamic_edges_last_one_frame() {
+        let mut world = World::default();
+        world.init_resource::<DynamicEdges>();
+        let geometric_order =
+            run_simple_forced(&mut world, |order| (order[0], ForceOrder::default()));
+        let mut schedule = Schedule::default();
+        schedule.add_system(sort_items_topological);
+        schedule.add_system(clear_dynamic_edges.after(sort_items_topological));
+
+        world
+            .resource_mut::<DynamicEdges>()
+            .push(geometric_order[1], geometric_order[0]);
+        schedule.run(&mut world);
+        assert_eq!(
+            actual_order(&mut world, SortMethod::Topological),
+            vec![geometric_order[1], geometric_order[0], geometric_order[2]]
+        );
+        assert!(world.resource::<DynamicEdges>().edges.is_empty());
+
+        schedule.run(&mut world);
+        assert_eq!(
+            actual_order(&mut world, SortMethod::Topological),
+            geometric_order
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "creates a cycle")]
+    fn forced_order_cycle_with_geometry() {
+        let mut world = World::default();
+        run_simple_forced(&mut world, |order| {
+            (
+                order[0],
+                ForceOrder {
+                    behind: vec![order[2]],
+                    ..default()
+                },
+            )
+        });
+    }
+
+    #[test]
+    fn forced_order_cycle_is_skipped_with_log_and_skip() {
+        let mut world = World::default();
+        world.insert_resource(PanicPolicy::LogAndSkip);
+        let geometric_order = run_simple_forced(&mut world, |order| {
+            (
+                order[0],
+                ForceOrder {
+                    behind: vec![order[2]],
+                    ..default()
+                },
+            )
+        });
+        assert_eq!(
+            actual_order(&mut world, SortMethod::Topological),
+            geometric_order
+        );
+    }
+
+    #[test]
+    fn sort_items_reads_dynamic_edges_for_one_frame() {
+        let mut world = World::default();
+        world.init_resource::<DynamicEdges>();
+        world.init_resource::<ActiveSortMethod>();
+        let mut schedule = Schedule::default();
+        schedule.add_system(sort_items);
+        schedule.add_system(clear_dynamic_edges.after(sort_items));
+        let [first, second] = [Cell::new(0, 0), Cell::new(3, 6)].map(|main_cell| {
+            let item = add_item(&mut world, main_cell, UVec3::ONE);
+            world.entity_mut(item).insert(Transform::default());
+            item
+        });
+        let z = |world: &World, entity| world.get::<Transform>(entity).unwrap().translation.z;
+
+        world.resource_mut::<DynamicEdges>().push(second, first);
+        schedule.run(&mut world);
+        assert!(z(&world, second) < z(&world, first));
+        assert!(world.resource::<DynamicEdges>().edges.is_empty());
+
+        schedule.run(&mut world);
+        assert!(z(&world, first) < z(&world, second));
+    }
+
+    #[test]
+    fn every_sort_of_the_frame_sees_the_dynamic_edges() {
+        let mut world = World::default();
+        world.init_resource::<DynamicEdges>();
+        world.init_resource::<ActiveSortMethod>();
+        let mut schedule = Schedule::default();
+        schedule.add_system(sort_items);
+        schedule.add_system(sort_items_topological);
+        schedule.add_system(
+            clear_dynamic_edges
+                .after(sort_items)
+                .after(sort_items_topological),
+        );
+        let [first, second] = [Cell::new(0, 0), Cell::new(3, 6)].map(|main_cell| {
+            let item = add_item(&mut world, main_cell, UVec3::ONE);
+            world.entity_mut(item).insert(Transform::default());
+            item
+        });
+        let z = |world: &World, entity| world.get::<Transform>(entity).unwrap().translation.z;
+
+        world.resource_mut::<DynamicEdges>().push(second, first);
+        schedule.run(&mut world);
+        assert!(z(&world, second) < z(&world, first));
+        assert!(
+            z_of(&world, second, SortMethod::Topological)
+                < z_of(&world, first, SortMethod::Topological)
+        );
+        assert!(world.resource::<DynamicEdges>().edges.is_empty());
     }
 
     #[test]
-    fn add_later_partial_cmp() {
+    #[allow(clippy::float_cmp)]
+    fn fixed_z_is_kept() {
         let mut world = World::default();
-        let positions = run_add_later(&mut world, sort_items_partial_cmp, SortMethod::PartialCmp);
-        let position_last_item = positions[0];
-        let position_item_2 = positions[1];
-        let position_item_3 = positions[2];
-        assert!(position_last_item < position_item_2);
-        assert!(position_last_item < position_item_3);
+        let mut schedule = Schedule::default();
+        let items = vec![
+            Item::new(1, Cell::new(0, 3), UVec3::new(2, 2, 1)),
+            Item::new(0, Cell::new(2, 2), UVec3::new(1, 2, 2)),
+            Item::new(2, Cell::new(1, 5), UVec3::new(1, 1, 2)),
+        ];
+        let expected_order = setup(&mut world, &mut schedule, &items, sort_items_partial_cmp);
+        world.entity_mut(expected_order[1]).insert(FixedZ(-10.));
+
+        for _ in 0..2 {
+            schedule.run(&mut world);
+            let method = SortMethod::PartialCmp;
+            assert_eq!(z_of(&world, expected_order[1], method), -10.);
+            assert!(
+                z_of(&world, expected_order[0], method) < z_of(&world, expected_order[2], method)
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_quantize_step_is_skipped() {
+        let sorted_zs = |quantize: Option<ZQuantize>| {
+            let mut world = World::default();
+            let mut schedule = Schedule::default();
+            schedule.add_system(sort_items_partial_cmp);
+            if let Some(quantize) = quantize {
+                world.insert_resource(quantize);
+            }
+            let entities = (0..4)
+                .map(|y| add_item(&mut world, Cell::new(1, y), UVec3::ONE))
+                .collect::<Vec<Entity>>();
+            schedule.run(&mut world);
+            entities
+                .into_iter()
+                .map(|entity| FloatOrd(z_of(&world, entity, SortMethod::PartialCmp)))
+                .collect::<Vec<FloatOrd>>()
+        };
+
+        let unquantized = sorted_zs(None);
+        for step in [0., -1., f32::NAN, f32::INFINITY] {
+            assert!(!ZQuantize(step).is_valid());
+            assert_eq!(sorted_zs(Some(ZQuantize(step))), unquantized, "{step}");
+        }
+    }
+
+    #[test]
+    fn quantized_z_keeps_order_with_fewer_values() {
+        let mut world = World::default();
+        let mut schedule = Schedule::default();
+        schedule.add_system(sort_items_partial_cmp);
+        world.insert_resource(ZQuantize(0.5));
+        let entities = (0..8)
+            .flat_map(|y| (0..4).map(move |x| Cell::new(x, y)))
+            .map(|main_cell| add_item(&mut world, main_cell, UVec3::ONE))
+            .collect::<Vec<Entity>>();
+
+        schedule.run(&mut world);
+
+        let z = |entity| z_of(&world, entity, SortMethod::PartialCmp);
+        let cells = |entity| world.get::<CurrentCells>(entity).unwrap();
+        for in_front in &entities {
+            for behind in &entities {
+                if cells(*in_front).occludes(cells(*behind)) {
+                    assert!(z(*behind) < z(*in_front), "{behind:?} {in_front:?}");
+                }
+            }
+        }
+        let mut zs = entities
+            .iter()
+            .map(|entity| FloatOrd(z(*entity)))
+            .collect::<Vec<_>>();
+        zs.sort();
+        zs.dedup();
+        assert!(zs.len() < entities.len());
+        assert!(zs.iter().all(|FloatOrd(z)| (z / 0.5).fract() == 0.));
+    }
+
+    #[test]
+    fn depth_peeling_stacks_translucent_items_bottom_to_top() {
+        let mut world = World::default();
+        let mut schedule = Schedule::default();
+        schedule.add_system(sort_items_partial_cmp);
+        world.insert_resource(DepthPeeling);
+        let panes = [2, 0, 1].map(|base_level| {
+            let pane = add_item(&mut world, Cell::new(1, 4), UVec3::ONE);
+            world.entity_mut(pane).insert(Translucent { base_level });
+            (base_level, pane)
+        });
+
+        schedule.run(&mut world);
+
+        let mut zs = panes
+            .iter()
+            .map(|(base_level, pane)| (*base_level, z_of(&world, *pane, SortMethod::PartialCmp)))
+            .collect::<Vec<(u32, f32)>>();
+        zs.sort_by_key(|(base_level, _)| *base_level);
+        assert!(zs.windows(2).all(|pair| pair[0].1 < pair[1].1), "{zs:?}");
+    }
+
+    #[test]
+    fn depth_peeling_survives_quantize() {
+        let mut world = World::default();
+        let mut schedule = Schedule::default();
+        schedule.add_system(sort_items_partial_cmp);
+        world.insert_resource(DepthPeeling);
+        world.insert_resource(ZQuantize(10.));
+        let panes = [2, 0, 1].map(|base_level| {
+            let pane = add_item(&mut world, Cell::new(1, 4), UVec3::ONE);
+            world.entity_mut(pane).insert(Translucent { base_level });
+            (base_level, pane)
+        });
+        let in_front = add_item(&mut world, Cell::new(1, 6), UVec3::ONE);
+
+        schedule.run(&mut world);
+
+        let mut zs = panes
+            .iter()
+            .map(|(base_level, pane)| (*base_level, z_of(&world, *pane, SortMethod::PartialCmp)))
+            .collect::<Vec<(u32, f32)>>();
+        zs.sort_by_key(|(base_level, _)| *base_level);
+        assert!(zs.windows(2).all(|pair| pair[0].1 < pair[1].1), "{zs:?}");
+        let in_front_z = z_of(&world, in_front, SortMethod::PartialCmp);
+        assert!(
+            zs.iter().all(|(_, z)| *z < in_front_z),
+            "{zs:?} {in_front_z}"
+        );
+    }
+
+    #[test]
+    fn non_interacting_items_order_by_sort_priority() {
+        for (system, method, cells) in [
+            (
+                sort_items_topological.into_config(),
+                SortMethod::Topological,
+                [Cell::new(0, 6), Cell::new(3, 0)],
+            ),
+            (
+                sort_items_partial_cmp.into_config(),
+                SortMethod::PartialCmp,
+                [Cell::new(1, 4), Cell::new(1, 4)],
+            ),
+        ] {
+            let mut world = World::default();
+            let mut schedule = Schedule::default();
+            schedule.add_system(system);
+            let first = add_item(&mut world, cells[0], UVec3::ONE);
+            let second = add_item(&mut world, cells[1], UVec3::new(1, 1, 2));
+            world.entity_mut(first).insert(SortPriority(2));
+            world.entity_mut(second).insert(SortPriority(1));
+
+            schedule.run(&mut world);
+
+            assert_eq!(actual_order(&mut world, method), vec![second, first]);
+        }
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn static_layer_follows_layering_and_fixed_z() {
+        let mut world = World::default();
+        world.insert_resource(ZLayering::Ranked { min_separation: 0. });
+        let mut schedule = Schedule::default();
+        schedule.add_system(sort_items_topological);
+        let mut add_tile = |cell, height| {
+            let tile = add_item(&mut world, cell, UVec3::new(1, 1, height));
+            world.entity_mut(tile).insert(StaticLayer);
+            tile
+        };
+        let apart = [add_tile(Cell::new(0, 1), 1), add_tile(Cell::new(3, 1), 1)];
+        let in_front = add_tile(Cell::new(0, 3), 1);
+        let flat = add_tile(Cell::new(2, 5), 0);
+        let pinned = add_tile(Cell::new(3, 5), 1);
+        world.entity_mut(pinned).insert(FixedZ(-42.));
+        schedule.run(&mut world);
+
+        let z = |entity| z_of(&world, entity, SortMethod::Topological);
+        assert_eq!(z(apart[0]), z(apart[1]));
+        assert!(z(apart[0]) < z(in_front));
+        assert!(z(in_front) < 0.);
+        assert_eq!(z(pinned), -42.);
+        assert_eq!(z(flat), 0.);
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn static_layer_keeps_its_z() {
+        let mut world = World::default();
+        let mut schedule = Schedule::default();
+        schedule.add_system(sort_items_topological);
+        let tiles = [Cell::new(1, 3), Cell::new(1, 4), Cell::new(1, 5)].map(|cell| {
+            let tile = add_item(&mut world, cell, UVec3::ONE);
+            world.entity_mut(tile).insert(StaticLayer);
+            tile
+        });
+        let crate_item = add_item(&mut world, Cell::new(0, 4), UVec3::ONE);
+        let player = add_item(&mut world, Cell::new(2, 6), UVec3::ONE);
+        schedule.run(&mut world);
+
+        let tile_zs = tiles.map(|tile| z_of(&world, tile, SortMethod::Topological));
+        assert!(tile_zs.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(tile_zs[2] < z_of(&world, crate_item, SortMethod::Topological));
+
+        // NOTE:
+        // - a z written over a tile's is left alone, which shows that the tiles are skipped
+        let mut compare = world.get_mut::<CompareTransforms>(tiles[0]).unwrap();
+        compare.set(SortMethod::Topological, -100.);
+        for y in [5, 3] {
+            *world.get_mut::<CurrentCells>(player).unwrap() = CurrentCells::new(
+                Cell::new(0, y),
+                UVec3::ONE,
+                Direction::BottomRight,
+                MapSize::new(4, 7),
+            );
+            schedule.run(&mut world);
+            assert_eq!(z_of(&world, tiles[0], SortMethod::Topological), -100.);
+            assert_eq!(z_of(&world, tiles[1], SortMethod::Topological), tile_zs[1]);
+            let in_front = y == 5;
+            let player_z = z_of(&world, player, SortMethod::Topological);
+            let crate_z = z_of(&world, crate_item, SortMethod::Topological);
+            assert_eq!(player_z > crate_z, in_front, "{y}");
+        }
+
+        let mut cells = world.get_mut::<CurrentCells>(tiles[1]).unwrap();
+        cells.set_changed();
+        schedule.run(&mut world);
+        assert_eq!(z_of(&world, tiles[0], SortMethod::Topological), tile_zs[0]);
+    }
+
+    // NOTE:
+    // - returns how many of the busy scene's items change z when another item is inserted among
+    //   them, checking that the occluding pairs stay in order
+    fn count_zs_changed_by_insert(stability: bool) -> usize {
+        let method = SortMethod::Topological;
+        let mut world = World::default();
+        if stability {
+            world.init_resource::<ZStability>();
+        }
+        let mut schedule = Schedule::default();
+        schedule.add_system(sort_items_topological);
+        let (scene, _) = busy_scene();
+        let initial = scene
+            .items()
+            .iter()
+            .map(|cells| add_item(&mut world, cells.main_cell, cells.dimensions))
+            .collect::<Vec<Entity>>();
+        schedule.run(&mut world);
+        let before = initial
+            .iter()
+            .map(|entity| z_of(&world, *entity, method))
+            .collect::<Vec<f32>>();
+
+        add_item(&mut world, Cell::new(1, 4), UVec3::ONE);
+        schedule.run(&mut world);
+
+        let mut items = world.query::<(Entity, &CurrentCells)>();
+        let items = items
+            .iter(&world)
+            .map(|(entity, cells)| (entity, cells.clone()))
+            .collect::<Vec<(Entity, CurrentCells)>>();
+        for (entity, cells) in &items {
+            for (other_entity, other_cells) in &items {
+                if cells.occludes(other_cells) && !other_cells.occludes(cells) {
+                    assert!(z_of(&world, *entity, method) > z_of(&world, *other_entity, method));
+                }
+            }
+        }
+        initial
+            .iter()
+            .zip(before)
+            .filter(|(entity, z)| (z_of(&world, **entity, method) - z).abs() > f32::EPSILON)
+            .count()
+    }
+
+    #[test]
+    fn stability_keeps_zs_when_an_item_is_inserted() {
+        let unstable = count_zs_changed_by_insert(false);
+        let stable = count_zs_changed_by_insert(true);
+        assert!(unstable > 0);
+        assert_eq!(stable, 0, "{unstable} items changed z without stability");
+    }
+
+    #[test]
+    fn stability_renormalises_a_gap_filled_again_and_again() {
+        let config = SortConfig::default();
+        let mut previous = HashMap::default();
+        let mut order = vec![Entity::from_raw(0), Entity::from_raw(1)];
+        let mut renormalised = 0;
+        for inserted in 2..40 {
+            order.insert(1, Entity::from_raw(inserted));
+            let step = config.z_step_spread(order.len());
+            let mut zs = order
+                .iter()
+                .enumerate()
+                .map(|(rank, entity)| (*entity, config.z_at(rank, step)))
+                .collect::<Vec<(Entity, f32)>>();
+            let last_z = previous.get(&order[order.len() - 1]).copied();
+            stabilise_zs(&mut zs, &mut previous, step, config);
+
+            if last_z.is_some_and(|z| (zs[zs.len() - 1].1 - z).abs() > f32::EPSILON) {
+                renormalised += 1;
+            }
+            for pair in zs.windows(2) {
+                assert!(pair[1].1 - pair[0].1 >= step * MIN_STABLE_GAP, "{inserted}");
+            }
+            let last_z = zs.last().unwrap().1;
+            assert!(zs[0].1 >= config.base_z && last_z <= config.base_z + config.z_span);
+        }
+        assert!(renormalised > 0);
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn custom_config_spreads_items_over_its_span() {
+        let config = SortConfig {
+            base_z: 100.,
+            z_span: 600.,
+        };
+        let mut world = World::default();
+        world.insert_resource(config);
+        let expected_order = run_busy(&mut world, sort_items_topological);
+        assert_order_matches(&mut world, SortMethod::Topological, &expected_order);
+
+        let step = config.z_step_spread(expected_order.len());
+        let first = z_of(&world, expected_order[0], SortMethod::Topological);
+        let last = z_of(
+            &world,
+            *expected_order.last().unwrap(),
+            SortMethod::Topological,
+        );
+        assert_eq!(first, config.base_z);
+        assert_eq!(last, config.base_z + config.z_span - step);
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn isolated_item_gets_a_z() {
+        let mut world = World::default();
+        let isolated = add_item(&mut world, Cell::new(3, 0), UVec3::ONE);
+        let mut compare = world.get_mut::<CompareTransforms>(isolated).unwrap();
+        compare.set(SortMethod::Topological, -1.);
+        let expected_order = run_busy(&mut world, sort_items_topological);
+
+        let z = z_of(&world, isolated, SortMethod::Topological);
+        assert_ne!(z, -1.);
+        assert!((BASE_Z..BASE_Z + Z_SPAN).contains(&z));
+        let mut actual_order = actual_order(&mut world, SortMethod::Topological);
+        actual_order.retain(|entity| *entity != isolated);
+        assert_eq!(actual_order, expected_order);
+
+        let (scene, _) = busy_scene();
+        let scene = scene.with_item(Cell::new(3, 0), UVec3::ONE);
+        assert_eq!(
+            scene.sort(SortMethod::Topological).len(),
+            scene.items().len()
+        );
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn no_occlusion_items_are_not_sorted() {
+        let mut world = World::default();
+        let marker = add_item(&mut world, Cell::new(1, 4), UVec3::ONE);
+        world.entity_mut(marker).insert(NoOcclusion);
+        let mut compare = world.get_mut::<CompareTransforms>(marker).unwrap();
+        compare.set(SortMethod::Topological, -1.);
+        let expected_order = run_busy(&mut world, sort_items_topological);
+
+        assert_eq!(z_of(&world, marker, SortMethod::Topological), -1.);
+        let mut actual_order = actual_order(&mut world, SortMethod::Topological);
+        actual_order.retain(|entity| *entity != marker);
+        assert_eq!(actual_order, expected_order);
+
+        let mut grid = CellGrid::new(MapSize::new(4, 7));
+        for (entity, cells) in world.query::<(Entity, &CurrentCells)>().iter(&world) {
+            grid.place(entity, cells);
+        }
+        assert!(grid.occupant_at(Cell::new(1, 4)).contains(&marker));
+    }
+
+    #[test]
+    fn timings_are_recorded() {
+        let mut world = World::default();
+        let mut schedule = Schedule::default();
+        world.init_resource::<Timings>();
+        schedule.add_systems((
+            sort_items_topological,
+            sort_items_partial_cmp,
+            sort_items_brute_force,
+        ));
+        add_item(&mut world, Cell::new(1, 3), UVec3::new(2, 2, 1));
+        add_item(&mut world, Cell::new(1, 5), UVec3::ONE);
+
+        schedule.run(&mut world);
+
+        let timings = world.resource::<Timings>();
+        for method in SortMethod::all() {
+            assert!(!timings.map[&method].is_zero(), "{method:?}");
+        }
+    }
+
+    fn spawn_contradicting_pair(world: &mut World) -> [Entity; 3] {
+        let map_size = MapSize::new(4, 7);
+        let pillar = |height| {
+            CurrentCells::new(
+                Cell::new(1, 3),
+                UVec3::new(1, 1, height),
+                Direction::BottomRight,
+                map_size,
+            )
+            .with_inclusive_behind()
+        };
+        let first = world.spawn((pillar(1), Transform::default())).id();
+        let second = world.spawn((pillar(2), Transform::default())).id();
+        let other = add_item(world, Cell::new(1, 5), UVec3::ONE);
+        world.entity_mut(other).insert(Transform::default());
+        [first, second, other]
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn log_and_skip_skips_contradicting_pair() {
+        let mut world = World::default();
+        let mut schedule = Schedule::default();
+        schedule.add_system(sort_items);
+        world.insert_resource(ActiveSortMethod(SortMethod::PartialCmp));
+        world.insert_resource(PanicPolicy::LogAndSkip);
+        let [first, second, other] = spawn_contradicting_pair(&mut world);
+        world.get_mut::<Transform>(second).unwrap().translation.z = -1.;
+
+        schedule.run(&mut world);
+
+        let z = |entity| world.get::<Transform>(entity).unwrap().translation.z;
+        assert_eq!(z(second), -1.);
+        assert!(z(first) < z(other));
+    }
+
+    #[test]
+    #[should_panic(expected = "Items cannot be both in front and behind each other")]
+    fn panic_policy_panics_on_contradicting_pair() {
+        let mut world = World::default();
+        let mut schedule = Schedule::default();
+        schedule.set_executor_kind(ExecutorKind::SingleThreaded);
+        schedule.add_system(sort_items);
+        world.insert_resource(ActiveSortMethod(SortMethod::PartialCmp));
+        spawn_contradicting_pair(&mut world);
+
+        schedule.run(&mut world);
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn none_leaves_z_untouched() {
+        let mut world = World::default();
+        let mut schedule = Schedule::default();
+        schedule.add_system(sort_items);
+        world.insert_resource(ActiveSortMethod(SortMethod::None));
+
+        let map_size = MapSize::new(4, 7);
+        let entities = [
+            (Cell::new(1, 3), 7.),
+            (Cell::new(1, 4), -2.),
+            (Cell::new(2, 2), 0.5),
+        ]
+        .map(|(main_cell, z)| {
+            let cells = CurrentCells::new(main_cell, UVec3::ONE, Direction::BottomRight, map_size);
+            let ghost = CurrentCells::new(main_cell, UVec3::ONE, Direction::BottomRight, map_size);
+            world.spawn((ghost, Ghost, Transform::from_xyz(0., 0., z)));
+            (world.spawn((cells, Transform::from_xyz(0., 0., z))).id(), z)
+        });
+        schedule.run(&mut world);
+
+        for (entity, z) in entities {
+            assert_eq!(world.get::<Transform>(entity).unwrap().translation.z, z);
+        }
+        let mut ghosts = world.query_filtered::<&Transform, With<Ghost>>();
+        let ghost_zs = ghosts
+            .iter(&world)
+            .map(|transform| transform.translation.z)
+            .collect::<Vec<f32>>();
+        assert_eq!(ghost_zs, vec![7., -2., 0.5]);
+    }
+
+    #[test]
+    fn ranked_layering_separates_long_chain() {
+        let mut world = World::default();
+        let mut schedule = Schedule::default();
+        schedule.add_system(sort_items_topological);
+        let min_separation = 0.01;
+        world.insert_resource(ZLayering::Ranked { min_separation });
+
+        let map_size = MapSize::new(1, 1000);
+        let chain = (0..1000)
+            .map(|y| {
+                let cells = CurrentCells::new(
+                    Cell::new(0, y),
+                    UVec3::ONE,
+                    Direction::BottomRight,
+                    map_size,
+                );
+                world.spawn((cells, CompareTransforms::default())).id()
+            })
+            .collect::<Vec<Entity>>();
+        schedule.run(&mut world);
+
+        let zs = chain
+            .iter()
+            .map(|entity| z_of(&world, *entity, SortMethod::Topological))
+            .collect::<Vec<f32>>();
+        assert!(zs
+            .windows(2)
+            .all(|pair| pair[1] - pair[0] >= min_separation * 0.99));
+    }
+
+    #[test]
+    fn active_sort_method_switches_transform_z() {
+        let mut world = World::default();
+        let mut schedule = Schedule::default();
+        schedule.add_system(sort_items);
+
+        let map_size = MapSize::new(4, 7);
+        let spawn = |world: &mut World, main_cell| {
+            let cells = CurrentCells::new(main_cell, UVec3::ONE, Direction::BottomRight, map_size);
+            world.spawn((cells, Transform::default())).id()
+        };
+        let a = spawn(&mut world, Cell::new(0, 1));
+        let b = spawn(&mut world, Cell::new(2, 5));
+        world.entity_mut(a).insert(ForceOrder {
+            behind: vec![b],
+            ..default()
+        });
+        let z = |world: &World, entity| world.get::<Transform>(entity).unwrap().translation.z;
+
+        world.insert_resource(ActiveSortMethod(SortMethod::Topological));
+        schedule.run(&mut world);
+        assert!(z(&world, b) < z(&world, a));
+
+        world.insert_resource(ActiveSortMethod(SortMethod::PartialCmp));
+        schedule.run(&mut world);
+        assert!(z(&world, a) < z(&world, b));
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn z_reaches_transform_and_skips_items_without_one() {
+        let mut world = World::default();
+        let mut schedule = Schedule::default();
+        schedule
+            .add_system(sort_items_topological)
+            .add_system(copy_z_to_transform.after(sort_items_topological));
+        world.init_resource::<ActiveSortMethod>();
+        let expected_order = run_busy(&mut world, sort_items_topological);
+        for entity in &expected_order[1..] {
+            world.entity_mut(*entity).insert(Transform::default());
+        }
+
+        schedule.run(&mut world);
+
+        let without_transform = expected_order[0];
+        assert!(world.get::<Transform>(without_transform).is_none());
+        let zs = expected_order[1..]
+            .iter()
+            .map(|entity| {
+                let z = world.get::<Transform>(*entity).unwrap().translation.z;
+                assert_eq!(z, z_of(&world, *entity, SortMethod::Topological));
+                z
+            })
+            .collect::<Vec<f32>>();
+        assert!(zs.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let mut sort_into_transform = Schedule::default();
+        sort_into_transform.add_system(sort_items);
+        sort_into_transform.run(&mut world);
+        let transform_zs = expected_order[1..]
+            .iter()
+            .map(|entity| world.get::<Transform>(*entity).unwrap().translation.z)
+            .collect::<Vec<f32>>();
+        assert!(transform_zs.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    // NOTE:
+    // - A and B hide each other where their footprints cross, and so do B and C, so the three of
+    //   them form a cycle
+    #[test]
+    fn cycle_is_reported_in_diagnostics() {
+        let mut world = World::default();
+        world.init_resource::<SortDiagnostics>();
+        let mut schedule = Schedule::default();
+        schedule.add_system(sort_items_topological);
+        let map_size = MapSize::new(4, 8);
+        let mut spawn = |main_cell, dimensions| {
+            let cells = CurrentCells::new(main_cell, dimensions, Direction::BottomRight, map_size);
+            world.spawn((cells, CompareTransforms::default())).id()
+        };
+        let cycle = [
+            spawn(Cell::new(0, 1), UVec3::new(3, 1, 1)),
+            spawn(Cell::new(1, 1), UVec3::new(3, 1, 1)),
+            spawn(Cell::new(1, 2), UVec3::new(1, 3, 1)),
+        ];
+        let apart = spawn(Cell::new(3, 7), UVec3::ONE);
+
+        schedule.run(&mut world);
+
+        let mut unsorted = world.resource::<SortDiagnostics>().unsorted.clone();
+        unsorted.sort();
+        assert_eq!(unsorted, cycle.to_vec());
+        let apart_z = z_of(&world, apart, SortMethod::Topological);
+        for entity in cycle {
+            assert!(z_of(&world, entity, SortMethod::Topological) > apart_z);
+        }
+    }
+
+    #[test]
+    fn unsorted_items_are_remembered_until_they_change() {
+        let map_size = MapSize::new(4, 8);
+        let cells = [Cell::new(0, 1), Cell::new(1, 1), Cell::new(3, 7)].map(|main_cell| {
+            CurrentCells::new(main_cell, UVec3::ONE, Direction::BottomRight, map_size)
+        });
+        let entities = [
+            Entity::from_raw(0),
+            Entity::from_raw(1),
+            Entity::from_raw(2),
+        ];
+        let items = entities
+            .into_iter()
+            .zip(cells.iter())
+            .collect::<Vec<(Entity, &CurrentCells)>>();
+        let mut warned = HashSet::new();
+
+        let mut ranks = vec![vec![entities[2]]];
+        let unsorted = rank_unsorted(&items, &mut ranks, ZLayering::Spread, &mut warned);
+        assert_eq!(unsorted, entities[..2].to_vec());
+        assert_eq!(ranks.len(), 3);
+        assert_eq!(warned, entities[..2].iter().copied().collect());
+
+        let mut ranks = vec![vec![entities[2]]];
+        rank_unsorted(&items, &mut ranks, ZLayering::Spread, &mut warned);
+        assert_eq!(ranks.len(), 3);
+        assert_eq!(warned, entities[..2].iter().copied().collect());
+
+        let mut ranks = vec![entities.to_vec()];
+        let unsorted = rank_unsorted(&items, &mut ranks, ZLayering::Spread, &mut warned);
+        assert!(unsorted.is_empty());
+        assert!(warned.is_empty());
+    }
+
+    #[test]
+    fn cell_index_gives_the_same_order() {
+        let map_size = MapSize::new(6, 12);
+        let placements = [
+            (Cell::new(0, 3), UVec3::new(2, 2, 1)),
+            (Cell::new(1, 6), UVec3::new(1, 2, 3)),
+            (Cell::new(2, 1), UVec3::new(1, 1, 2)),
+            (Cell::new(1, 5), UVec3::new(1, 1, 2)),
+            (Cell::new(4, 9), UVec3::new(3, 1, 1)),
+            (Cell::new(2, 3), UVec3::new(1, 3, 1)),
+            (Cell::new(3, 11), UVec3::new(2, 2, 4)),
+            (Cell::new(5, 7), UVec3::new(1, 1, 1)),
+        ];
+        let sorted_zs = |with_index: bool| {
+            let mut world = World::default();
+            let mut schedule = Schedule::default();
+            schedule.add_system(sort_items_topological);
+            if with_index {
+                world.init_resource::<CellIndex>();
+                schedule.add_system(update_cell_index.before(sort_items_topological));
+            }
+            let entities = placements
+                .iter()
+                .map(|(main_cell, dimensions)| {
+                    let cells = CurrentCells::new(
+                        *main_cell,
+                        *dimensions,
+                        Direction::BottomRight,
+                        map_size,
+                    );
+                    world.spawn((cells, CompareTransforms::default())).id()
+                })
+                .collect::<Vec<Entity>>();
+            schedule.run(&mut world);
+            entities
+                .into_iter()
+                .map(|entity| z_of(&world, entity, SortMethod::Topological))
+                .collect::<Vec<f32>>()
+        };
+        assert_eq!(sorted_zs(true), sorted_zs(false));
+
+        let items = placements
+            .iter()
+            .enumerate()
+            .map(|(index, (main_cell, dimensions))| {
+                let cells =
+                    CurrentCells::new(*main_cell, *dimensions, Direction::BottomRight, map_size);
+                (Entity::from_raw(u32::try_from(index).unwrap()), cells)
+            })
+            .collect::<Vec<(Entity, CurrentCells)>>();
+        let items = items
+            .iter()
+            .map(|(entity, cells)| (*entity, cells))
+            .collect::<Vec<(Entity, &CurrentCells)>>();
+        let index = CellIndex::from_items(items.iter().copied());
+        let mut indexed = indexed_geometric_edges(&items, &index);
+        let mut all_pairs = geometric_edges(&items);
+        indexed.sort();
+        all_pairs.sort();
+        assert!(!all_pairs.is_empty());
+        assert_eq!(indexed, all_pairs);
+    }
+
+    #[cfg(feature = "trace")]
+    mod spans {
+        use bevy::utils::tracing::{
+            span::{Attributes, Id, Record},
+            subscriber::with_default,
+            Event, Metadata, Subscriber,
+        };
+        use std::sync::{Arc, Mutex};
+
+        use super::*;
+
+        #[derive(Default)]
+        struct SpanNames(Arc<Mutex<Vec<&'static str>>>);
+
+        impl Subscriber for SpanNames {
+            fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut names = self.0.lock().unwrap();
+                names.push(span.metadata().name());
+                Id::from_u64(names.len() as u64)
+            }
+
+            fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+            fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+            fn event(&self, _event: &Event<'_>) {}
+
+            fn enter(&self, _span: &Id) {}
+
+            fn exit(&self, _span: &Id) {}
+        }
+
+        #[test]
+        fn sort_phases_emit_spans() {
+            let names = Arc::new(Mutex::new(Vec::new()));
+            with_default(SpanNames(names.clone()), || {
+                let mut world = World::default();
+                let items = [
+                    (Cell::new(0, 3), UVec3::new(2, 2, 1)),
+                    (Cell::new(1, 6), UVec3::new(1, 2, 1)),
+                    (Cell::new(2, 1), UVec3::new(1, 1, 2)),
+                    (Cell::new(1, 5), UVec3::new(1, 1, 2)),
+                ]
+                .map(|(main_cell, dimensions)| {
+                    let entity = world.spawn_empty().id();
+                    let cells = CurrentCells::new(
+                        main_cell,
+                        dimensions,
+                        Direction::BottomRight,
+                        MapSize::new(4, 7),
+                    );
+                    (entity, cells)
+                });
+                let items = items
+                    .iter()
+                    .map(|(entity, cells)| (*entity, cells))
+                    .collect::<Vec<(Entity, &CurrentCells)>>();
+                topological_order(&items, &[]);
+                partial_cmp_order(&items);
+            });
+
+            let names = names.lock().unwrap();
+            assert!(names.contains(&"topological_dependencies"));
+            assert!(names.contains(&"partial_cmp_sort"));
+            assert!(names.contains(&"current_cells_behind"));
+        }
+    }
+
+    #[test]
+    fn bare_items_get_compare_transforms() {
+        let mut world = World::default();
+        let mut schedule = Schedule::default();
+        schedule
+            .add_system(insert_compare_transforms)
+            .add_system(apply_system_buffers.after(insert_compare_transforms))
+            .add_system(sort_items_partial_cmp.after(apply_system_buffers));
+
+        let map_size = MapSize::new(4, 7);
+        let behind = CurrentCells::new(
+            Cell::new(1, 2),
+            UVec3::ONE,
+            Direction::BottomRight,
+            map_size,
+        );
+        let in_front = CurrentCells::new(
+            Cell::new(1, 4),
+            UVec3::ONE,
+            Direction::BottomRight,
+            map_size,
+        );
+        let behind = world.spawn(behind).id();
+        let in_front = world.spawn(in_front).id();
+
+        schedule.run(&mut world);
+
+        assert!(world.get::<CompareTransforms>(behind).is_some());
+        assert!(world.get::<CompareTransforms>(in_front).is_some());
+        assert_eq!(
+            actual_order(&mut world, SortMethod::PartialCmp),
+            vec![behind, in_front]
+        );
     }
 }
@@ -0,0 +1,98 @@
+use bevy::prelude::*;
+use isometric_sort::cells::{
+    cell::{Cell, Direction},
+    current::CurrentCells,
+    graph::OcclusionGraph,
+    grid::CellIndex,
+    map::MapSize,
+    plugin::{IsometricSortPlugin, SortThisFrame},
+    saved::SortMethod,
+};
+
+fn spawn_item(app: &mut App, main_cell: Cell) -> Entity {
+    let cells = CurrentCells::new(
+        main_cell,
+        UVec3::ONE,
+        Direction::BottomRight,
+        MapSize::new(3, 7),
+    );
+    app.world.spawn((cells, Transform::default())).id()
+}
+
+fn z_of(app: &App, entity: Entity) -> f32 {
+    app.world.get::<Transform>(entity).unwrap().translation.z
+}
+
+#[test]
+fn plugin_assigns_z_to_items() {
+    for method in [SortMethod::Topological, SortMethod::PartialCmp] {
+        let mut app = App::new();
+        app.add_plugin(IsometricSortPlugin::new(method));
+        let behind = spawn_item(&mut app, Cell::new(1, 2));
+        let in_front = spawn_item(&mut app, Cell::new(1, 4));
+
+        app.update();
+
+        assert!(z_of(&app, behind) < z_of(&app, in_front), "{method:?}");
+    }
+}
+
+#[test]
+fn plugin_rebuilds_the_cell_index_before_the_sort() {
+    let mut app = App::new();
+    app.add_plugin(IsometricSortPlugin::new(SortMethod::Topological));
+    let behind = spawn_item(&mut app, Cell::new(1, 2));
+    let in_front = spawn_item(&mut app, Cell::new(1, 4));
+
+    app.update();
+    let index = app.world.resource::<CellIndex>();
+    assert_eq!(index.occupants(Cell::new(1, 2)), &[behind]);
+    assert_eq!(index.occupants(Cell::new(1, 4)), &[in_front]);
+    assert!(z_of(&app, behind) < z_of(&app, in_front));
+}
+
+#[test]
+fn plugin_keeps_the_occlusion_graph_up_to_date() {
+    let mut app = App::new();
+    app.add_plugin(IsometricSortPlugin::new(SortMethod::Topological).with_occlusion_graph());
+    let behind = spawn_item(&mut app, Cell::new(1, 2));
+    let in_front = spawn_item(&mut app, Cell::new(1, 4));
+
+    app.update();
+    let edges = app
+        .world
+        .resource::<OcclusionGraph>()
+        .edges()
+        .collect::<Vec<_>>();
+    assert_eq!(edges, vec![(behind, in_front)]);
+    assert!(z_of(&app, behind) < z_of(&app, in_front));
+
+    app.insert_resource(SortThisFrame(false));
+    let cells = CurrentCells::new(
+        Cell::new(1, 6),
+        UVec3::ONE,
+        Direction::BottomRight,
+        MapSize::new(3, 7),
+    );
+    *app.world.get_mut::<CurrentCells>(behind).unwrap() = cells;
+    app.update();
+    app.insert_resource(SortThisFrame(true));
+    app.update();
+    assert!(z_of(&app, in_front) < z_of(&app, behind));
+}
+
+#[test]
+fn plugin_skips_the_sort_when_asked() {
+    let mut app = App::new();
+    app.add_plugin(IsometricSortPlugin::default());
+    app.insert_resource(SortThisFrame(false));
+    let behind = spawn_item(&mut app, Cell::new(1, 2));
+    let in_front = spawn_item(&mut app, Cell::new(1, 4));
+
+    app.update();
+    assert!((z_of(&app, behind) - z_of(&app, in_front)).abs() < f32::EPSILON);
+
+    app.insert_resource(SortThisFrame(true));
+    app.update();
+    assert!(z_of(&app, behind) < z_of(&app, in_front));
+}